@@ -0,0 +1,167 @@
+use minifb::{Key, KeyRepeat, WindowOptions, Window, Scale};
+
+use enniesse_core::ppu::Ppu;
+use enniesse_core::ppu::debug;
+use enniesse_core::ppu::debug::Image;
+use enniesse_core::ppu::palette::Palette;
+
+// number keys pick the pattern table palette, 1-4 for backgrounds and 5-8 for sprites
+const PALETTE_KEYS: [Key; 8] = [Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8];
+
+pub const VIEW_NAMES: [&'static str; 5] = ["nametables", "patterns", "oam", "palette", "all"];
+
+// which of the ppu viewers to open next to the game
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DebugViews {
+    pub nametables: bool,
+    pub pattern_tables: bool,
+    pub oam: bool,
+    pub palette: bool,
+}
+
+impl DebugViews {
+    // a comma separated list of view names
+    pub fn from_names(names: &str) -> Option<DebugViews> {
+        let mut views = DebugViews::default();
+
+        for name in names.split(',') {
+            match name.trim() {
+                "nametables" => views.nametables = true,
+                "patterns" => views.pattern_tables = true,
+                "oam" => views.oam = true,
+                "palette" => views.palette = true,
+                "all" => {
+                    views = DebugViews { nametables: true, pattern_tables: true, oam: true, palette: true };
+                },
+                _ => return None
+            }
+        }
+
+        Some(views)
+    }
+}
+
+pub struct DebugWindows {
+    nametables: Option<DebugWindow>,
+    pattern_tables: Option<DebugWindow>,
+    oam: Option<DebugWindow>,
+    palette: Option<DebugWindow>,
+    pattern_palette: u8,
+}
+
+impl DebugWindows {
+    pub fn new(views: DebugViews) -> DebugWindows {
+        DebugWindows {
+            nametables: open_if(views.nametables, "nametables", 512, 480, Scale::X1),
+            pattern_tables: open_if(views.pattern_tables, "pattern tables", 256, 128, Scale::X2),
+            // tall enough for 8x16 sprites
+            oam: open_if(views.oam, "oam", 64, 128, Scale::X4),
+            palette: open_if(views.palette, "palette", 256, 32, Scale::X2),
+            pattern_palette: 0,
+        }
+    }
+
+    // redraws every open window, and forgets the ones that have been closed
+    pub fn update(&mut self, ppu: &mut Ppu, palette: &Palette) {
+        if let Some(ref mut window) = self.nametables {
+            window.show(&debug::nametables(ppu, palette, true));
+        }
+
+        if let Some(ref mut window) = self.pattern_tables {
+            for (number, &key) in PALETTE_KEYS.iter().enumerate() {
+                if window.window.is_key_pressed(key, KeyRepeat::No) {
+                    self.pattern_palette = number as u8;
+                }
+            }
+
+            window.show(&debug::pattern_tables(ppu, palette, self.pattern_palette));
+        }
+
+        if let Some(ref mut window) = self.oam {
+            // space dumps the decoded sprites, since they're hard to read off the image
+            if window.window.is_key_pressed(Key::Space, KeyRepeat::No) {
+                print_sprites(ppu);
+            }
+
+            window.show(&debug::oam(ppu, palette));
+        }
+
+        if let Some(ref mut window) = self.palette {
+            window.show(&debug::palette_ram(ppu, palette));
+        }
+
+        close_if_needed(&mut self.nametables);
+        close_if_needed(&mut self.pattern_tables);
+        close_if_needed(&mut self.oam);
+        close_if_needed(&mut self.palette);
+    }
+}
+
+struct DebugWindow {
+    window: Window,
+    buffer: Vec<u32>,
+    width: usize,
+}
+
+impl DebugWindow {
+    // images smaller than the window are drawn in its top left corner
+    fn show(&mut self, image: &Image) {
+        for pixel in self.buffer.iter_mut() {
+            *pixel = 0;
+        }
+
+        let height = self.buffer.len() / self.width;
+        for y in 0 .. image.height.min(height) {
+            for x in 0 .. image.width.min(self.width) {
+                let rgb = image.pixel(x, y);
+                self.buffer[y * self.width + x] = 0xff00_0000 | (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+            }
+        }
+
+        self.window.update_with_buffer(&self.buffer).expect("Window update failed");
+    }
+}
+
+fn open_if(open: bool, title: &str, width: usize, height: usize, scale: Scale) -> Option<DebugWindow> {
+    if !open {
+        return None;
+    }
+
+    let window = Window::new(title, width, height,
+                             WindowOptions {
+                                 borderless: false,
+                                 title: true,
+                                 resize: false,
+                                 scale: scale,
+                             }).unwrap_or_else(|e| {
+                                 panic!("{}", e);
+                             });
+
+    Some(DebugWindow {
+        window: window,
+        buffer: vec![0; width * height],
+        width: width,
+    })
+}
+
+fn close_if_needed(window: &mut Option<DebugWindow>) {
+    let closed = match *window {
+        Some(ref window) => !window.window.is_open() || window.window.is_key_down(Key::Escape),
+        None => false
+    };
+
+    if closed {
+        *window = None;
+    }
+}
+
+fn print_sprites(ppu: &Ppu) {
+    println!(" #   x   y tile pal pri flip");
+    for sprite in debug::oam_sprites(ppu) {
+        println!("{:2} {:3} {:3}   {:02X}   {}   {}   {}{}",
+                 sprite.index, sprite.x, sprite.y, sprite.tile, sprite.palette,
+                 if sprite.behind_background { "B" } else { "F" },
+                 if sprite.flip_horizontal { "H" } else { "-" },
+                 if sprite.flip_vertical { "V" } else { "-" });
+    }
+}
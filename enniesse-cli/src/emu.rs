@@ -1,54 +1,251 @@
-use minifb::{Key, WindowOptions, Window, Scale};
+use minifb::{Key, KeyRepeat, WindowOptions, Window, Scale};
 
 use enniesse_core::nes::Nes;
 use enniesse_core::input::Button;
 use enniesse_core::ppu;
-use enniesse_core::rom::Rom;
+use enniesse_core::ppu::palette::Palette;
+use enniesse_core::ppu::ntsc;
+use enniesse_core::ppu::ntsc::{NtscFilter, NtscSettings};
+use enniesse_core::rom::{Rom, PatchedFile};
+use enniesse_core::disk;
+use enniesse_core::disk::DiskImage;
+use enniesse_core::movie::{Movie, MovieMode};
+use enniesse_core::memory::RamPattern;
+use enniesse_core::rewind;
+use enniesse_core::rewind::Rewind;
+use std::fs;
 use std::thread;
-use std::time;
-use std::path::Path;
+use std::time::Instant;
+use std::path::{Path, PathBuf};
+
+use pacer;
+use pacer::FramePacer;
+use video::{VideoFilter, VideoOptions};
+use debug::{DebugViews, DebugWindows};
+
+pub struct EmuOptions {
+    pub record_movie: Option<String>,
+    pub play_movie: Option<String>,
+    pub ram_pattern: RamPattern,
+    // bytes of rewind history to keep, 0 disables rewinding
+    pub rewind_memory: usize,
+    pub slow_motion_factor: f64,
+    // turning the 8 sprites per scanline limit off removes flicker
+    pub sprite_limit: bool,
+    pub palette: Palette,
+    // the ntsc filter replaces the palette when set
+    pub ntsc: Option<NtscSettings>,
+    pub video: VideoOptions,
+    pub debug_views: DebugViews,
+    // the disk system bios, otherwise disksys.rom is looked for next to the disk and then in the current directory
+    pub fds_bios: Option<String>,
+}
+
+impl Default for EmuOptions {
+    fn default() -> EmuOptions {
+        EmuOptions {
+            record_movie: None,
+            play_movie: None,
+            ram_pattern: RamPattern::default(),
+            rewind_memory: rewind::DEFAULT_MEMORY_BUDGET,
+            slow_motion_factor: pacer::DEFAULT_SLOW_MOTION,
+            sprite_limit: true,
+            palette: Palette::default(),
+            ntsc: None,
+            video: VideoOptions::default(),
+            debug_views: DebugViews::default(),
+            fds_bios: None,
+        }
+    }
+}
 
 pub struct Emu {
     window: Window,
     pub nes: Nes,
+    rewind: Rewind,
+    pacer: FramePacer,
+    ntsc: Option<NtscFilter>,
+    video: VideoFilter,
+    // the frame in color, before the video filter
+    frame_buffer: Vec<u32>,
+    debug_windows: DebugWindows,
+    rom_filename: String,
+    // where changes to a disk are kept, as an ips patch of the original image
+    disk_save_path: Option<PathBuf>,
+    options: EmuOptions,
 }
 
 impl Emu {
-    pub fn new<P: AsRef<Path>>(path: P) -> Emu {
-        let rom = Rom::from_file(path);
+    // roms in archives are treated as though they'd been unpacked next to them
+    pub fn new(file: PatchedFile, options: EmuOptions) -> Emu {
+        let path = file.path.as_path();
+        let rom_filename = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+        let (rom, disk_save_path) = if file.is_disk() {
+            let disk_save_path = path.with_extension("sav.ips");
+            (load_disk(path, &file.data, &disk_save_path, options.fds_bios.as_ref()), Some(disk_save_path))
+        } else {
+            (Rom::parse(&file.data).unwrap_or_else(|e| panic!("Failed to load {}: {}", path.display(), e)), None)
+        };
+
+        let mut pacer = FramePacer::new(rom.region.frame_rate());
+        pacer.set_slow_motion_factor(options.slow_motion_factor);
+
+        let ntsc = options.ntsc.map(NtscFilter::new);
+        let video = VideoFilter::new(options.video);
+
+        let (frame_width, frame_height, pixel_width) = frame_size(ntsc.is_some());
+        let (width, height) = video.output_size(frame_width, frame_height, pixel_width);
+        let title = rom.title.clone().unwrap_or_else(|| "nesrs".to_string());
+        let nes = Nes::new(Box::new(rom)).unwrap_or_else(|e| panic!("Failed to load {}: {}", path.display(), e));
 
         Emu {
-            window: Window::new("nesrs", ppu::SCREEN_WIDTH, ppu::SCREEN_HEIGHT,
+            window: Window::new(&title, width, height,
                                 WindowOptions { 
                                     borderless: false,
                                     title: true,
                                     resize: false,
-                                    scale: Scale::X2,
+                                    scale: Scale::X1,
                                 }).unwrap_or_else(|e| {
                                     panic!("{}", e);
                                 }),
-            nes: Nes::new(Box::new(rom)),
+            nes: nes,
+            rewind: Rewind::new(rewind::DEFAULT_SNAPSHOT_INTERVAL, options.rewind_memory),
+            pacer: pacer,
+            ntsc: ntsc,
+            video: video,
+            frame_buffer: vec![0; frame_width * frame_height],
+            debug_windows: DebugWindows::new(options.debug_views),
+            rom_filename: rom_filename,
+            disk_save_path: disk_save_path,
+            options: options,
         }
     }
 
     pub fn start(&mut self) {
-        self.nes.power_on();
+        self.nes.ram_pattern = self.options.ram_pattern;
+        self.nes.cpu.memory_interface.ppu.set_sprite_limit(self.options.sprite_limit);
+        self.nes.power_cycle();
+
+        if let Some(ref path) = self.options.play_movie {
+            let movie = Movie::load_fm2(path).unwrap_or_else(|e| panic!("Failed to load movie {}: {}", path, e));
+            self.nes.play_movie(movie).unwrap_or_else(|e| panic!("Failed to start movie {}: {}", path, e));
+        } else if self.options.record_movie.is_some() {
+            self.nes.record_movie(true);
+        }
 
-        let mut buffer: Vec<u32> = vec![0; ppu::SCREEN_WIDTH * ppu::SCREEN_HEIGHT];
+        let (frame_width, frame_height, pixel_width) = frame_size(self.ntsc.is_some());
+        let (width, height) = self.video.output_size(frame_width, frame_height, pixel_width);
+
+        let mut buffer: Vec<u32> = vec![0; width * height];
         while self.window.is_open() && !self.window.is_key_down(Key::Escape) {
-            let (_, render) = self.nes.step();
+            let rewinding = self.options.rewind_memory > 0 && self.window.is_key_down(Key::Backspace);
+
+            if rewinding {
+                self.rewind.step_back(&mut self.nes);
+            } else if self.pacer.should_run_frame() {
+                // no audio output yet, so the frame's samples are dropped
+                self.nes.run_frame();
 
-            if render {
-                for i in 0 .. ppu::SCREEN_WIDTH * ppu::SCREEN_HEIGHT {
-                    buffer[i] = (self.nes.cpu.memory_interface.ppu.display_buffer[i * 3] as u32) << 16 |
-                                (self.nes.cpu.memory_interface.ppu.display_buffer[i * 3 + 1] as u32) << 8 |
-                                self.nes.cpu.memory_interface.ppu.display_buffer[i * 3 + 2] as u32;
+                if self.options.rewind_memory > 0 {
+                    self.rewind.capture(&self.nes);
                 }
-                self.window.update_with_buffer(&buffer).expect("Window update failed");
-                thread::sleep(time::Duration::from_millis(16));
             }
 
-            self.read_keys();
+            self.draw_frame(&mut buffer);
+            self.window.update_with_buffer(&buffer).expect("Window update failed");
+            self.debug_windows.update(&mut self.nes.cpu.memory_interface.ppu, &self.options.palette);
+
+            let wait = self.pacer.frame_finished(Instant::now());
+            thread::sleep(wait);
+
+            self.read_pacer_keys();
+
+            // input only changes between frames so movies can reproduce it exactly
+            if self.nes.movie_mode() != Some(MovieMode::Playing) {
+                self.read_hotkeys();
+                self.read_keys();
+            }
+        }
+
+        self.save_movie();
+        self.save_disk();
+    }
+
+    fn draw_frame(&mut self, buffer: &mut [u32]) {
+        let ppu = &self.nes.cpu.memory_interface.ppu;
+
+        match self.ntsc {
+            Some(ref mut filter) => filter.apply(&ppu.display_buffer[..], ppu.odd_frame(), &mut self.frame_buffer),
+            None => self.options.palette.to_argb(&ppu.display_buffer[..], &mut self.frame_buffer)
+        }
+
+        let (width, height, pixel_width) = frame_size(self.ntsc.is_some());
+        self.video.apply(&self.frame_buffer, width, height, pixel_width, buffer);
+    }
+
+    fn save_movie(&mut self) {
+        if let Some(ref path) = self.options.record_movie {
+            if let Some(mut movie) = self.nes.stop_movie() {
+                movie.rom_filename = self.rom_filename.clone();
+                movie.save_fm2(path).unwrap_or_else(|e| panic!("Failed to save movie {}: {}", path, e));
+            }
+        }
+    }
+
+    fn save_disk(&mut self) {
+        if let Some(ref path) = self.disk_save_path {
+            if let Some(changes) = self.nes.disk_changes() {
+                fs::write(path, changes).unwrap_or_else(|e| panic!("Failed to save disk {}: {}", path.display(), e));
+            }
+        }
+    }
+
+    fn read_pacer_keys(&mut self) {
+        self.pacer.set_fast_forward(self.window.is_key_down(Key::Tab));
+
+        if self.window.is_key_pressed(Key::P, KeyRepeat::No) {
+            self.pacer.toggle_pause();
+        }
+
+        if self.window.is_key_pressed(Key::F, KeyRepeat::Yes) {
+            self.pacer.request_frame_advance();
+        }
+
+        if self.window.is_key_pressed(Key::S, KeyRepeat::No) {
+            let slow_motion = !self.pacer.is_slow_motion();
+            self.pacer.set_slow_motion(slow_motion);
+        }
+    }
+
+    fn read_hotkeys(&mut self) {
+        if self.window.is_key_pressed(Key::F1, KeyRepeat::No) {
+            self.nes.reset();
+        }
+
+        if self.window.is_key_pressed(Key::F2, KeyRepeat::No) {
+            self.nes.power_cycle();
+        }
+
+        if self.window.is_key_pressed(Key::F3, KeyRepeat::No) && self.nes.disk_side_count() > 0 {
+            self.nes.insert_or_eject_disk();
+            self.print_disk_side();
+        }
+
+        if self.window.is_key_pressed(Key::F4, KeyRepeat::No) && self.nes.disk_side_count() > 0 {
+            self.nes.select_disk_side();
+            self.print_disk_side();
+        }
+    }
+
+    fn print_disk_side(&self) {
+        let (side, inserted) = self.nes.disk_side();
+        let name = disk_side_name(side);
+
+        if inserted {
+            println!("Disk {} inserted", name);
+        } else {
+            println!("Disk ejected, {} selected", name);
         }
     }
 
@@ -62,4 +259,40 @@ impl Emu {
         self.nes.cpu.memory_interface.input.handle_input(Button::Left, self.window.is_key_down(Key::Left));
         self.nes.cpu.memory_interface.input.handle_input(Button::Right, self.window.is_key_down(Key::Right));
     }
+}
+
+// disks are numbered 1A, 1B, 2A and so on
+fn disk_side_name(side: usize) -> String {
+    format!("{}{}", side / 2 + 1, if side % 2 == 0 { 'A' } else { 'B' })
+}
+
+// the disk system is loaded with its bios as the cartridge, and any changes saved last time put back on the disk
+fn load_disk(path: &Path, data: &[u8], save_path: &Path, bios: Option<&String>) -> Rom {
+    let mut disk = DiskImage::from_bytes(data).unwrap_or_else(|e| panic!("Failed to load disk {}: {}", path.display(), e));
+
+    if let Ok(changes) = fs::read(save_path) {
+        disk.apply_changes(&changes).unwrap_or_else(|e| panic!("Failed to load disk save {}: {}", save_path.display(), e));
+    }
+
+    let bios_path = match bios {
+        Some(bios) => PathBuf::from(bios),
+        None => {
+            let beside_disk = path.with_file_name(disk::BIOS_NAME);
+            if beside_disk.exists() { beside_disk } else { PathBuf::from(disk::BIOS_NAME) }
+        }
+    };
+    let bios = fs::read(&bios_path).unwrap_or_else(|e| {
+        panic!("Failed to load the disk system bios {}: {}. Pass it with --fds-bios or put {} next to the disk", bios_path.display(), e, disk::BIOS_NAME)
+    });
+
+    Rom::from_disk(bios.into_boxed_slice(), disk)
+}
+
+// width, height and the number of pixels across each nes pixel of the frame fed to the video filter
+fn frame_size(ntsc: bool) -> (usize, usize, usize) {
+    if ntsc {
+        (ntsc::NTSC_WIDTH, ntsc::NTSC_HEIGHT, ntsc::NTSC_WIDTH / ppu::SCREEN_WIDTH)
+    } else {
+        (ppu::SCREEN_WIDTH, ppu::SCREEN_HEIGHT, 1)
+    }
 }
\ No newline at end of file
@@ -1,15 +1,189 @@
 use std::env;
+use std::process;
+
+use enniesse_core::memory::RamPattern;
+use enniesse_core::rom;
+use enniesse_core::ppu;
+use enniesse_core::ppu::palette;
+use enniesse_core::ppu::palette::Palette;
+use enniesse_core::ppu::ntsc::NtscSettings;
+
+use video::{Scaler, Overscan};
+use debug::DebugViews;
 
 extern crate minifb;
 extern crate enniesse_core;
 
 mod emu;
+mod pacer;
+mod video;
+mod debug;
+mod music;
+mod wav;
 
 fn main() {
-    let mut args = env::args();
-    let rom_file_name = args.nth(1).unwrap();
-    
-    let mut emu = emu::Emu::new(rom_file_name);
+    let mut args = env::args().skip(1);
+    let mut rom_file_name = None;
+    let mut options = emu::EmuOptions::default();
+    let mut music_options = music::MusicOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => options.record_movie = args.next(),
+            "--play" => options.play_movie = args.next(),
+            "--ram" => options.ram_pattern = parse_ram_pattern(args.next()),
+            "--rewind-memory" => options.rewind_memory = parse_megabytes(args.next()),
+            "--slow-motion" => options.slow_motion_factor = parse_factor(args.next()),
+            "--no-sprite-limit" => options.sprite_limit = false,
+            "--palette" => options.palette = parse_palette(args.next()),
+            "--ntsc" => options.ntsc = Some(options.ntsc.unwrap_or_default()),
+            "--ntsc-sharpness" => ntsc_settings(&mut options).sharpness = parse_number(args.next()),
+            "--ntsc-saturation" => ntsc_settings(&mut options).saturation = parse_number(args.next()),
+            "--ntsc-hue" => ntsc_settings(&mut options).hue = parse_number(args.next()),
+            "--scaler" => options.video.scaler = parse_scaler(args.next()),
+            "--scanlines" => options.video.scanlines = true,
+            "--crt-mask" => options.video.crt_mask = true,
+            "--aspect" => options.video.aspect_correction = true,
+            "--overscan" => options.video.overscan = parse_overscan(args.next()),
+            "--debug" => options.debug_views = parse_debug_views(args.next()),
+            "--fds-bios" => options.fds_bios = args.next(),
+            "--track" => music_options.track = Some(parse_number(args.next()) as usize),
+            "--tracks" => music_options.list_tracks = true,
+            "--wav" => music_options.wav_dir = args.next(),
+            _ => rom_file_name = Some(arg)
+        }
+    }
+
+    let rom_file_name = rom_file_name.unwrap_or_else(|| {
+        eprintln!("Usage: enniesse <rom|disk.fds|music.nsf> [--fds-bios disksys.rom] [--track n] [--tracks] [--wav dir] [--record movie.fm2] [--play movie.fm2] [--ram zeros|ones|random[:seed]] [--rewind-memory MB] [--slow-motion factor] [--no-sprite-limit] [--palette name|file.pal] [--ntsc] [--ntsc-sharpness 0-1] [--ntsc-saturation factor] [--ntsc-hue degrees] [--scaler 1x|2x|3x|4x|scale2x|scale3x|hq2x|xbr2x|smooth3x] [--scanlines] [--crt-mask] [--aspect] [--overscan top,bottom,left,right] [--debug nametables,patterns,oam,palette|all]");
+        eprintln!("There's no sound output yet, so music files play silently. Export them with --wav dir to listen to them");
+        process::exit(1);
+    });
+
+    let file = rom::read_file(&rom_file_name).unwrap_or_else(|e| panic!("Failed to load {}: {}", rom_file_name, e));
+    // a hack or translation sitting next to the rom
+    if let Some(ref patch_path) = file.patch {
+        println!("Applied {}", patch_path.display());
+    }
+
+    if let Some(nsf) = music::load(&rom_file_name, &file.data) {
+        music::run(&rom_file_name, nsf, music_options);
+        return;
+    }
+
+    let mut emu = emu::Emu::new(file, options);
     emu.start();
 }
 
+fn parse_ram_pattern(arg: Option<String>) -> RamPattern {
+    let arg = arg.unwrap_or_default();
+    let mut parts = arg.splitn(2, ':');
+
+    match (parts.next(), parts.next()) {
+        (Some("zeros"), None) => RamPattern::Zeros,
+        (Some("ones"), None) => RamPattern::Ones,
+        (Some("random"), None) => RamPattern::Random(0),
+        (Some("random"), Some(seed)) => RamPattern::Random(seed.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid ram seed: {}", seed);
+            process::exit(1);
+        })),
+        _ => {
+            eprintln!("Invalid ram pattern: {}", arg);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_megabytes(arg: Option<String>) -> usize {
+    let arg = arg.unwrap_or_default();
+
+    match arg.parse::<usize>() {
+        Ok(megabytes) => megabytes * 1024 * 1024,
+        Err(_) => {
+            eprintln!("Invalid memory size: {}", arg);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_factor(arg: Option<String>) -> f64 {
+    let arg = arg.unwrap_or_default();
+
+    match arg.parse::<f64>() {
+        Ok(factor) if factor > 0.0 => factor,
+        _ => {
+            eprintln!("Invalid speed factor: {}", arg);
+            process::exit(1);
+        }
+    }
+}
+
+// either one of the built in palettes or a path to a .pal file
+fn parse_palette(arg: Option<String>) -> Palette {
+    let arg = arg.unwrap_or_default();
+
+    if let Some(palette) = Palette::builtin(&arg) {
+        return palette;
+    }
+
+    Palette::from_file(&arg).unwrap_or_else(|e| {
+        eprintln!("Failed to load palette {}: {}", arg, e);
+        eprintln!("Built in palettes: {}", palette::BUILTIN_PALETTES.join(", "));
+        process::exit(1);
+    })
+}
+
+// any of the ntsc options turn the filter on
+fn ntsc_settings(options: &mut emu::EmuOptions) -> &mut NtscSettings {
+    options.ntsc.get_or_insert_with(NtscSettings::default)
+}
+
+fn parse_number(arg: Option<String>) -> f32 {
+    let arg = arg.unwrap_or_default();
+
+    arg.parse::<f32>().unwrap_or_else(|_| {
+        eprintln!("Invalid number: {}", arg);
+        process::exit(1);
+    })
+}
+
+fn parse_scaler(arg: Option<String>) -> Scaler {
+    let arg = arg.unwrap_or_default();
+
+    Scaler::from_name(&arg).unwrap_or_else(|| {
+        eprintln!("Invalid scaler: {}", arg);
+        process::exit(1);
+    })
+}
+
+// pixels to crop from each edge, either one number for all of them or top,bottom,left,right
+fn parse_overscan(arg: Option<String>) -> Overscan {
+    let arg = arg.unwrap_or_default();
+    let values: Result<Vec<usize>, _> = arg.split(',').map(|v| v.trim().parse::<usize>()).collect();
+
+    let overscan = match values.as_ref().map(|v| v.as_slice()) {
+        Ok(&[all]) => Overscan { top: all, bottom: all, left: all, right: all },
+        Ok(&[top, bottom, left, right]) => Overscan { top: top, bottom: bottom, left: left, right: right },
+        _ => {
+            eprintln!("Invalid overscan: {}", arg);
+            process::exit(1);
+        }
+    };
+
+    if !overscan.fits(ppu::SCREEN_WIDTH, ppu::SCREEN_HEIGHT) {
+        eprintln!("Overscan {} crops away the whole picture, which is {}x{}", arg, ppu::SCREEN_WIDTH, ppu::SCREEN_HEIGHT);
+        process::exit(1);
+    }
+
+    overscan
+}
+
+fn parse_debug_views(arg: Option<String>) -> DebugViews {
+    let arg = arg.unwrap_or_default();
+
+    DebugViews::from_names(&arg).unwrap_or_else(|| {
+        eprintln!("Invalid debug views: {}", arg);
+        eprintln!("Debug views: {}", debug::VIEW_NAMES.join(", "));
+        process::exit(1);
+    })
+}
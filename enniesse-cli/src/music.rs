@@ -0,0 +1,161 @@
+use minifb::{Key, KeyRepeat, WindowOptions, Window, Scale};
+
+use enniesse_core::apu;
+use enniesse_core::nsf::{Nsf, NsfPlayer};
+use enniesse_core::ppu;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Instant;
+
+use pacer::FramePacer;
+use wav;
+
+// tracks without a time in the file are exported at this length, in milliseconds
+const DEFAULT_TRACK_TIME: u32 = 150_000;
+const DEFAULT_FADE: u32 = 5_000;
+
+#[derive(Default)]
+pub struct MusicOptions {
+    // 1 based, as players show them
+    pub track: Option<usize>,
+    pub list_tracks: bool,
+    // a directory to export tracks to as wav files instead of playing them
+    pub wav_dir: Option<String>,
+}
+
+// an nsf or nsfe file, or None for anything else
+pub fn load(path: &str, data: &[u8]) -> Option<Nsf> {
+    if !Nsf::is_nsf(data) {
+        return None;
+    }
+
+    Some(Nsf::from_bytes(data).unwrap_or_else(|e| {
+        eprintln!("Failed to load {}: {}", path, e);
+        process::exit(1);
+    }))
+}
+
+pub fn run(path: &str, nsf: Nsf, options: MusicOptions) {
+    // an nsfe playlist can leave nothing to play
+    if nsf.track_order().is_empty() {
+        eprintln!("{} has no tracks to play", path);
+        process::exit(1);
+    }
+
+    let first_track = match options.track {
+        Some(track) if track == 0 || track > nsf.track_count => {
+            eprintln!("Invalid track {}, there are {}", track, nsf.track_count);
+            process::exit(1);
+        },
+        Some(track) => track - 1,
+        None => nsf.starting_track
+    };
+
+    if options.list_tracks {
+        print_tracks(&nsf);
+    } else if let Some(ref dir) = options.wav_dir {
+        let tracks = if options.track.is_some() { vec![first_track] } else { nsf.track_order() };
+        export_wav(path, nsf, dir, &tracks);
+    } else {
+        play(nsf, first_track);
+    }
+}
+
+fn print_tracks(nsf: &Nsf) {
+    for line in &[&nsf.title, &nsf.artist, &nsf.copyright] {
+        if !line.is_empty() {
+            println!("{}", line);
+        }
+    }
+
+    for track in nsf.track_order() {
+        println!("{}", track_description(nsf, track));
+    }
+}
+
+fn track_description(nsf: &Nsf, track: usize) -> String {
+    let mut description = format!("{:>3}", track + 1);
+    if let Some(label) = nsf.track_label(track) {
+        description.push_str(&format!(" {}", label));
+    }
+    if let Some(time) = nsf.track_time(track) {
+        description.push_str(&format!(" ({}:{:02})", time / 60000, time / 1000 % 60));
+    }
+
+    description
+}
+
+// each track goes to <name>-<track>.wav in the directory
+fn export_wav(path: &str, nsf: Nsf, dir: &str, tracks: &[usize]) {
+    let name = Path::new(path).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut player = NsfPlayer::new(nsf);
+
+    for &track in tracks {
+        let time = player.nsf.track_time(track).unwrap_or(DEFAULT_TRACK_TIME);
+        let fade = player.nsf.track_fade(track).unwrap_or(DEFAULT_FADE);
+        let samples = player.render_track(track, time + fade, fade);
+
+        let out_path = Path::new(dir).join(format!("{}-{:02}.wav", name, track + 1));
+        let result = File::create(&out_path).and_then(|file| wav::write_wav(BufWriter::new(file), &samples, apu::SAMPLE_RATE));
+        if let Err(e) = result {
+            eprintln!("Failed to write {}: {}", out_path.display(), e);
+            process::exit(1);
+        }
+
+        println!("{}", out_path.display());
+    }
+}
+
+// there's no audio output yet, so this runs the tracks silently and only --wav gives something to listen to
+fn play(nsf: Nsf, first_track: usize) {
+    println!("There's no sound output yet, export the tracks with --wav <dir> to listen to them");
+
+    let mut window = Window::new("nesrs", ppu::SCREEN_WIDTH, ppu::SCREEN_HEIGHT,
+                                 WindowOptions {
+                                     borderless: false,
+                                     title: true,
+                                     resize: false,
+                                     scale: Scale::X2,
+                                 }).unwrap_or_else(|e| {
+                                     panic!("{}", e);
+                                 });
+
+    let order = nsf.track_order();
+    let mut position = order.iter().position(|&track| track == first_track).unwrap_or(0);
+
+    let mut player = NsfPlayer::new(nsf);
+    let mut pacer = FramePacer::new(player.region.frame_rate());
+    let cycles_per_frame = (player.region.cpu_clock_rate() / player.region.frame_rate()) as u64;
+
+    player.start_track(order[position]);
+    println!("{}", track_description(&player.nsf, order[position]));
+
+    let buffer = vec![0; ppu::SCREEN_WIDTH * ppu::SCREEN_HEIGHT];
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        if pacer.should_run_frame() {
+            player.run_cycles(cycles_per_frame);
+            player.take_audio_samples();
+        }
+
+        window.update_with_buffer(&buffer).expect("Window update failed");
+
+        let wait = pacer.frame_finished(Instant::now());
+        thread::sleep(wait);
+
+        let next = window.is_key_pressed(Key::Right, KeyRepeat::No);
+        let previous = window.is_key_pressed(Key::Left, KeyRepeat::No);
+        if next || previous {
+            position = if next { (position + 1) % order.len() } else { (position + order.len() - 1) % order.len() };
+
+            player.start_track(order[position]);
+            println!("{}", track_description(&player.nsf, order[position]));
+        }
+
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            pacer.toggle_pause();
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_SLOW_MOTION: f64 = 0.5;
+
+// if emulation falls this many frames behind, stop trying to catch up
+const MAX_LAG_FRAMES: u32 = 4;
+
+// decides when frames should run. times are passed in rather than read so it can be driven by tests
+pub struct FramePacer {
+    frame_rate: f64,
+    slow_motion_factor: f64,
+
+    fast_forward: bool,
+    slow_motion: bool,
+    paused: bool,
+    advance_requested: bool,
+
+    next_frame: Option<Instant>,
+}
+
+impl FramePacer {
+    pub fn new(frame_rate: f64) -> FramePacer {
+        FramePacer {
+            frame_rate: frame_rate,
+            slow_motion_factor: DEFAULT_SLOW_MOTION,
+
+            fast_forward: false,
+            slow_motion: false,
+            paused: false,
+            advance_requested: false,
+
+            next_frame: None,
+        }
+    }
+
+    // fraction of normal speed to run at while slow motion is on
+    pub fn set_slow_motion_factor(&mut self, factor: f64) {
+        if factor > 0.0 {
+            self.slow_motion_factor = factor;
+        }
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn set_slow_motion(&mut self, slow_motion: bool) {
+        self.slow_motion = slow_motion;
+    }
+
+    pub fn is_slow_motion(&self) -> bool {
+        self.slow_motion
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advance_requested = false;
+    }
+
+    // runs a single frame while paused. if running, this pauses first
+    pub fn request_frame_advance(&mut self) {
+        if self.paused {
+            self.advance_requested = true;
+        } else {
+            self.paused = true;
+        }
+    }
+
+    // whether the emulator should run a frame on this iteration
+    pub fn should_run_frame(&mut self) -> bool {
+        if !self.paused {
+            return true;
+        }
+
+        let advance = self.advance_requested;
+        self.advance_requested = false;
+        advance
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        let mut rate = self.frame_rate;
+        if self.slow_motion {
+            rate *= self.slow_motion_factor;
+        }
+
+        Duration::from_secs_f64(1.0 / rate)
+    }
+
+    // call after each frame is presented, returns how long to wait before starting the next one
+    pub fn frame_finished(&mut self, now: Instant) -> Duration {
+        if self.fast_forward && !self.paused {
+            self.next_frame = Some(now);
+            return Duration::from_secs(0);
+        }
+
+        let frame_duration = self.frame_duration();
+        let next_frame = self.next_frame.unwrap_or(now) + frame_duration;
+
+        if next_frame <= now {
+            // running behind, let it catch up unless it's too far gone (window dragged, debugger, etc)
+            self.next_frame = if now - next_frame > frame_duration * MAX_LAG_FRAMES { Some(now) } else { Some(next_frame) };
+            return Duration::from_secs(0);
+        }
+
+        self.next_frame = Some(next_frame);
+        next_frame - now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_at_the_frame_rate() {
+        let mut pacer = FramePacer::new(50.0);
+        let start = Instant::now();
+
+        assert_eq!(pacer.frame_finished(start), Duration::from_millis(20));
+        // the schedule is absolute, so time spent emulating comes out of the wait
+        assert_eq!(pacer.frame_finished(start + Duration::from_millis(25)), Duration::from_millis(15));
+    }
+
+    #[test]
+    fn catches_up_after_a_short_stall() {
+        let mut pacer = FramePacer::new(50.0);
+        let start = Instant::now();
+
+        pacer.frame_finished(start);
+        assert_eq!(pacer.frame_finished(start + Duration::from_millis(50)), Duration::from_secs(0));
+        assert_eq!(pacer.frame_finished(start + Duration::from_millis(50)), Duration::from_millis(10));
+
+        // a long stall resets the schedule instead
+        assert_eq!(pacer.frame_finished(start + Duration::from_secs(10)), Duration::from_secs(0));
+        assert_eq!(pacer.frame_finished(start + Duration::from_secs(10)), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn fast_forward_and_slow_motion() {
+        let mut pacer = FramePacer::new(50.0);
+        let start = Instant::now();
+
+        pacer.set_fast_forward(true);
+        assert_eq!(pacer.frame_finished(start), Duration::from_secs(0));
+        pacer.set_fast_forward(false);
+
+        pacer.set_slow_motion_factor(0.25);
+        pacer.set_slow_motion(true);
+        assert_eq!(pacer.frame_finished(start), Duration::from_millis(80));
+    }
+
+    #[test]
+    fn pause_and_frame_advance() {
+        let mut pacer = FramePacer::new(60.0);
+        assert!(pacer.should_run_frame());
+
+        pacer.request_frame_advance();
+        assert!(!pacer.should_run_frame());
+
+        pacer.request_frame_advance();
+        assert!(pacer.should_run_frame());
+        assert!(!pacer.should_run_frame());
+
+        pacer.toggle_pause();
+        assert!(pacer.should_run_frame());
+    }
+}
@@ -0,0 +1,704 @@
+// cpu side post processing between the emulator's frame and the window.
+// frames are 0xAARRGGBB words, the same as the window buffer
+
+// nes pixels are slightly wider than they are tall
+const PIXEL_ASPECT: f64 = 8.0 / 7.0;
+
+const SCANLINE_BRIGHTNESS: u32 = 192;
+const MASK_BRIGHTNESS: u32 = 176;
+
+// thresholds for deciding if two colors are different, the same ones hqx uses
+const YUV_Y_THRESHOLD: i32 = 48;
+const YUV_U_THRESHOLD: i32 = 7;
+const YUV_V_THRESHOLD: i32 = 6;
+// colors closer than this count as the same when xbr checks the shape of an edge
+const XBR_SIMILAR_DISTANCE: i32 = 155;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scaler {
+    // integer factor
+    Nearest(usize),
+    Scale2x,
+    Scale3x,
+    Hq2x,
+    Xbr2x,
+    Smooth3x,
+}
+
+impl Scaler {
+    pub fn from_name(name: &str) -> Option<Scaler> {
+        match name {
+            "1x" => Some(Scaler::Nearest(1)),
+            "2x" => Some(Scaler::Nearest(2)),
+            "3x" => Some(Scaler::Nearest(3)),
+            "4x" => Some(Scaler::Nearest(4)),
+            "scale2x" => Some(Scaler::Scale2x),
+            "scale3x" => Some(Scaler::Scale3x),
+            "hq2x" => Some(Scaler::Hq2x),
+            "xbr2x" => Some(Scaler::Xbr2x),
+            "smooth3x" => Some(Scaler::Smooth3x),
+            _ => None
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        match *self {
+            Scaler::Nearest(factor) => factor,
+            Scaler::Scale2x | Scaler::Hq2x | Scaler::Xbr2x => 2,
+            Scaler::Scale3x | Scaler::Smooth3x => 3,
+        }
+    }
+}
+
+// nes pixels to cut from each edge, most tvs hid some of the picture
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    // whether at least one pixel of a width x height frame is left after cropping
+    pub fn fits(&self, width: usize, height: usize) -> bool {
+        self.top + self.bottom < height && self.left + self.right < width
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VideoOptions {
+    pub scaler: Scaler,
+    pub scanlines: bool,
+    pub crt_mask: bool,
+    pub aspect_correction: bool,
+    pub overscan: Overscan,
+}
+
+impl Default for VideoOptions {
+    fn default() -> VideoOptions {
+        VideoOptions {
+            scaler: Scaler::Nearest(2),
+            scanlines: false,
+            crt_mask: false,
+            aspect_correction: false,
+            overscan: Overscan::default(),
+        }
+    }
+}
+
+pub struct VideoFilter {
+    options: VideoOptions,
+
+    cropped: Vec<u32>,
+    scaled: Vec<u32>,
+}
+
+impl VideoFilter {
+    pub fn new(options: VideoOptions) -> VideoFilter {
+        VideoFilter {
+            options: options,
+            cropped: Vec::new(),
+            scaled: Vec::new(),
+        }
+    }
+
+    // pixel_width is how many source pixels make up one nes pixel horizontally, 2 for the ntsc filter's output.
+    // sources that are already wider than the nes only get nearest scaling, then are resampled to the width
+    // the scaler would give the nes picture
+    pub fn output_size(&self, width: usize, height: usize, pixel_width: usize) -> (usize, usize) {
+        let (cropped_width, cropped_height) = self.cropped_size(width, height, pixel_width);
+        let factor = self.options.scaler.factor();
+
+        let scaled_width = (cropped_width * factor + pixel_width / 2) / pixel_width;
+        let output_width = if self.options.aspect_correction {
+            (scaled_width as f64 * PIXEL_ASPECT).round() as usize
+        } else {
+            scaled_width
+        };
+
+        (output_width, cropped_height * factor)
+    }
+
+    pub fn apply(&mut self, frame: &[u32], width: usize, height: usize, pixel_width: usize, out: &mut [u32]) {
+        let (cropped_width, cropped_height) = self.cropped_size(width, height, pixel_width);
+        let (scale_x, scale_y) = self.scale(pixel_width);
+        let overscan = self.options.overscan;
+
+        self.cropped.clear();
+        for y in overscan.top .. overscan.top + cropped_height {
+            let start = y * width + overscan.left * pixel_width;
+            self.cropped.extend_from_slice(&frame[start .. start + cropped_width]);
+        }
+
+        let scaled_width = cropped_width * scale_x;
+        let scaled_height = cropped_height * scale_y;
+        self.scaled.resize(scaled_width * scaled_height, 0);
+
+        let scaler = if pixel_width == 1 { self.options.scaler } else { Scaler::Nearest(0) };
+        match scaler {
+            Scaler::Scale2x => scale2x(&self.cropped, cropped_width, cropped_height, &mut self.scaled),
+            Scaler::Scale3x => scale3x(&self.cropped, cropped_width, cropped_height, &mut self.scaled),
+            Scaler::Hq2x => hq2x(&self.cropped, cropped_width, cropped_height, &mut self.scaled),
+            Scaler::Xbr2x => xbr2x(&self.cropped, cropped_width, cropped_height, &mut self.scaled),
+            Scaler::Smooth3x => smooth3x(&self.cropped, cropped_width, cropped_height, &mut self.scaled),
+            Scaler::Nearest(_) => nearest(&self.cropped, cropped_width, cropped_height, scale_x, scale_y, &mut self.scaled),
+        }
+
+        let (output_width, output_height) = self.output_size(width, height, pixel_width);
+        if output_width == scaled_width {
+            out[.. output_width * output_height].copy_from_slice(&self.scaled);
+        } else {
+            resample_horizontal(&self.scaled, scaled_width, scaled_height, output_width, out);
+        }
+
+        if self.options.scanlines && scale_y > 1 {
+            // darken the last row of each source line
+            for y in 0 .. output_height {
+                if y % scale_y == scale_y - 1 {
+                    for pixel in &mut out[y * output_width .. (y + 1) * output_width] {
+                        *pixel = scale_color(*pixel, [SCANLINE_BRIGHTNESS; 3]);
+                    }
+                }
+            }
+        }
+
+        if self.options.crt_mask {
+            // aperture grille, each column only lets its own primary through at full strength
+            for y in 0 .. output_height {
+                for x in 0 .. output_width {
+                    let mut brightness = [MASK_BRIGHTNESS; 3];
+                    brightness[x % 3] = 256;
+
+                    let pixel = &mut out[y * output_width + x];
+                    *pixel = scale_color(*pixel, brightness);
+                }
+            }
+        }
+    }
+
+    fn cropped_size(&self, width: usize, height: usize, pixel_width: usize) -> (usize, usize) {
+        let overscan = self.options.overscan;
+
+        (width.saturating_sub((overscan.left + overscan.right) * pixel_width).max(1),
+         height.saturating_sub(overscan.top + overscan.bottom).max(1))
+    }
+
+    // enough nearest scaling to reach the output width, which resample_horizontal takes the rest of the way
+    fn scale(&self, pixel_width: usize) -> (usize, usize) {
+        let factor = self.options.scaler.factor();
+
+        ((factor + pixel_width - 1) / pixel_width, factor)
+    }
+}
+
+fn nearest(src: &[u32], width: usize, height: usize, scale_x: usize, scale_y: usize, out: &mut [u32]) {
+    let out_width = width * scale_x;
+
+    for y in 0 .. height * scale_y {
+        let src_row = &src[(y / scale_y) * width .. (y / scale_y + 1) * width];
+        for (x, pixel) in out[y * out_width .. (y + 1) * out_width].iter_mut().enumerate() {
+            *pixel = src_row[x / scale_x];
+        }
+    }
+}
+
+// linear interpolation between columns, used for aspect correction
+fn resample_horizontal(src: &[u32], width: usize, height: usize, out_width: usize, out: &mut [u32]) {
+    let step = width as f64 / out_width as f64;
+
+    for y in 0 .. height {
+        let src_row = &src[y * width .. (y + 1) * width];
+
+        for x in 0 .. out_width {
+            let pos = ((x as f64 + 0.5) * step - 0.5).max(0.0);
+            let left = (pos as usize).min(width - 1);
+            let right = (left + 1).min(width - 1);
+            let weight = ((pos - left as f64) * 256.0) as u32;
+
+            out[y * out_width + x] = blend(src_row[left], 256 - weight, src_row[right], weight);
+        }
+    }
+}
+
+// the 3x3 neighborhood around a pixel, clamped at the edges
+//   a b c
+//   d e f
+//   g h i
+struct Neighbors {
+    a: u32, b: u32, c: u32,
+    d: u32, e: u32, f: u32,
+    g: u32, h: u32, i: u32,
+}
+
+impl Neighbors {
+    fn new(src: &[u32], width: usize, height: usize, x: usize, y: usize) -> Neighbors {
+        let pixel = |dx: isize, dy: isize| get_clamped(src, width, height, x as isize + dx, y as isize + dy);
+
+        Neighbors {
+            a: pixel(-1, -1), b: pixel(0, -1), c: pixel(1, -1),
+            d: pixel(-1, 0), e: pixel(0, 0), f: pixel(1, 0),
+            g: pixel(-1, 1), h: pixel(0, 1), i: pixel(1, 1),
+        }
+    }
+}
+
+fn get_clamped(src: &[u32], width: usize, height: usize, x: isize, y: isize) -> u32 {
+    let x = x.max(0).min(width as isize - 1) as usize;
+    let y = y.max(0).min(height as isize - 1) as usize;
+
+    src[y * width + x]
+}
+
+// scale2x/scale3x (also known as EPX/AdvMAME) only ever copies existing colors
+fn scale2x(src: &[u32], width: usize, height: usize, out: &mut [u32]) {
+    let out_width = width * 2;
+
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let n = Neighbors::new(src, width, height, x, y);
+            let mut block = [n.e; 4];
+
+            if n.b != n.h && n.d != n.f {
+                if n.d == n.b { block[0] = n.d; }
+                if n.b == n.f { block[1] = n.f; }
+                if n.d == n.h { block[2] = n.d; }
+                if n.h == n.f { block[3] = n.f; }
+            }
+
+            write_block(out, out_width, x, y, 2, &block);
+        }
+    }
+}
+
+fn scale3x(src: &[u32], width: usize, height: usize, out: &mut [u32]) {
+    let out_width = width * 3;
+
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let n = Neighbors::new(src, width, height, x, y);
+            let mut block = [n.e; 9];
+
+            if n.b != n.h && n.d != n.f {
+                if n.d == n.b { block[0] = n.d; }
+                if (n.d == n.b && n.e != n.c) || (n.b == n.f && n.e != n.a) { block[1] = n.b; }
+                if n.b == n.f { block[2] = n.f; }
+                if (n.d == n.b && n.e != n.g) || (n.d == n.h && n.e != n.a) { block[3] = n.d; }
+                if (n.b == n.f && n.e != n.i) || (n.h == n.f && n.e != n.c) { block[5] = n.f; }
+                if n.d == n.h { block[6] = n.d; }
+                if (n.d == n.h && n.e != n.i) || (n.h == n.f && n.e != n.g) { block[7] = n.h; }
+                if n.h == n.f { block[8] = n.f; }
+            }
+
+            write_block(out, out_width, x, y, 3, &block);
+        }
+    }
+}
+
+// hq2x as ffmpeg's hqx filter does it. each neighbor that differs from the center in yuv sets a bit in a
+// pattern, and the pattern picks how each corner is blended. the rules are written for the top left corner,
+// the others mirror the window onto it
+fn hq2x(src: &[u32], width: usize, height: usize, out: &mut [u32]) {
+    let out_width = width * 2;
+
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let mut block = [0; 4];
+
+            for (corner, &(mirror_x, mirror_y)) in [(1, 1), (-1, 1), (1, -1), (-1, -1)].iter().enumerate() {
+                let mut w = [0; 9];
+                for (i, pixel) in w.iter_mut().enumerate() {
+                    let (dx, dy) = ((i % 3) as isize - 1, (i / 3) as isize - 1);
+                    *pixel = get_clamped(src, width, height, x as isize + dx * mirror_x, y as isize + dy * mirror_y);
+                }
+
+                block[corner] = hq2x_corner(&w);
+            }
+
+            write_block(out, out_width, x, y, 2, &block);
+        }
+    }
+}
+
+// w is the 3x3 window in reading order, with the center at 4
+fn hq2x_corner(w: &[u32; 9]) -> u32 {
+    // a bit for each neighbor, skipping the center
+    let pattern = [0, 1, 2, 3, 5, 6, 7, 8].iter().enumerate().fold(0, |pattern, (bit, &i)| {
+        if w[i] != w[4] && yuv_differs(w[4], w[i]) { pattern | 1 << bit } else { pattern }
+    });
+    let matches = |cases: &[(u32, u32)]| cases.iter().any(|&(mask, value)| pattern & mask == value);
+    let (w0, w1, w3, w4) = (w[0], w[1], w[3], w[4]);
+
+    if matches(&[(0xbf, 0x37), (0xdb, 0x13)]) && yuv_differs(w1, w[5]) {
+        blend(w4, 192, w3, 64)
+    } else if matches(&[(0xdb, 0x49), (0xef, 0x6d)]) && yuv_differs(w[7], w3) {
+        blend(w4, 192, w1, 64)
+    } else if matches(&[(0x0b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && yuv_differs(w3, w1) {
+        w4
+    } else if matches(&[(0x6f, 0x2a), (0x5b, 0x0a), (0xbf, 0x3a), (0xdf, 0x5a), (0x9f, 0x8a), (0xcf, 0x8a), (0xef, 0x4e),
+                        (0x3f, 0x0e), (0xfb, 0x5a), (0xbb, 0x8a), (0x7f, 0x5a), (0xaf, 0x8a), (0xeb, 0x8a)])
+              && yuv_differs(w3, w1) {
+        blend(w4, 192, w0, 64)
+    } else if matches(&[(0x0b, 0x08)]) {
+        blend3(w4, 128, w0, 64, w1, 64)
+    } else if matches(&[(0x0b, 0x02)]) {
+        blend3(w4, 128, w0, 64, w3, 64)
+    } else if matches(&[(0x2f, 0x2f)]) {
+        blend3(w4, 224, w3, 16, w1, 16)
+    } else if matches(&[(0xbf, 0x37), (0xdb, 0x13)]) {
+        blend3(w4, 160, w1, 64, w3, 32)
+    } else if matches(&[(0xdb, 0x49), (0xef, 0x6d)]) {
+        blend3(w4, 160, w3, 64, w1, 32)
+    } else if matches(&[(0x1b, 0x03), (0x4f, 0x43), (0x8b, 0x83), (0x6b, 0x43)]) {
+        blend(w4, 192, w3, 64)
+    } else if matches(&[(0x4b, 0x09), (0x8b, 0x89), (0x1f, 0x19), (0x3b, 0x19)]) {
+        blend(w4, 192, w1, 64)
+    } else if matches(&[(0x7e, 0x2a), (0xef, 0xab), (0xbf, 0x8f), (0x7e, 0x0e)]) {
+        blend3(w4, 64, w3, 96, w1, 96)
+    } else if matches(&[(0xfb, 0x6a), (0x6f, 0x6e), (0x3f, 0x3e), (0xfb, 0xfa), (0xdf, 0xde), (0xdf, 0x1e)]) {
+        blend(w4, 192, w0, 64)
+    } else if matches(&[(0x0a, 0x00), (0x4f, 0x4b), (0x9f, 0x1b), (0x2f, 0x0b), (0xbe, 0x0a), (0xee, 0x0a), (0x7e, 0x0a),
+                        (0xeb, 0x4b), (0x3b, 0x1b)]) {
+        blend3(w4, 128, w3, 64, w1, 64)
+    } else {
+        blend3(w4, 192, w3, 32, w1, 32)
+    }
+}
+
+// compares neighbors in yuv with the same thresholds as hqx and blends along the edges it finds, but with a
+// handful of rules rather than hq3x's pattern table, so it's softer and doesn't match hq3x
+fn smooth3x(src: &[u32], width: usize, height: usize, out: &mut [u32]) {
+    let out_width = width * 3;
+
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let n = Neighbors::new(src, width, height, x, y);
+
+            // corners, each with the two neighbors along its sides and the one diagonal to it
+            let top_left = smooth_corner(n.e, n.b, n.d, n.a);
+            let top_right = smooth_corner(n.e, n.b, n.f, n.c);
+            let bottom_left = smooth_corner(n.e, n.h, n.d, n.g);
+            let bottom_right = smooth_corner(n.e, n.h, n.f, n.i);
+
+            let top = smooth_side(n.e, n.b, top_left != n.e || top_right != n.e);
+            let left = smooth_side(n.e, n.d, top_left != n.e || bottom_left != n.e);
+            let right = smooth_side(n.e, n.f, top_right != n.e || bottom_right != n.e);
+            let bottom = smooth_side(n.e, n.h, bottom_left != n.e || bottom_right != n.e);
+
+            write_block(out, out_width, x, y, 3, &[
+                top_left, top, top_right,
+                left, n.e, right,
+                bottom_left, bottom, bottom_right
+            ]);
+        }
+    }
+}
+
+fn smooth_corner(e: u32, side1: u32, side2: u32, diagonal: u32) -> u32 {
+    let differs1 = yuv_differs(e, side1);
+    let differs2 = yuv_differs(e, side2);
+
+    if differs1 && differs2 && !yuv_differs(side1, side2) {
+        // an edge crosses the corner
+        if yuv_differs(e, diagonal) {
+            blend3(e, 2, side1, 1, side2, 1)
+        } else {
+            blend3(e, 6, side1, 1, side2, 1)
+        }
+    } else if !differs1 && !differs2 && yuv_differs(e, diagonal) {
+        blend(e, 192, diagonal, 64)
+    } else if differs1 != differs2 {
+        // soften along the side that differs
+        let other = if differs1 { side1 } else { side2 };
+        blend(e, 224, other, 32)
+    } else {
+        e
+    }
+}
+
+fn smooth_side(e: u32, side: u32, corner_blended: bool) -> u32 {
+    if corner_blended && yuv_differs(e, side) {
+        blend(e, 224, side, 32)
+    } else {
+        e
+    }
+}
+
+fn yuv_differs(a: u32, b: u32) -> bool {
+    let (ay, au, av) = yuv(a);
+    let (by, bu, bv) = yuv(b);
+
+    (ay - by).abs() > YUV_Y_THRESHOLD || (au - bu).abs() > YUV_U_THRESHOLD || (av - bv).abs() > YUV_V_THRESHOLD
+}
+
+fn yuv(color: u32) -> (i32, i32, i32) {
+    let (r, g, b) = (((color >> 16) & 0xff) as i32, ((color >> 8) & 0xff) as i32, (color & 0xff) as i32);
+
+    ((r * 299 + g * 587 + b * 114) / 1000,
+     (-r * 169 - g * 331 + b * 500) / 1000 + 128,
+     (r * 500 - g * 419 - b * 81) / 1000 + 128)
+}
+
+// the yuv distance xbr uses to find edges
+fn yuv_distance(a: u32, b: u32) -> i32 {
+    let (ay, au, av) = yuv(a);
+    let (by, bu, bv) = yuv(b);
+
+    (ay - by).abs() + (au - bu).abs() + (av - bv).abs()
+}
+
+fn yuv_similar(a: u32, b: u32) -> bool {
+    yuv_distance(a, b) < XBR_SIMILAR_DISTANCE
+}
+
+// hyllian's 2xbr at level 2, as ffmpeg's xbr filter does it. each corner compares the weighted distances along
+// the two diagonals of a 5x5 window, then looks at the slope of the edge to decide how far the blend spreads.
+// the rules are written for the bottom right corner and the window is rotated onto it for the others
+fn xbr2x(src: &[u32], width: usize, height: usize, out: &mut [u32]) {
+    let out_width = width * 2;
+
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let mut block = [get_clamped(src, width, height, x as isize, y as isize); 4];
+
+            // quarter turns, in the order the corners are filtered
+            let rotations: [fn(isize, isize) -> (isize, isize); 4] = [
+                |dx, dy| (dx, dy), |dx, dy| (dy, -dx), |dx, dy| (-dx, -dy), |dx, dy| (-dy, dx)
+            ];
+            for rotate in rotations.iter() {
+                let pixel = |dx: isize, dy: isize| {
+                    let (dx, dy) = rotate(dx, dy);
+                    get_clamped(src, width, height, x as isize + dx, y as isize + dy)
+                };
+                // where the bottom right, bottom left and top right corners end up in the block
+                let corner = |dx: isize, dy: isize| {
+                    let (dx, dy) = rotate(dx, dy);
+                    (dy > 0) as usize * 2 + (dx > 0) as usize
+                };
+
+                xbr_corner(&pixel, &mut block, corner(1, 1), corner(-1, 1), corner(1, -1));
+            }
+
+            write_block(out, out_width, x, y, 2, &block);
+        }
+    }
+}
+
+//      b1 c1
+//   a  b  c  c4
+//   d  e  f  f4
+//   g  h  i  i4
+//      h5 i5
+fn xbr_corner<F: Fn(isize, isize) -> u32>(pixel: &F, block: &mut [u32; 4], corner: usize, left: usize, up: usize) {
+    let (b, c, d, e, f) = (pixel(0, -1), pixel(1, -1), pixel(-1, 0), pixel(0, 0), pixel(1, 0));
+    let (g, h, i) = (pixel(-1, 1), pixel(0, 1), pixel(1, 1));
+    let (f4, i4, h5, i5) = (pixel(2, 0), pixel(2, 1), pixel(0, 2), pixel(1, 2));
+
+    if e == h || e == f {
+        return;
+    }
+
+    let edge = yuv_distance(e, c) + yuv_distance(e, g) + yuv_distance(i, h5) + yuv_distance(i, f4)
+             + 4 * yuv_distance(h, f);
+    let across = yuv_distance(h, d) + yuv_distance(h, i5) + yuv_distance(f, i4) + yuv_distance(f, b)
+               + 4 * yuv_distance(e, i);
+    let closer = if yuv_distance(e, f) <= yuv_distance(e, h) { f } else { h };
+    let towards = |color: u32, weight: u32| blend(color, 256 - weight, closer, weight);
+
+    let corner_edge = (!yuv_similar(f, b) && !yuv_similar(h, d))
+                   || (yuv_similar(e, i) && !yuv_similar(f, i4) && !yuv_similar(h, i5))
+                   || yuv_similar(e, g) || yuv_similar(e, c);
+
+    if edge < across && corner_edge {
+        // a shallow edge spreads the blend to the left, a steep one upwards
+        let shallow = 2 * yuv_distance(f, g) <= yuv_distance(h, c) && e != g && d != g;
+        let steep = yuv_distance(f, g) >= 2 * yuv_distance(h, c) && e != c && b != c;
+
+        if shallow && steep {
+            block[corner] = towards(block[corner], 224);
+            block[left] = towards(block[left], 64);
+            block[up] = block[left];
+        } else if shallow {
+            block[corner] = towards(block[corner], 192);
+            block[left] = towards(block[left], 64);
+        } else if steep {
+            block[corner] = towards(block[corner], 192);
+            block[up] = towards(block[up], 64);
+        } else {
+            block[corner] = towards(block[corner], 128);
+        }
+    } else if edge <= across {
+        block[corner] = towards(block[corner], 128);
+    }
+}
+
+fn write_block(out: &mut [u32], out_width: usize, x: usize, y: usize, factor: usize, block: &[u32]) {
+    for row in 0 .. factor {
+        let start = (y * factor + row) * out_width + x * factor;
+        out[start .. start + factor].copy_from_slice(&block[row * factor .. (row + 1) * factor]);
+    }
+}
+
+// weights are out of 256
+fn blend(a: u32, weight_a: u32, b: u32, weight_b: u32) -> u32 {
+    let channel = |shift: u32| {
+        ((((a >> shift) & 0xff) * weight_a + ((b >> shift) & 0xff) * weight_b) / 256) << shift
+    };
+
+    0xff00_0000 | channel(16) | channel(8) | channel(0)
+}
+
+fn blend3(a: u32, weight_a: u32, b: u32, weight_b: u32, c: u32, weight_c: u32) -> u32 {
+    let total = weight_a + weight_b + weight_c;
+    let channel = |shift: u32| {
+        ((((a >> shift) & 0xff) * weight_a + ((b >> shift) & 0xff) * weight_b + ((c >> shift) & 0xff) * weight_c) / total) << shift
+    };
+
+    0xff00_0000 | channel(16) | channel(8) | channel(0)
+}
+
+// brightness per channel is out of 256
+fn scale_color(color: u32, brightness: [u32; 3]) -> u32 {
+    let channel = |shift: u32, brightness: u32| (((color >> shift) & 0xff) * brightness / 256) << shift;
+
+    0xff00_0000 | channel(16, brightness[0]) | channel(8, brightness[1]) | channel(0, brightness[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: u32 = 0xff00_0000;
+    const WHITE: u32 = 0xffff_ffff;
+
+    fn run(options: VideoOptions, frame: &[u32], width: usize, height: usize) -> (usize, usize, Vec<u32>) {
+        let mut filter = VideoFilter::new(options);
+        let (out_width, out_height) = filter.output_size(width, height, 1);
+        let mut out = vec![0; out_width * out_height];
+        filter.apply(frame, width, height, 1, &mut out);
+
+        (out_width, out_height, out)
+    }
+
+    #[test]
+    fn nearest_scaling_and_cropping() {
+        let frame: Vec<u32> = (0 .. 16).collect();
+        let options = VideoOptions {
+            scaler: Scaler::Nearest(2),
+            overscan: Overscan { top: 1, bottom: 1, left: 1, right: 0 },
+            ..VideoOptions::default()
+        };
+
+        let (width, height, out) = run(options, &frame, 4, 4);
+        assert_eq!((width, height), (6, 4));
+        assert_eq!(&out[0 .. 6], &[5, 5, 6, 6, 7, 7]);
+        assert_eq!(&out[18 .. 24], &[9, 9, 10, 10, 11, 11]);
+    }
+
+    #[test]
+    fn overscan_has_to_leave_a_pixel() {
+        let overscan = |top, bottom, left, right| Overscan { top: top, bottom: bottom, left: left, right: right };
+
+        assert!(overscan(119, 120, 0, 0).fits(256, 240));
+        assert!(!overscan(120, 120, 0, 0).fits(256, 240));
+        assert!(!overscan(240, 0, 0, 0).fits(256, 240));
+        assert!(overscan(0, 0, 127, 128).fits(256, 240));
+        assert!(!overscan(0, 0, 128, 128).fits(256, 240));
+        assert!(!overscan(0, 0, 0, 256).fits(256, 240));
+    }
+
+    #[test]
+    fn aspect_correction_widens_the_picture() {
+        let frame = vec![WHITE; 256 * 240];
+        let options = VideoOptions { aspect_correction: true, ..VideoOptions::default() };
+
+        let (width, height, out) = run(options, &frame, 256, 240);
+        assert_eq!((width, height), (585, 480));
+        assert!(out.iter().all(|&pixel| pixel == WHITE));
+    }
+
+    #[test]
+    fn scale2x_smooths_diagonals() {
+        // a diagonal line of white on black
+        let frame = [
+            WHITE, BLACK, BLACK,
+            BLACK, WHITE, BLACK,
+            BLACK, BLACK, WHITE,
+        ];
+        let options = VideoOptions { scaler: Scaler::Scale2x, ..VideoOptions::default() };
+
+        let (width, _, out) = run(options, &frame, 3, 3);
+        // the black pixel right of the center gets its lower left corner filled in
+        assert_eq!(out[2 * width + 4], BLACK);
+        assert_eq!(out[3 * width + 4], WHITE);
+
+        for &scaler in [Scaler::Scale3x, Scaler::Hq2x, Scaler::Xbr2x, Scaler::Smooth3x].iter() {
+            let options = VideoOptions { scaler: scaler, ..VideoOptions::default() };
+            let (width, height, out) = run(options, &frame, 3, 3);
+
+            assert_eq!((width, height), (3 * scaler.factor(), 3 * scaler.factor()));
+            // the middle of the line stays bright, though the smoothing ones may round it off a little
+            assert!(out[(height / 2) * width + width / 2] & 0xff > 0x80);
+        }
+    }
+
+    #[test]
+    fn hq2x_and_xbr_blend_across_edges() {
+        let frame = [
+            WHITE, BLACK, BLACK,
+            BLACK, WHITE, BLACK,
+            BLACK, BLACK, WHITE,
+        ];
+
+        // hq2x keeps the corners along the line and blends the ones off it half way
+        let options = VideoOptions { scaler: Scaler::Hq2x, ..VideoOptions::default() };
+        let (width, _, out) = run(options, &frame, 3, 3);
+        assert_eq!(out[2 * width + 2], WHITE);
+        assert_eq!(out[3 * width + 3], WHITE);
+        assert_eq!(out[2 * width + 3], 0xff7f_7f7f);
+
+        // xbr fills in the corner of the pixel above the line, most of the way towards white
+        let options = VideoOptions { scaler: Scaler::Xbr2x, ..VideoOptions::default() };
+        let (width, _, out) = run(options, &frame, 3, 3);
+        assert_eq!(out[width + 2], 0xffbf_bfbf);
+        assert_eq!(out[2 * width + 2], WHITE);
+
+        // and neither touches flat areas or straight edges
+        let mut frame = vec![BLACK; 16];
+        for pixel in frame[8 ..].iter_mut() {
+            *pixel = WHITE;
+        }
+        for &scaler in [Scaler::Hq2x, Scaler::Xbr2x].iter() {
+            let options = VideoOptions { scaler: scaler, ..VideoOptions::default() };
+            let (width, _, out) = run(options, &frame, 4, 4);
+            assert!(out.iter().enumerate().all(|(i, &pixel)| pixel == if i / width < 4 { BLACK } else { WHITE }));
+        }
+    }
+
+    #[test]
+    fn wide_sources_keep_the_nes_width() {
+        let frame = vec![WHITE; 512 * 240];
+        let options = VideoOptions { scaler: Scaler::Nearest(3), ..VideoOptions::default() };
+        let mut filter = VideoFilter::new(options);
+
+        let (width, height) = filter.output_size(512, 240, 2);
+        assert_eq!((width, height), (768, 720));
+
+        let mut out = vec![0; width * height];
+        filter.apply(&frame, 512, 240, 2, &mut out);
+        assert!(out.iter().all(|&pixel| pixel == WHITE));
+
+        let options = VideoOptions { scaler: Scaler::Nearest(2), ..VideoOptions::default() };
+        assert_eq!(VideoFilter::new(options).output_size(512, 240, 2), (512, 480));
+    }
+
+    #[test]
+    fn scanlines_darken_every_other_row() {
+        let frame = vec![WHITE; 4];
+        let options = VideoOptions { scanlines: true, ..VideoOptions::default() };
+
+        let (width, _, out) = run(options, &frame, 2, 2);
+        assert_eq!(out[0], WHITE);
+        assert_eq!(out[width], 0xffbf_bfbf);
+    }
+}
@@ -0,0 +1,60 @@
+use std::io;
+use std::io::Write;
+
+const BITS_PER_SAMPLE: u16 = 16;
+
+// 16 bit mono pcm, with samples clamped to -1.0 to 1.0
+pub fn write_wav<W: Write>(mut writer: W, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    let block_align = BITS_PER_SAMPLE / 8;
+    let data_size = samples.len() as u32 * block_align as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&u32_bytes(36 + data_size))?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&u32_bytes(16))?;
+    // pcm, 1 channel
+    writer.write_all(&u16_bytes(1))?;
+    writer.write_all(&u16_bytes(1))?;
+    writer.write_all(&u32_bytes(sample_rate))?;
+    writer.write_all(&u32_bytes(sample_rate * block_align as u32))?;
+    writer.write_all(&u16_bytes(block_align))?;
+    writer.write_all(&u16_bytes(BITS_PER_SAMPLE))?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&u32_bytes(data_size))?;
+
+    let mut data = Vec::with_capacity(data_size as usize);
+    for &sample in samples {
+        let val = (sample.max(-1.0).min(1.0) * 32767.0) as i16;
+        data.extend_from_slice(&u16_bytes(val as u16));
+    }
+    writer.write_all(&data)
+}
+
+fn u16_bytes(val: u16) -> [u8; 2] {
+    [val as u8, (val >> 8) as u8]
+}
+
+fn u32_bytes(val: u32) -> [u8; 4] {
+    [val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_pcm_header_and_clamped_samples() {
+        let mut out = Vec::new();
+        write_wav(&mut out, &[0.0, 1.0, -2.0], 44100).unwrap();
+
+        assert_eq!(out.len(), 44 + 6);
+        assert_eq!(&out[0 .. 4], b"RIFF");
+        assert_eq!(&out[4 .. 8], &[42, 0, 0, 0]);
+        assert_eq!(&out[24 .. 28], &u32_bytes(44100));
+        assert_eq!(&out[40 .. 44], &[6, 0, 0, 0]);
+        assert_eq!(&out[44 ..], &[0x00, 0x00, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}
@@ -1,5 +1,7 @@
 use mapper::Mapper;
+use rom::Region;
 use memory::Memory;
+use state::{SaveState, StateWriter, StateReader};
 use std::rc::Rc;
 use std::cell::RefCell;
 
@@ -43,6 +45,13 @@ const DMC_RATES: [u16; 16] = [
     428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106,  84,  72,  54
 ];
 
+// frame sequencer steps in cpu cycles, the last two are the ends of the four and five step sequences.
+// the dendy counts like an ntsc console, so its sequence runs at 59hz
+const NTSC_FRAME_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+pub const SAMPLE_RATE: u32 = 44100;
+
 pub struct Apu {
     cycle: u64,
 
@@ -53,10 +62,21 @@ pub struct Apu {
     dmc: DmcChannel,
 
     frame_mode: FrameMode,
-    
+    frame_steps: [u32; 5],
+    frame_counter: u32,
+    frame_interrupt_inhibit: bool,
+
     pub frame_interrupt: bool,
     pub dmc_interrupt: bool,
 
+    // resampling to SAMPLE_RATE
+    clock_rate: f64,
+    sample_clock: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    filters: [Filter; 3],
+    samples: Vec<f32>,
+
     mapper: Rc<RefCell<Box<Mapper>>>,
 }
 
@@ -72,20 +92,162 @@ impl Apu {
             dmc: DmcChannel::default(),
 
             frame_mode: FrameMode::FourStep,
+            frame_steps: NTSC_FRAME_STEPS,
+            frame_counter: 0,
+            frame_interrupt_inhibit: false,
             frame_interrupt: false,
 
             dmc_interrupt: false,
 
+            clock_rate: Region::Ntsc.cpu_clock_rate(),
+            sample_clock: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            filters: Filter::output_chain(SAMPLE_RATE as f32),
+            samples: Vec::new(),
+
             mapper: mapper,
         }
     }
 
+    // reset silences the apu, but leaves the frame counter mode alone
+    pub fn reset(&mut self) {
+        self.write_status(0);
+
+        // the triangle restarts at the beginning of its sequence and the dmc output loses its upper 6 bits
+        self.triangle.sequence_index = 0;
+        self.dmc.output_level &= 1;
+
+        self.frame_interrupt = false;
+    }
+
+    pub fn power_cycle(&mut self) {
+        self.pulse1 = PulseChannel::new(1);
+        self.pulse2 = PulseChannel::new(2);
+        self.triangle = TriangleChannel::default();
+        self.noise = NoiseChannel::new();
+        self.dmc = DmcChannel::default();
+
+        self.frame_mode = FrameMode::FourStep;
+        self.frame_counter = 0;
+        self.frame_interrupt_inhibit = false;
+        self.frame_interrupt = false;
+        self.dmc_interrupt = false;
+        self.cycle = 0;
+    }
+
+    // sets the frame sequencer timing and the cpu clock rate the output is resampled from
+    pub fn set_region(&mut self, region: Region) {
+        self.frame_steps = match region {
+            Region::Pal => PAL_FRAME_STEPS,
+            Region::Ntsc | Region::Dendy => NTSC_FRAME_STEPS
+        };
+        self.clock_rate = region.cpu_clock_rate();
+    }
+
+    // samples at SAMPLE_RATE, roughly in the range -1.0 to 1.0
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub fn clear_samples(&mut self) {
+        self.samples.clear();
+    }
+
+    // called once per cpu cycle
     pub fn step(&mut self) {
-        // step each channel, get and mix output
-        
+        self.step_frame_counter();
+
+        // pulse timers are clocked every other cpu cycle, the rest every cycle
+        if self.cycle % 2 == 1 {
+            self.step_pulse();
+        }
+        self.step_triangle();
+        self.step_noise();
+        self.step_dmc();
+
+        self.step_output();
+
         self.cycle += 1;
     }
 
+    fn step_frame_counter(&mut self) {
+        self.frame_counter += 1;
+
+        let steps = self.frame_steps;
+        let five_step = self.frame_mode == FrameMode::FiveStep;
+
+        if self.frame_counter == steps[0] || self.frame_counter == steps[2] {
+            self.clock_quarter_frame();
+        } else if self.frame_counter == steps[1] {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        } else if self.frame_counter == steps[3] && !five_step {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+
+            if !self.frame_interrupt_inhibit {
+                self.frame_interrupt = true;
+            }
+            self.frame_counter = 0;
+        } else if self.frame_counter == steps[4] && five_step {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+            self.frame_counter = 0;
+        }
+    }
+
+    // envelopes and the triangle's linear counter
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.triangle.step_linear_counter();
+        self.noise.envelope.clock();
+    }
+
+    // length counters and sweep units
+    fn clock_half_frame(&mut self) {
+        self.pulse1.length_counter.clock();
+        self.pulse2.length_counter.clock();
+        self.triangle.length_counter.clock();
+        self.noise.length_counter.clock();
+
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    fn step_output(&mut self) {
+        self.sample_sum += self.mix();
+        self.sample_count += 1;
+
+        // box filter down to the output rate
+        self.sample_clock += SAMPLE_RATE as f64;
+        if self.sample_clock >= self.clock_rate {
+            self.sample_clock -= self.clock_rate;
+
+            let mut sample = self.sample_sum / self.sample_count as f32;
+            for filter in self.filters.iter_mut() {
+                sample = filter.apply(sample);
+            }
+            self.samples.push(sample);
+
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+        }
+    }
+
+    // nonlinear mixer approximation from the nesdev wiki, plus whatever the cartridge adds
+    fn mix(&self) -> f32 {
+        let pulse_out = mix_pulses(self.pulse1.output(), self.pulse2.output());
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+                + self.noise.output() as f32 / 12241.0
+                + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+
+        pulse_out + tnd_out + self.mapper.borrow().audio_output()
+    }
+
     fn read_status(&mut self) -> u8 {
         let status = (self.dmc_interrupt as u8) << 7
                     | (self.frame_interrupt as u8) << 6
@@ -134,31 +296,30 @@ impl Apu {
     }
 
     fn write_frame_counter(&mut self, val: u8) {
+        self.frame_counter = 0;
+
         if (val >> 7) & 1 == 0 {
             self.frame_mode = FrameMode::FourStep;
         } else {
             self.frame_mode = FrameMode::FiveStep;
+
+            // five step mode clocks everything immediately
+            self.clock_quarter_frame();
+            self.clock_half_frame();
         }
 
-        self.frame_interrupt = (val >> 6) & 1 != 1;
+        self.frame_interrupt_inhibit = (val >> 6) & 1 == 1;
+        if self.frame_interrupt_inhibit {
+            self.frame_interrupt = false;
+        }
     }
 
     // channel steps
 
     // Pulse
     fn step_pulse(&mut self) {
-        let pulse1 = &mut self.pulse1;
-        let pulse2 = &mut self.pulse2;
-
-        let clock1 = pulse1.timer.tick();
-        if clock1 {
-            pulse1.sequence_index = (pulse1.sequence_index + 1) % 8;
-        }
-
-        let clock2 = pulse2.timer.tick();
-        if clock2 {
-            pulse2.sequence_index = (pulse2.sequence_index + 1) % 8;
-        }
+        self.pulse1.step_timer();
+        self.pulse2.step_timer();
     }
 
     // Triangle
@@ -204,13 +365,14 @@ impl Apu {
     fn step_dmc_memory_reader(&mut self) {
         let dmc = &mut self.dmc;
 
-        if dmc.sample_buffer == 0 && dmc.bytes_remaining > 0 {
+        if !dmc.sample_buffer_full && dmc.bytes_remaining > 0 {
             // TODO: CPU stalls for up to 4 cycles
             
             dmc.sample_buffer = self.mapper.borrow_mut().load_byte_prg(dmc.current_address);
+            dmc.sample_buffer_full = true;
             
-            dmc.current_address += 1;
             // address wraps around to 0x8000
+            dmc.current_address = dmc.current_address.wrapping_add(1);
             if dmc.current_address == 0 {
                 dmc.current_address = 0x8000;
             }
@@ -231,19 +393,22 @@ impl Apu {
 
         if dmc.output_bits_remaining == 0 {
             dmc.output_bits_remaining = 8;
-            if dmc.sample_buffer != 0 {
+            dmc.silence = !dmc.sample_buffer_full;
+            if dmc.sample_buffer_full {
                 dmc.output_shift_register = dmc.sample_buffer;
-                dmc.sample_buffer = 0;
+                dmc.sample_buffer_full = false;
             }
         }
 
-        if dmc.output_shift_register & 1 == 1 {
-            if dmc.output_level <= 125 {
-                dmc.output_level += 2;
-            }
-        } else {
-            if dmc.output_level >= 2 {
-                dmc.output_level -= 2;
+        if !dmc.silence {
+            if dmc.output_shift_register & 1 == 1 {
+                if dmc.output_level <= 125 {
+                    dmc.output_level += 2;
+                }
+            } else {
+                if dmc.output_level >= 2 {
+                    dmc.output_level -= 2;
+                }
             }
         }
 
@@ -279,8 +444,49 @@ impl Memory for Apu {
     }
 }
 
-struct PulseChannel {
+impl SaveState for Apu {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u64(self.cycle);
+
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+
+        state.write_bool(self.frame_mode == FrameMode::FiveStep);
+        state.write_u32(self.frame_counter);
+        state.write_bool(self.frame_interrupt_inhibit);
+        state.write_bool(self.frame_interrupt);
+        state.write_bool(self.dmc_interrupt);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.cycle = state.read_u64();
+
+        self.pulse1.load_state(state);
+        self.pulse2.load_state(state);
+        self.triangle.load_state(state);
+        self.noise.load_state(state);
+        self.dmc.load_state(state);
+
+        self.frame_mode = if state.read_bool() { FrameMode::FiveStep } else { FrameMode::FourStep };
+        self.frame_counter = state.read_u32();
+        self.frame_interrupt_inhibit = state.read_bool();
+        self.frame_interrupt = state.read_bool();
+        self.dmc_interrupt = state.read_bool();
+    }
+}
+
+// the pulse mixer, also used for the pulse channels on cartridges
+pub fn mix_pulses(pulse1: u8, pulse2: u8) -> f32 {
+    let pulse = (pulse1 + pulse2) as f32;
+    if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) }
+}
+
+// public for mappers with their own pulse channels (mmc5), which work the same minus the sweep unit
+pub struct PulseChannel {
     channel: u8,
+    has_sweep: bool,
     enabled: bool,
     duty_cycle: u8,
     length_counter: LengthCounter,
@@ -299,6 +505,7 @@ impl PulseChannel {
     fn new(channel: u8) -> PulseChannel {
         PulseChannel {
             channel: channel,
+            has_sweep: true,
             enabled: false,
             duty_cycle: 0,
             length_counter: LengthCounter::default(),
@@ -314,7 +521,15 @@ impl PulseChannel {
         }
     }
 
-    fn write(&mut self, addr: u16, val: u8) {
+    pub fn without_sweep() -> PulseChannel {
+        PulseChannel {
+            has_sweep: false,
+            .. PulseChannel::new(0)
+        }
+    }
+
+    // registers 0, 2 and 3 of the channel, by the low 2 bits of the address
+    pub fn write(&mut self, addr: u16, val: u8) {
         match addr & 3 {
             0 => {
                 // DDLC VVVV	Duty (D), envelope loop / length counter halt (L), constant volume (C), volume/envelope (V)
@@ -326,10 +541,11 @@ impl PulseChannel {
             1 => {
                 // EPPP NSSS	Sweep unit: enabled (E), period (P), negate (N), shift (S)
                 self.sweep_enabled = (val >> 7) & 1 == 1;
-                // sweep period is P + 1
-                self.sweep_period = ((val >> 4) & 7) + 1;
+                // the divider counts P + 1 half frames
+                self.sweep_period = (val >> 4) & 7;
                 self.sweep_negate = (val >> 3) & 1 == 1;
                 self.sweep_shift = val & 7;
+                self.sweep_reload = true;
             },
             2 => {
                 // TTTT TTTT	Timer low (T)
@@ -350,40 +566,73 @@ impl PulseChannel {
         }
     }
 
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter.counter = 0;
+        }
+    }
+
+    pub fn length_active(&self) -> bool {
+        self.length_counter.counter > 0
+    }
+
+    // every other cpu cycle
+    pub fn step_timer(&mut self) {
+        if self.timer.tick() {
+            self.sequence_index = (self.sequence_index + 1) % 8;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+        if self.has_sweep {
+            self.clock_sweep();
+        }
+    }
+
     fn clock_sweep(&mut self) {
-        if self.sweep_reload {
-            if self.sweep_counter == 0 && self.sweep_enabled {
-                self.sweep_timer();
-            }
-            
+        if self.sweep_counter == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.sweep_muted() {
+            self.timer.period = self.sweep_target();
+        }
+
+        if self.sweep_counter == 0 || self.sweep_reload {
             self.sweep_counter = self.sweep_period;
             self.sweep_reload = false;
-        } else if self.sweep_counter > 0 {
+        } else {
             self.sweep_counter -= 1;
-        } else if self.sweep_enabled {
-            self.sweep_counter = self.sweep_period;
-            self.sweep_timer();
         }
     }
 
-    fn sweep_timer(&mut self) {
-        let mut change = self.timer.period >> self.sweep_shift;
+    // the period the sweep unit is moving towards, calculated continuously
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer.period >> self.sweep_shift;
 
         if self.sweep_negate {
-            self.timer.period -= change;
             // pulse1 adds the one's complement, which subtracts one more
             if self.channel == 1 {
-                self.timer.period -= 1;
+                self.timer.period.saturating_sub(change + 1)
+            } else {
+                self.timer.period.saturating_sub(change)
             }
         } else {
-            self.timer.period += change;
+            self.timer.period + change
         }
     }
 
-    fn output(&self) -> u8 {
+    // the channel is silenced if the period is too low or the sweep would overflow, even with the sweep disabled
+    fn sweep_muted(&self) -> bool {
+        self.has_sweep && (self.timer.period < 8 || self.sweep_target() > 0x7ff)
+    }
+
+    pub fn output(&self) -> u8 {
         if !self.enabled
             || self.length_counter.counter == 0
-            || self.timer.period < 8 || self.timer.period > 0x7ff
+            || self.sweep_muted()
             || PULSE_SEQUENCE[self.duty_cycle as usize][self.sequence_index as usize] == 0 {
             return 0;
         }
@@ -392,6 +641,37 @@ impl PulseChannel {
     }
 }
 
+impl SaveState for PulseChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.duty_cycle);
+        self.length_counter.save_state(state);
+        self.envelope.save_state(state);
+        state.write_bool(self.sweep_enabled);
+        state.write_u8(self.sweep_period);
+        state.write_bool(self.sweep_negate);
+        state.write_u8(self.sweep_shift);
+        state.write_bool(self.sweep_reload);
+        state.write_u8(self.sweep_counter);
+        self.timer.save_state(state);
+        state.write_u8(self.sequence_index);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.enabled = state.read_bool();
+        self.duty_cycle = state.read_u8();
+        self.length_counter.load_state(state);
+        self.envelope.load_state(state);
+        self.sweep_enabled = state.read_bool();
+        self.sweep_period = state.read_u8();
+        self.sweep_negate = state.read_bool();
+        self.sweep_shift = state.read_u8();
+        self.sweep_reload = state.read_bool();
+        self.sweep_counter = state.read_u8();
+        self.timer.load_state(state);
+        self.sequence_index = state.read_u8();
+    }
+}
+
 #[derive(Default)]
 struct TriangleChannel {
     enabled: bool,
@@ -453,6 +733,29 @@ impl TriangleChannel {
     }
 }
 
+impl SaveState for TriangleChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        self.length_counter.save_state(state);
+        state.write_u8(self.linear_counter);
+        state.write_bool(self.linear_counter_control_flag);
+        state.write_bool(self.linear_counter_reload_flag);
+        state.write_u8(self.linear_counter_reload_value);
+        self.timer.save_state(state);
+        state.write_u8(self.sequence_index);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.enabled = state.read_bool();
+        self.length_counter.load_state(state);
+        self.linear_counter = state.read_u8();
+        self.linear_counter_control_flag = state.read_bool();
+        self.linear_counter_reload_flag = state.read_bool();
+        self.linear_counter_reload_value = state.read_u8();
+        self.timer.load_state(state);
+        self.sequence_index = state.read_u8();
+    }
+}
+
 struct NoiseChannel {
     enabled: bool,
     length_counter: LengthCounter,
@@ -486,7 +789,8 @@ impl NoiseChannel {
             0x400e => {
                 // L--- PPPP	Loop noise (L), noise period (P)
                 self.mode_flag = (val >> 7) & 1 == 1;
-                self.timer.period = NOISE_TABLE[val as usize & 0x0f];
+                // the table is in cpu cycles and the timer counts period + 1
+                self.timer.period = NOISE_TABLE[val as usize & 0x0f] - 1;
             },
             0x400f => {
                 // LLLL L---	Length counter load (L)
@@ -511,6 +815,25 @@ impl NoiseChannel {
     }
 }
 
+impl SaveState for NoiseChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        self.length_counter.save_state(state);
+        self.envelope.save_state(state);
+        self.timer.save_state(state);
+        state.write_bool(self.mode_flag);
+        state.write_u16(self.shift_register);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.enabled = state.read_bool();
+        self.length_counter.load_state(state);
+        self.envelope.load_state(state);
+        self.timer.load_state(state);
+        self.mode_flag = state.read_bool();
+        self.shift_register = state.read_u16();
+    }
+}
+
 #[derive(Default)]
 struct DmcChannel {
     enabled: bool,
@@ -519,6 +842,8 @@ struct DmcChannel {
     sample_address: u16,
     sample_length: u16,
     sample_buffer: u8,
+    sample_buffer_full: bool,
+    silence: bool,
     current_address: u16,
     bytes_remaining: u16,
     output_level: u8,
@@ -534,7 +859,7 @@ impl DmcChannel {
                 // IL-- RRRR	IRQ enable (I), loop (L), frequency (R)
                 self.interrupt_enable = (val >> 7) & 1 == 1;
                 self.dmc_loop = (val >> 6) & 1 == 1;
-                self.timer.period = DMC_RATES[val as usize & 0x0f];
+                self.timer.period = DMC_RATES[val as usize & 0x0f] - 1;
             },
             0x4011 => {
                 // -DDD DDDD	Load counter (D)
@@ -564,6 +889,41 @@ impl DmcChannel {
     }
 }
 
+impl SaveState for DmcChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.interrupt_enable);
+        state.write_bool(self.dmc_loop);
+        state.write_u16(self.sample_address);
+        state.write_u16(self.sample_length);
+        state.write_u8(self.sample_buffer);
+        state.write_bool(self.sample_buffer_full);
+        state.write_bool(self.silence);
+        state.write_u16(self.current_address);
+        state.write_u16(self.bytes_remaining);
+        state.write_u8(self.output_level);
+        state.write_u8(self.output_shift_register);
+        state.write_u8(self.output_bits_remaining);
+        self.timer.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.enabled = state.read_bool();
+        self.interrupt_enable = state.read_bool();
+        self.dmc_loop = state.read_bool();
+        self.sample_address = state.read_u16();
+        self.sample_length = state.read_u16();
+        self.sample_buffer = state.read_u8();
+        self.sample_buffer_full = state.read_bool();
+        self.silence = state.read_bool();
+        self.current_address = state.read_u16();
+        self.bytes_remaining = state.read_u16();
+        self.output_level = state.read_u8();
+        self.output_shift_register = state.read_u8();
+        self.output_bits_remaining = state.read_u8();
+        self.timer.load_state(state);
+    }
+}
+
 #[derive(Default)]
 struct Envelope {
     start_flag: bool,
@@ -608,6 +968,25 @@ impl Envelope {
     }
 }
 
+impl SaveState for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.start_flag);
+        state.write_bool(self.loop_flag);
+        state.write_bool(self.use_constant_volume);
+        state.write_u8(self.divider_counter);
+        state.write_u8(self.constant_volume);
+        state.write_u8(self.decay_level);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.start_flag = state.read_bool();
+        self.loop_flag = state.read_bool();
+        self.use_constant_volume = state.read_bool();
+        self.divider_counter = state.read_u8();
+        self.constant_volume = state.read_u8();
+        self.decay_level = state.read_u8();
+    }
+}
+
 #[derive(Default)]
 struct LengthCounter {
     halt: bool,
@@ -626,6 +1005,17 @@ impl LengthCounter {
     }
 }
 
+impl SaveState for LengthCounter {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.halt);
+        state.write_u8(self.counter);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.halt = state.read_bool();
+        self.counter = state.read_u8();
+    }
+}
+
 #[derive(Default)]
 struct Timer {
     period: u16,
@@ -645,15 +1035,70 @@ impl Timer {
     }
 
     fn write_low(&mut self, val: u8) {
-        self.value = (self.value & 0xff00) | val as u16;
+        self.period = (self.period & 0xff00) | val as u16;
     }
 
     fn write_high(&mut self, val: u8) {
-        self.value = (self.value & 0x00ff) | ((val as u16) << 8);
+        self.period = (self.period & 0x00ff) | ((val as u16) << 8);
+    }
+}
+
+impl SaveState for Timer {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.period);
+        state.write_u16(self.value);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.period = state.read_u16();
+        self.value = state.read_u16();
     }
 }
 
+#[derive(Eq, PartialEq)]
 enum FrameMode {
     FourStep,
     FiveStep,
 }
+
+// first order filters applied to the resampled output, the console itself has two high passes and a low pass
+struct Filter {
+    high_pass: bool,
+    alpha: f32,
+    prev_input: f32,
+    prev_output: f32,
+}
+
+impl Filter {
+    fn output_chain(sample_rate: f32) -> [Filter; 3] {
+        [
+            Filter::new(true, 90.0, sample_rate),
+            Filter::new(true, 440.0, sample_rate),
+            Filter::new(false, 14000.0, sample_rate),
+        ]
+    }
+
+    fn new(high_pass: bool, cutoff: f32, sample_rate: f32) -> Filter {
+        let rc = 1.0 / (2.0 * ::std::f32::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate;
+
+        Filter {
+            high_pass: high_pass,
+            alpha: if high_pass { rc / (rc + dt) } else { dt / (rc + dt) },
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    fn apply(&mut self, input: f32) -> f32 {
+        let output = if self.high_pass {
+            self.alpha * (self.prev_output + input - self.prev_input)
+        } else {
+            self.prev_output + self.alpha * (input - self.prev_output)
+        };
+
+        self.prev_input = input;
+        self.prev_output = output;
+
+        output
+    }
+}
@@ -0,0 +1,152 @@
+// roms packed in zip or gzip archives. a file in a zip can be picked with archive.zip#file.nes, otherwise
+// it's the first one with a rom's extension
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+use zip::result::ZipError;
+
+pub const ROM_EXTENSIONS: [&'static str; 6] = ["nes", "fds", "nsf", "nsfe", "unf", "unif"];
+
+const ZIP_HEADER: &'static [u8] = b"PK\x03\x04";
+const GZIP_HEADER: &'static [u8] = b"\x1f\x8b";
+const SEVEN_ZIP_HEADER: &'static [u8] = b"7z\xbc\xaf\x27\x1c";
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(io::Error),
+    Zip(ZipError),
+    Unsupported(&'static str),
+    NoRom,
+    MissingEntry(String)
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArchiveError::Io(ref e) => write!(f, "{}", e),
+            ArchiveError::Zip(ref e) => write!(f, "Invalid zip file. {}", e),
+            ArchiveError::Unsupported(format) => write!(f, "{} archives aren't supported, only zip and gzip", format),
+            ArchiveError::NoRom => write!(f, "Archive has no {} file", extension_list()),
+            ArchiveError::MissingEntry(ref name) => write!(f, "Archive has no file named {}", name)
+        }
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> ArchiveError {
+        ArchiveError::Io(e)
+    }
+}
+
+impl From<ZipError> for ArchiveError {
+    fn from(e: ZipError) -> ArchiveError {
+        match e {
+            ZipError::Io(e) => ArchiveError::Io(e),
+            e => ArchiveError::Zip(e)
+        }
+    }
+}
+
+pub struct RomFile {
+    // where the rom would be if it were unpacked next to the archive, for finding saves and patches
+    pub path: PathBuf,
+    pub data: Vec<u8>
+}
+
+// reads a rom, unpacking it if it's in an archive. anything that isn't an archive is read as it is
+pub fn read_rom<P: AsRef<Path>>(path: P) -> Result<RomFile, ArchiveError> {
+    let (path, entry) = split_path(path.as_ref());
+
+    let mut data = Vec::new();
+    fs::File::open(&path)?.read_to_end(&mut data)?;
+
+    if data.starts_with(ZIP_HEADER) {
+        read_zip(&path, &data, entry)
+    } else if data.starts_with(GZIP_HEADER) {
+        read_gzip(&path, &data)
+    } else if data.starts_with(SEVEN_ZIP_HEADER) {
+        Err(ArchiveError::Unsupported("7z"))
+    } else {
+        Ok(RomFile { path: path, data: data })
+    }
+}
+
+// separates a file in an archive from the archive's path, unless there's a file with the whole name
+pub fn split_path(path: &Path) -> (PathBuf, Option<String>) {
+    if path.exists() {
+        return (path.to_path_buf(), None);
+    }
+
+    let path_string = path.to_string_lossy();
+    match path_string.rfind('#') {
+        Some(i) if i + 1 < path_string.len() => (PathBuf::from(&path_string[.. i]), Some(path_string[i + 1 ..].to_string())),
+        _ => (path.to_path_buf(), None)
+    }
+}
+
+pub fn is_rom_name(name: &str) -> bool {
+    Path::new(name).extension().and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ROM_EXTENSIONS.iter().any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext)))
+}
+
+fn read_zip(path: &Path, data: &[u8], entry: Option<String>) -> Result<RomFile, ArchiveError> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+
+    // by index rather than name, so first means first in the archive
+    let mut index = None;
+    for i in 0 .. archive.len() {
+        let file = archive.by_index(i)?;
+        let wanted = match entry {
+            Some(ref entry) => file.name() == entry || Path::new(file.name()).file_name().map_or(false, |name| name == entry.as_str()),
+            None => file.is_file() && is_rom_name(file.name())
+        };
+        if wanted {
+            index = Some(i);
+            break;
+        }
+    }
+    let index = match (index, entry) {
+        (Some(index), _) => index,
+        (None, Some(entry)) => return Err(ArchiveError::MissingEntry(entry)),
+        (None, None) => return Err(ArchiveError::NoRom)
+    };
+
+    let mut file = archive.by_index(index)?;
+    let mut rom = Vec::new();
+    file.read_to_end(&mut rom)?;
+
+    Ok(RomFile { path: unpacked_path(path, file.name()), data: rom })
+}
+
+// gzip holds a single file, whose name might be in the header
+fn read_gzip(path: &Path, data: &[u8]) -> Result<RomFile, ArchiveError> {
+    let mut decoder = GzDecoder::new(data);
+    let mut rom = Vec::new();
+    decoder.read_to_end(&mut rom)?;
+
+    let name = match decoder.header().and_then(|header| header.filename()) {
+        Some(name) => String::from_utf8_lossy(name).into_owned(),
+        // game.nes.gz holds game.nes
+        None => path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default()
+    };
+
+    Ok(RomFile { path: unpacked_path(path, &name), data: rom })
+}
+
+// files in folders inside the archive are treated as though they were next to it
+fn unpacked_path(archive_path: &Path, name: &str) -> PathBuf {
+    match Path::new(name).file_name() {
+        Some(file_name) => archive_path.with_file_name(file_name),
+        None => archive_path.to_path_buf()
+    }
+}
+
+fn extension_list() -> String {
+    ROM_EXTENSIONS.iter().map(|ext| format!(".{}", ext)).collect::<Vec<_>>().join(", ")
+}
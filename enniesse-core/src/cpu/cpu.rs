@@ -1,6 +1,7 @@
 use super::super::memory;
 use super::super::memory::{Memory, MemoryInterface};
-use super::super::rom::Rom;
+use super::super::mapper::Mapper;
+use super::super::state::{SaveState, StateWriter, StateReader};
 use super::addressing_mode;
 use super::addressing_mode::AddressingMode;
 use super::opcode;
@@ -13,6 +14,9 @@ const BRK_VECTOR: u16 = 0xfffe;
 
 const STACK_START: u16 = 0x0100;
 
+// interrupts disabled, bit 5 always reads back set
+const POWER_ON_STATUS: u8 = 0x24;
+
 pub struct Cpu {
     // accumulator
     pub reg_a: u8,
@@ -35,34 +39,43 @@ pub struct Cpu {
 }
 
 impl Cpu {
-    pub fn new(rom: Box<Rom>) -> Cpu {
+    pub fn new(mapper: Box<Mapper>) -> Cpu {
         Cpu {
             reg_a: 0,
             reg_x: 0,
             reg_y: 0,
-            reg_pc: 0xc000,
-            reg_sp: 0xfd,
-            reg_p: StatusRegister::from(0x24),
+            reg_pc: 0,
+            reg_sp: 0,
+            reg_p: StatusRegister::from(POWER_ON_STATUS),
             cycle: 0,
-            memory_interface: MemoryInterface::new(rom),
+            memory_interface: MemoryInterface::new(mapper),
             current_instruction: 0
         }
     }
     
+    // registers are cleared on power on, then the cpu goes through the normal reset sequence
+    pub fn power_cycle(&mut self) {
+        self.reg_a = 0;
+        self.reg_x = 0;
+        self.reg_y = 0;
+        self.reg_sp = 0;
+        self.reg_p = StatusRegister::from(POWER_ON_STATUS);
+        self.cycle = 0;
+
+        self.reset();
+    }
+
     pub fn reset(&mut self) {
-        // TODO: accurately model reset
-        
+        // reset runs the interrupt sequence with writes suppressed, so S is decremented by 3 without touching the stack
+        self.reg_sp = self.reg_sp.wrapping_sub(3);
+        self.reg_p.interrupt_disable = true;
+
         self.reg_pc = self.load_word(RESET_VECTOR);
+        self.cycle += 7;
     }
     
     pub fn nmi(&mut self) {
-        let pc = self.reg_pc;
-        let flags = self.reg_p.as_u8();
-        
-        self.stack_push_word(pc);
-        self.stack_push_byte(flags);
-        
-        self.reg_pc = self.load_word(NMI_VECTOR);
+        self.interrupt(NMI_VECTOR);
     }
     
     pub fn irq(&mut self) {
@@ -70,15 +83,30 @@ impl Cpu {
             return;
         }
         
+        self.interrupt(BRK_VECTOR);
+    }
+
+    // takes the place of an instruction, so it's only called between them
+    fn interrupt(&mut self, vector: u16) {
         let pc = self.reg_pc;
-        let flags = self.reg_p.as_u8();
+        // unlike brk and php, the break flag is pushed clear
+        let flags = (self.reg_p.as_u8() & !(1 << 4)) | (1 << 5);
         
         self.stack_push_word(pc);
         self.stack_push_byte(flags);
         
-        self.reg_pc = self.load_word(BRK_VECTOR);
+        self.reg_p.interrupt_disable = true;
+        self.reg_pc = self.load_word(vector);
+        self.cycle += 7;
     }
     
+    // calls a subroutine as if by a jsr that would return to return_addr, for running code like nsf
+    // routines that the program itself never calls
+    pub fn call(&mut self, addr: u16, return_addr: u16) {
+        self.stack_push_word(return_addr.wrapping_sub(1));
+        self.reg_pc = addr;
+    }
+
     pub fn step(&mut self) {
         let opcode = self.load_byte_from_pc();
         
@@ -888,6 +916,31 @@ impl Memory for Cpu {
     }
 }
 
+impl SaveState for Cpu {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.reg_a);
+        state.write_u8(self.reg_x);
+        state.write_u8(self.reg_y);
+        state.write_u16(self.reg_pc);
+        state.write_u8(self.reg_sp);
+        state.write_u8(self.reg_p.as_u8());
+        state.write_u16(self.cycle);
+
+        self.memory_interface.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.reg_a = state.read_u8();
+        self.reg_x = state.read_u8();
+        self.reg_y = state.read_u8();
+        self.reg_pc = state.read_u16();
+        self.reg_sp = state.read_u8();
+        self.reg_p = StatusRegister::from(state.read_u8());
+        self.cycle = state.read_u16();
+
+        self.memory_interface.load_state(state);
+    }
+}
+
 impl fmt::Debug for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:04X} {:20} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
@@ -0,0 +1,120 @@
+// famicom disk system images, either with fwnes's 16 byte header or without
+// one. both store each side as 65500 bytes of file blocks, without the gaps or crcs
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use patch;
+use patch::PatchError;
+
+pub const SIDE_SIZE: usize = 65500;
+// the usual name for a dump of the disk system's bios
+pub const BIOS_NAME: &'static str = "disksys.rom";
+
+const FWNES_HEADER: [u8; 4] = *b"FDS\x1a";
+const FWNES_HEADER_SIZE: usize = 16;
+// every side starts with the disk info block, which has this right after the block type
+const DISK_INFO_BLOCK: u8 = 1;
+const DISK_VERIFICATION: &'static [u8] = b"*NINTENDO-HVC*";
+
+#[derive(Debug)]
+pub enum DiskError {
+    Io(io::Error),
+    InvalidSize(usize),
+    InvalidSide(usize),
+    Save(PatchError)
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DiskError::Io(ref e) => write!(f, "{}", e),
+            DiskError::InvalidSize(size) => write!(f, "Disk image is {} bytes, expected a multiple of {}", size, SIDE_SIZE),
+            DiskError::InvalidSide(side) => write!(f, "Side {} of the disk image has no disk info block", side + 1),
+            DiskError::Save(ref e) => write!(f, "Invalid disk save: {}", e)
+        }
+    }
+}
+
+impl From<io::Error> for DiskError {
+    fn from(e: io::Error) -> DiskError {
+        DiskError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DiskImage {
+    pub sides: Vec<Vec<u8>>,
+    // the sides as they were loaded, which changes are saved against
+    original: Vec<u8>,
+}
+
+impl DiskImage {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<DiskImage, DiskError> {
+        let mut file = File::open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        DiskImage::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<DiskImage, DiskError> {
+        // the header's side count isn't always right, so the size is what counts
+        let data = if data.starts_with(&FWNES_HEADER) && data.len() >= FWNES_HEADER_SIZE {
+            &data[FWNES_HEADER_SIZE ..]
+        } else {
+            data
+        };
+
+        if data.is_empty() || data.len() % SIDE_SIZE != 0 {
+            return Err(DiskError::InvalidSize(data.len()));
+        }
+
+        let sides: Vec<Vec<u8>> = data.chunks(SIDE_SIZE).map(|side| side.to_vec()).collect();
+        for (i, side) in sides.iter().enumerate() {
+            if side[0] != DISK_INFO_BLOCK || !side[1 ..].starts_with(DISK_VERIFICATION) {
+                return Err(DiskError::InvalidSide(i));
+            }
+        }
+
+        Ok(DiskImage {
+            sides: sides,
+            original: data.to_vec(),
+        })
+    }
+
+    // whether a file looks like a disk image rather than a cartridge
+    pub fn is_disk_image(data: &[u8]) -> bool {
+        data.starts_with(&FWNES_HEADER)
+            || data.len() > DISK_VERIFICATION.len() && data[0] == DISK_INFO_BLOCK && data[1 ..].starts_with(DISK_VERIFICATION)
+    }
+
+    // all the sides one after another, without a header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.sides.concat()
+    }
+
+    // applies changes saved by changes()
+    pub fn apply_changes(&mut self, changes: &[u8]) -> Result<(), DiskError> {
+        let mut data = self.to_bytes();
+        patch::apply_ips(&mut data, changes).map_err(DiskError::Save)?;
+        data.resize(self.sides.len() * SIDE_SIZE, 0);
+
+        self.sides = data.chunks(SIDE_SIZE).map(|side| side.to_vec()).collect();
+        Ok(())
+    }
+
+    // an ips patch of everything written to the disk since it was loaded, or None if nothing was
+    pub fn changes(&self) -> Option<Vec<u8>> {
+        let data = self.to_bytes();
+        if data == self.original {
+            return None;
+        }
+
+        // a disk image is well under the 16mb an ips patch can cover
+        patch::create_ips(&self.original, &data).ok()
+    }
+}
@@ -1,4 +1,5 @@
 use memory::Memory;
+use state::{SaveState, StateWriter, StateReader};
 
 const CONTROLLER1_ADDR: u16 = 0x4016;
 const CONTROLLER2_ADDR: u16 = 0x4017;
@@ -27,6 +28,23 @@ impl Input {
         }
     }
     
+    // raw port state, one bit per button in the order they are read out (A in bit 0, Right in bit 7)
+    pub fn port_state(&self, port: usize) -> u8 {
+        match port {
+            0 => self.controller1.buttons(),
+            1 => self.controller2.buttons(),
+            _ => 0
+        }
+    }
+
+    pub fn set_port_state(&mut self, port: usize, buttons: u8) {
+        match port {
+            0 => self.controller1.set_buttons(buttons),
+            1 => self.controller2.set_buttons(buttons),
+            _ => {}
+        }
+    }
+
     pub fn handle_input(&mut self, button: Button, pressed: bool) {
         match button {
             Button::A       => self.controller1.a      = pressed,
@@ -44,8 +62,9 @@ impl Input {
 impl Memory for Input {
     fn load_byte(&mut self, addr: u16) -> u8 {
         match addr {
-            CONTROLLER1_ADDR => 0x40 | self.controller1.get_button_state(), 
-            CONTROLLER2_ADDR => 0x40 | self.controller2.get_button_state(),
+            // the upper bits aren't driven, the memory interface fills them from open bus
+            CONTROLLER1_ADDR => self.controller1.get_button_state(),
+            CONTROLLER2_ADDR => self.controller2.get_button_state(),
             _ => 0
         }
     }
@@ -58,6 +77,17 @@ impl Memory for Input {
     }
 }
 
+impl SaveState for Input {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.controller1.save_state(state);
+        self.controller2.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.controller1.load_state(state);
+        self.controller2.load_state(state);
+    }
+}
+
 #[derive(Default)]
 struct ControllerState {
     a: bool,
@@ -75,6 +105,28 @@ struct ControllerState {
 }
 
 impl ControllerState {
+    fn buttons(&self) -> u8 {
+        (self.a as u8)             |
+        (self.b as u8)        << 1 |
+        (self.select as u8)   << 2 |
+        (self.start as u8)    << 3 |
+        (self.up as u8)       << 4 |
+        (self.down as u8)     << 5 |
+        (self.left as u8)     << 6 |
+        (self.right as u8)    << 7
+    }
+
+    fn set_buttons(&mut self, buttons: u8) {
+        self.a      = buttons & (1 << 0) != 0;
+        self.b      = buttons & (1 << 1) != 0;
+        self.select = buttons & (1 << 2) != 0;
+        self.start  = buttons & (1 << 3) != 0;
+        self.up     = buttons & (1 << 4) != 0;
+        self.down   = buttons & (1 << 5) != 0;
+        self.left   = buttons & (1 << 6) != 0;
+        self.right  = buttons & (1 << 7) != 0;
+    }
+
     fn get_button_state(&mut self) -> u8 {        
         let result = match self.next_button_read {
             0 => self.a as u8,
@@ -102,4 +154,18 @@ impl ControllerState {
             self.read_reset = false;
         }
     }
+}
+
+impl SaveState for ControllerState {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.buttons());
+        state.write_u8(self.next_button_read);
+        state.write_bool(self.read_reset);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        let buttons = state.read_u8();
+        self.set_buttons(buttons);
+        self.next_button_read = state.read_u8();
+        self.read_reset = state.read_bool();
+    }
 }
\ No newline at end of file
@@ -1,8 +1,21 @@
+extern crate flate2;
+extern crate zip;
+
 pub mod cpu;
 pub mod apu;
 pub mod ppu;
 pub mod nes;
 pub mod rom;
+pub mod archive;
+pub mod unif;
+pub mod romdb;
+pub mod hash;
+pub mod disk;
+pub mod patch;
+pub mod nsf;
 pub mod memory;
 pub mod mapper;
-pub mod input;
\ No newline at end of file
+pub mod input;
+pub mod state;
+pub mod movie;
+pub mod rewind;
\ No newline at end of file
@@ -0,0 +1,567 @@
+use std::iter;
+
+use rom::Rom;
+use disk::{DiskImage, SIDE_SIZE};
+use mapper::{Mapper, MapperError, Mirroring};
+use mapper::fds_audio::FdsAudio;
+use state::{SaveState, StateWriter, StateReader};
+
+const RAM_SIZE: usize = 32 * 1024;
+const CHR_RAM_SIZE: usize = 8 * 1024;
+const BIOS_START: u16 = 0xe000;
+
+// the drive sees a side as one long stream, with gaps before and between the blocks and a crc
+// after each one. disk images leave all of that out, so it's put back while the disk is in the drive
+const LEADING_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const BLOCK_START: u8 = 0x80;
+// room for a full side's worth of blocks along with their gaps
+const GAPPED_SIDE_SIZE: usize = 75000;
+
+// cpu cycles for the head to get back to the start of the disk, and to read or write a byte
+const HEAD_RETURN_DELAY: u32 = 50000;
+const BYTE_DELAY: u32 = 150;
+
+// mapper 20, the famicom disk system's ram adapter with the drive plugged in
+pub struct Fds {
+    // the bios
+    rom: Box<Rom>,
+    ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+
+    disk: DiskImage,
+    sides: Vec<Vec<u8>>,
+    // sides that have been written to since the disk was loaded
+    modified: Vec<bool>,
+    side: usize,
+    inserted: bool,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+    disk_irq: bool,
+
+    disk_registers_enabled: bool,
+    sound_registers_enabled: bool,
+
+    // $4025
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    horizontal_mirroring: bool,
+    crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+
+    write_data: u8,
+    read_data: u8,
+    transfer_complete: bool,
+    external_port: u8,
+
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    crc: u16,
+    previous_crc_control: bool,
+
+    audio: FdsAudio,
+}
+
+impl Fds {
+    pub fn new(mut rom: Box<Rom>) -> Result<Fds, MapperError> {
+        let disk = match rom.disk.take() {
+            Some(disk) => disk,
+            None => return Err(MapperError::NoDisk)
+        };
+        let sides: Vec<Vec<u8>> = disk.sides.iter().map(|side| add_gaps(side)).collect();
+        let side_count = sides.len();
+
+        Ok(Fds {
+            rom: rom,
+            ram: vec![0; RAM_SIZE],
+            chr_ram: vec![0; CHR_RAM_SIZE],
+
+            disk: disk,
+            sides: sides,
+            modified: vec![false; side_count],
+            side: 0,
+            inserted: true,
+
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+            disk_irq: false,
+
+            disk_registers_enabled: false,
+            sound_registers_enabled: false,
+
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            horizontal_mirroring: false,
+            crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+
+            write_data: 0,
+            read_data: 0,
+            transfer_complete: false,
+            external_port: 0,
+
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            crc: 0,
+            previous_crc_control: false,
+
+            audio: FdsAudio::new(),
+        })
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    // moves the disk along under the head, reading or writing a byte every BYTE_DELAY cycles
+    fn clock_drive(&mut self) {
+        if !self.inserted || !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+
+        if self.end_of_head {
+            self.delay = HEAD_RETURN_DELAY;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+
+        let side_len = self.sides[self.side].len();
+        if self.read_mode {
+            let val = self.sides[self.side][self.position];
+            let mut irq = self.disk_irq_enabled;
+
+            if !self.disk_ready {
+                self.gap_ended = false;
+            } else if val != 0 && !self.gap_ended {
+                // the start mark at the end of a gap doesn't raise an irq
+                self.gap_ended = true;
+                irq = false;
+            }
+
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = val;
+                if irq {
+                    self.disk_irq = true;
+                }
+            }
+        } else {
+            let mut val = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                val = self.write_data;
+                if self.disk_irq_enabled {
+                    self.disk_irq = true;
+                }
+            }
+
+            if !self.disk_ready {
+                val = 0;
+                self.crc = 0;
+            }
+
+            if !self.crc_control {
+                self.crc = update_crc(self.crc, val);
+            } else {
+                if !self.previous_crc_control {
+                    self.crc = update_crc(self.crc, 0);
+                    self.crc = update_crc(self.crc, 0);
+                }
+                val = self.crc as u8;
+                self.crc >>= 8;
+            }
+
+            self.sides[self.side][self.position] = val;
+            self.modified[self.side] = true;
+            self.gap_ended = false;
+        }
+
+        self.previous_crc_control = self.crc_control;
+
+        self.position += 1;
+        if self.position >= side_len {
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_DELAY;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn load_byte_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4030 => {
+                let val = self.timer_irq as u8 | (self.transfer_complete as u8) << 1;
+                self.transfer_complete = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+                val
+            },
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                self.read_data
+            },
+            0x4032 => {
+                // disk missing, not ready and write protected
+                let missing = !self.inserted as u8;
+                let not_ready = (!self.inserted || !self.scanning) as u8;
+                missing | not_ready << 1 | missing << 2
+            },
+            // the battery's always fine, and bit 7 of the external port reads back as set
+            0x4033 => self.external_port & 0x7f | 0x80,
+            0x4040 ... 0x4092 => self.audio.load_byte(addr),
+            0x6000 ... 0xdfff => self.ram[addr as usize - 0x6000],
+            BIOS_START ... 0xffff => self.rom.prg_rom[(addr - BIOS_START) as usize % self.rom.prg_rom.len()],
+            _ => 0
+        }
+    }
+
+    fn store_byte_prg(&mut self, addr: u16, val: u8) {
+        if addr == 0x4023 {
+            self.disk_registers_enabled = val & 1 != 0;
+            self.sound_registers_enabled = val & 2 != 0;
+            if !self.disk_registers_enabled {
+                self.irq_enabled = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+            }
+            return;
+        }
+
+        match addr {
+            0x4020 ... 0x4026 if !self.disk_registers_enabled => {},
+            0x4020 => self.irq_reload = (self.irq_reload & 0xff00) | val as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00ff) | (val as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = val & 1 != 0;
+                self.irq_enabled = val & 2 != 0;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            },
+            0x4024 => {
+                self.write_data = val;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            },
+            0x4025 => {
+                self.motor_on = val & 0x01 != 0;
+                self.reset_transfer = val & 0x02 != 0;
+                self.read_mode = val & 0x04 != 0;
+                self.horizontal_mirroring = val & 0x08 != 0;
+                self.crc_control = val & 0x10 != 0;
+                self.disk_ready = val & 0x40 != 0;
+                self.disk_irq_enabled = val & 0x80 != 0;
+                self.disk_irq = false;
+            },
+            0x4026 => self.external_port = val,
+            0x4040 ... 0x408a => {
+                if self.sound_registers_enabled {
+                    self.audio.store_byte(addr, val);
+                }
+            },
+            0x6000 ... 0xdfff => self.ram[addr as usize - 0x6000] = val,
+            _ => {}
+        }
+    }
+
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        match addr {
+            0x4030 ... 0x4033 => self.disk_registers_enabled,
+            0x4040 ... 0x407f | 0x4090 | 0x4092 => self.sound_registers_enabled,
+            _ => addr >= 0x6000
+        }
+    }
+
+    fn load_byte_chr(&mut self, addr: u16) -> u8 {
+        self.chr_ram[addr as usize & (CHR_RAM_SIZE - 1)]
+    }
+    fn store_byte_chr(&mut self, addr: u16, val: u8) {
+        self.chr_ram[addr as usize & (CHR_RAM_SIZE - 1)] = val;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.horizontal_mirroring { Mirroring::Horizontal } else { Mirroring::Vertical }
+    }
+
+    fn clock_cpu(&mut self) {
+        self.clock_timer();
+        self.clock_drive();
+        self.audio.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn reset(&mut self) {
+        self.irq_enabled = false;
+        self.timer_irq = false;
+        self.disk_irq = false;
+        self.motor_on = false;
+        self.end_of_head = true;
+        self.scanning = false;
+    }
+
+    fn disk_side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    fn disk_side(&self) -> (usize, bool) {
+        (self.side, self.inserted)
+    }
+
+    fn set_disk_side(&mut self, side: usize, inserted: bool) {
+        self.side = side % self.sides.len();
+        self.inserted = inserted;
+    }
+
+    fn disk_changes(&self) -> Option<Vec<u8>> {
+        let mut disk = self.disk.clone();
+        for (i, side) in self.sides.iter().enumerate() {
+            if self.modified[i] {
+                disk.sides[i] = remove_gaps(side);
+            }
+        }
+
+        disk.changes()
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_bytes(&self.chr_ram);
+
+        state.write_u8(self.sides.len() as u8);
+        for (i, side) in self.sides.iter().enumerate() {
+            state.write_bytes(side);
+            state.write_bool(self.modified[i]);
+        }
+        state.write_u8(self.side as u8);
+        state.write_bool(self.inserted);
+
+        state.write_u16(self.irq_reload);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_repeat);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.timer_irq);
+        state.write_bool(self.disk_irq);
+
+        state.write_bool(self.disk_registers_enabled);
+        state.write_bool(self.sound_registers_enabled);
+
+        state.write_bool(self.motor_on);
+        state.write_bool(self.reset_transfer);
+        state.write_bool(self.read_mode);
+        state.write_bool(self.horizontal_mirroring);
+        state.write_bool(self.crc_control);
+        state.write_bool(self.disk_ready);
+        state.write_bool(self.disk_irq_enabled);
+
+        state.write_u8(self.write_data);
+        state.write_u8(self.read_data);
+        state.write_bool(self.transfer_complete);
+        state.write_u8(self.external_port);
+
+        state.write_u32(self.position as u32);
+        state.write_u32(self.delay);
+        state.write_bool(self.end_of_head);
+        state.write_bool(self.scanning);
+        state.write_bool(self.gap_ended);
+        state.write_u16(self.crc);
+        state.write_bool(self.previous_crc_control);
+
+        self.audio.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.ram);
+        state.read_bytes_into(&mut self.chr_ram);
+
+        // a state from a different disk leaves the sides as they are
+        let side_count = state.read_u8() as usize;
+        for i in 0 .. side_count {
+            let side = state.read_bytes();
+            let modified = state.read_bool();
+            if i < self.sides.len() && !side.is_empty() {
+                self.sides[i] = side;
+                self.modified[i] = modified;
+            }
+        }
+        self.side = state.read_u8() as usize % self.sides.len();
+        self.inserted = state.read_bool();
+
+        self.irq_reload = state.read_u16();
+        self.irq_counter = state.read_u16();
+        self.irq_repeat = state.read_bool();
+        self.irq_enabled = state.read_bool();
+        self.timer_irq = state.read_bool();
+        self.disk_irq = state.read_bool();
+
+        self.disk_registers_enabled = state.read_bool();
+        self.sound_registers_enabled = state.read_bool();
+
+        self.motor_on = state.read_bool();
+        self.reset_transfer = state.read_bool();
+        self.read_mode = state.read_bool();
+        self.horizontal_mirroring = state.read_bool();
+        self.crc_control = state.read_bool();
+        self.disk_ready = state.read_bool();
+        self.disk_irq_enabled = state.read_bool();
+
+        self.write_data = state.read_u8();
+        self.read_data = state.read_u8();
+        self.transfer_complete = state.read_bool();
+        self.external_port = state.read_u8();
+
+        self.position = (state.read_u32() as usize).min(self.sides[self.side].len() - 1);
+        self.delay = state.read_u32();
+        self.end_of_head = state.read_bool();
+        self.scanning = state.read_bool();
+        self.gap_ended = state.read_bool();
+        self.crc = state.read_u16();
+        self.previous_crc_control = state.read_bool();
+
+        self.audio.load_state(state);
+    }
+}
+
+// how long the block starting at the front of data is, or None if it isn't a block. file data blocks
+// take their size from the file header block before them
+fn block_length(data: &[u8], file_size: &mut usize) -> Option<usize> {
+    match data.first() {
+        Some(&1) => Some(56),
+        Some(&2) => Some(2),
+        Some(&3) if data.len() >= 16 => {
+            *file_size = data[13] as usize | (data[14] as usize) << 8;
+            Some(16)
+        },
+        Some(&4) => Some(1 + *file_size),
+        _ => None
+    }
+}
+
+// the drive's crc-16, which covers a block's start mark and comes out as 0 when run over the crc too
+fn update_crc(mut crc: u16, val: u8) -> u16 {
+    for bit in 0 .. 8 {
+        let carry = crc & 1 != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= 0x8408;
+        }
+        if val & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+
+    crc
+}
+
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut gapped = vec![0; LEADING_GAP];
+    let mut file_size = 0;
+    let mut pos = 0;
+
+    while let Some(length) = block_length(&side[pos ..], &mut file_size) {
+        let block = match side.get(pos .. pos + length) {
+            Some(block) => block,
+            None => break
+        };
+
+        let crc = iter::once(&BLOCK_START).chain(block).chain(&[0, 0]).fold(0, |crc, &val| update_crc(crc, val));
+
+        gapped.push(BLOCK_START);
+        gapped.extend_from_slice(block);
+        gapped.extend_from_slice(&[crc as u8, (crc >> 8) as u8]);
+        gapped.extend(iter::repeat(0).take(BLOCK_GAP));
+
+        pos += length;
+    }
+
+    if gapped.len() < GAPPED_SIDE_SIZE {
+        gapped.resize(GAPPED_SIDE_SIZE, 0);
+    }
+    gapped
+}
+
+// turns a side back into the image format, for saving what was written to it
+fn remove_gaps(gapped: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut file_size = 0;
+    let mut pos = 0;
+
+    loop {
+        while pos < gapped.len() && gapped[pos] == 0 {
+            pos += 1;
+        }
+        if gapped.get(pos) != Some(&BLOCK_START) {
+            break;
+        }
+        pos += 1;
+
+        let length = match block_length(&gapped[pos ..], &mut file_size) {
+            Some(length) => length,
+            None => break
+        };
+        let block = match gapped.get(pos .. pos + length) {
+            Some(block) => block,
+            None => break
+        };
+
+        side.extend_from_slice(block);
+        // skip the crc
+        pos += length + 2;
+    }
+
+    side.resize(SIDE_SIZE, 0);
+    side
+}
@@ -0,0 +1,329 @@
+// the disk system's sound: one 64 step wavetable channel with a volume envelope, whose
+// pitch is bent by a second table stepping through a modulation counter
+
+use state::{SaveState, StateWriter, StateReader};
+
+const WAVE_SIZE: usize = 64;
+// 32 entries, each of which counts for 2 steps
+const MOD_TABLE_SIZE: usize = 64;
+// the volume gain can be set up to 63, but the output stops getting louder at 32
+const MAX_GAIN: u8 = 32;
+const DEFAULT_ENVELOPE_SPEED: u8 = 0xe8;
+// the master volume is 2/2, 2/3, 2/4 or 2/5
+const MASTER_VOLUMES: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+// at full volume it's about 2.4 times as loud as an apu pulse
+const FDS_VOLUME: f32 = 2.4 * 0.1494;
+
+// what each value in the modulation table does to the counter. 4 resets it
+const MOD_ADJUSTMENTS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MOD_RESET: u8 = 4;
+
+struct Envelope {
+    // set to use the speed bits as the gain directly
+    disabled: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    counter: u32,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            disabled: true,
+            increase: false,
+            speed: 0,
+            gain: 0,
+            counter: 0,
+        }
+    }
+
+    fn write(&mut self, val: u8, master_speed: u8) {
+        self.disabled = val & 0x80 != 0;
+        self.increase = val & 0x40 != 0;
+        self.speed = val & 0x3f;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.reset_counter(master_speed);
+    }
+
+    fn reset_counter(&mut self, master_speed: u8) {
+        self.counter = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled {
+            return;
+        }
+
+        if self.counter > 0 {
+            self.counter -= 1;
+            return;
+        }
+
+        self.reset_counter(master_speed);
+        if self.increase && self.gain < MAX_GAIN {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+impl SaveState for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.disabled);
+        state.write_bool(self.increase);
+        state.write_u8(self.speed);
+        state.write_u8(self.gain);
+        state.write_u32(self.counter);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.disabled = state.read_bool();
+        self.increase = state.read_bool();
+        self.speed = state.read_u8();
+        self.gain = state.read_u8();
+        self.counter = state.read_u32();
+    }
+}
+
+pub struct FdsAudio {
+    wave: [u8; WAVE_SIZE],
+    // the cpu can only write the wavetable while this is set, and the output holds while it is
+    wave_write: bool,
+    wave_frequency: u16,
+    wave_halted: bool,
+    wave_accumulator: u32,
+    wave_position: usize,
+    // the sample the channel is outputting, only updated while the wave isn't being written
+    wave_output: u8,
+    volume: Envelope,
+    envelopes_halted: bool,
+    envelope_speed: u8,
+    master_volume: usize,
+
+    mod_table: [u8; MOD_TABLE_SIZE],
+    mod_frequency: u16,
+    mod_halted: bool,
+    mod_accumulator: u32,
+    mod_position: usize,
+    // 7 bit signed
+    mod_counter: i8,
+    sweep: Envelope,
+}
+
+impl FdsAudio {
+    pub fn new() -> FdsAudio {
+        FdsAudio {
+            wave: [0; WAVE_SIZE],
+            wave_write: false,
+            wave_frequency: 0,
+            wave_halted: true,
+            wave_accumulator: 0,
+            wave_position: 0,
+            wave_output: 0,
+            volume: Envelope::new(),
+            envelopes_halted: false,
+            envelope_speed: DEFAULT_ENVELOPE_SPEED,
+            master_volume: 0,
+
+            mod_table: [0; MOD_TABLE_SIZE],
+            mod_frequency: 0,
+            mod_halted: true,
+            mod_accumulator: 0,
+            mod_position: 0,
+            mod_counter: 0,
+            sweep: Envelope::new(),
+        }
+    }
+
+    pub fn load_byte(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4040 ... 0x407f => {
+                let val = if self.wave_write { self.wave[addr as usize & 0x3f] } else { self.wave_output };
+                val | 0x40
+            },
+            0x4090 => self.volume.gain | 0x40,
+            0x4092 => self.sweep.gain | 0x40,
+            _ => 0x40
+        }
+    }
+
+    pub fn store_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4040 ... 0x407f => {
+                if self.wave_write {
+                    self.wave[addr as usize & 0x3f] = val & 0x3f;
+                }
+            },
+            0x4080 => self.volume.write(val, self.envelope_speed),
+            0x4082 => self.wave_frequency = (self.wave_frequency & 0xf00) | val as u16,
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0xff) | ((val & 0x0f) as u16) << 8;
+                self.wave_halted = val & 0x80 != 0;
+                self.envelopes_halted = val & 0x40 != 0;
+
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_counter(self.envelope_speed);
+                    self.sweep.reset_counter(self.envelope_speed);
+                }
+            },
+            0x4084 => self.sweep.write(val, self.envelope_speed),
+            0x4085 => {
+                // sign extend the 7 bits
+                self.mod_counter = ((val << 1) as i8) >> 1;
+            },
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0xf00) | val as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0xff) | ((val & 0x0f) as u16) << 8;
+                self.mod_halted = val & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            },
+            0x4088 => {
+                // the table can only be written while modulation is halted, each write filling the 2 entries of
+                // the pair the position is in. halting can leave the position odd, and it stays odd
+                if self.mod_halted {
+                    let pair = self.mod_position & !1;
+                    self.mod_table[pair] = val & 7;
+                    self.mod_table[pair + 1] = val & 7;
+                    self.mod_position = (self.mod_position + 2) % MOD_TABLE_SIZE;
+                }
+            },
+            0x4089 => {
+                self.wave_write = val & 0x80 != 0;
+                self.master_volume = (val & 3) as usize;
+            },
+            0x408a => self.envelope_speed = val,
+            _ => {}
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.envelopes_halted && !self.wave_halted && self.envelope_speed != 0 {
+            self.volume.clock(self.envelope_speed);
+            self.sweep.clock(self.envelope_speed);
+        }
+
+        self.clock_modulator();
+
+        if !self.wave_halted && !self.wave_write {
+            let frequency = self.modulated_frequency();
+            self.wave_accumulator += frequency;
+
+            if self.wave_accumulator > 0xffff {
+                self.wave_accumulator &= 0xffff;
+                self.wave_position = (self.wave_position + 1) % WAVE_SIZE;
+            }
+        }
+
+        if !self.wave_write {
+            self.wave_output = self.wave[self.wave_position];
+        }
+    }
+
+    fn clock_modulator(&mut self) {
+        if self.mod_halted || self.mod_frequency == 0 {
+            return;
+        }
+
+        self.mod_accumulator += self.mod_frequency as u32;
+        if self.mod_accumulator > 0xffff {
+            self.mod_accumulator &= 0xffff;
+
+            let step = self.mod_table[self.mod_position];
+            self.mod_counter = if step == MOD_RESET {
+                0
+            } else {
+                // wraps around within 7 bits
+                let counter = self.mod_counter as i16 + MOD_ADJUSTMENTS[step as usize] as i16;
+                (((counter as u8) << 1) as i8) >> 1
+            };
+            self.mod_position = (self.mod_position + 1) % MOD_TABLE_SIZE;
+        }
+    }
+
+    // the wave frequency bent by the modulator, worked out the same odd way as the hardware
+    fn modulated_frequency(&self) -> u32 {
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.sweep.gain as i32;
+        let remainder = temp & 0x0f;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        temp *= self.wave_frequency as i32;
+        let remainder = temp & 0x3f;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+
+        (self.wave_frequency as i32 + temp).max(0) as u32
+    }
+
+    pub fn output(&self) -> f32 {
+        let gain = self.volume.gain.min(MAX_GAIN) as f32;
+        let level = self.wave_output as f32 * gain / (63.0 * MAX_GAIN as f32);
+
+        level * MASTER_VOLUMES[self.master_volume] * FDS_VOLUME
+    }
+}
+
+impl SaveState for FdsAudio {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.wave);
+        state.write_bool(self.wave_write);
+        state.write_u16(self.wave_frequency);
+        state.write_bool(self.wave_halted);
+        state.write_u32(self.wave_accumulator);
+        state.write_u8(self.wave_position as u8);
+        state.write_u8(self.wave_output);
+        self.volume.save_state(state);
+        state.write_bool(self.envelopes_halted);
+        state.write_u8(self.envelope_speed);
+        state.write_u8(self.master_volume as u8);
+
+        state.write_bytes(&self.mod_table);
+        state.write_u16(self.mod_frequency);
+        state.write_bool(self.mod_halted);
+        state.write_u32(self.mod_accumulator);
+        state.write_u8(self.mod_position as u8);
+        state.write_u8(self.mod_counter as u8);
+        self.sweep.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.wave);
+        self.wave_write = state.read_bool();
+        self.wave_frequency = state.read_u16();
+        self.wave_halted = state.read_bool();
+        self.wave_accumulator = state.read_u32();
+        self.wave_position = state.read_u8() as usize;
+        self.wave_output = state.read_u8();
+        self.volume.load_state(state);
+        self.envelopes_halted = state.read_bool();
+        self.envelope_speed = state.read_u8();
+        self.master_volume = state.read_u8() as usize;
+
+        state.read_bytes_into(&mut self.mod_table);
+        self.mod_frequency = state.read_u16();
+        self.mod_halted = state.read_bool();
+        self.mod_accumulator = state.read_u32();
+        self.mod_position = state.read_u8() as usize;
+        self.mod_counter = state.read_u8() as i8;
+        self.sweep.load_state(state);
+    }
+}
@@ -0,0 +1,432 @@
+use rom::Rom;
+use mapper::{Mapper, Mirroring};
+use state::{SaveState, StateWriter, StateReader};
+
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const CHR_RAM_SIZE: usize = 8 * 1024;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+// tones and noise tick every 16 cpu cycles, the envelope twice as often since it has 32 steps
+const TONE_DIVIDER: u8 = 16;
+const ENVELOPE_DIVIDER: u8 = 8;
+const ENVELOPE_STEPS: u8 = 32;
+const TONE_CHANNELS: usize = 3;
+// one channel at full volume is about as loud as an apu pulse
+const SUNSOFT_5B_VOLUME: f32 = 0.1494;
+
+// mapper 69. the fme-7 and 5a are the same mapper, the 5b adds the audio
+pub struct Fme7 {
+    rom: Box<Rom>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+
+    command: u8,
+    chr_banks: [u8; 8],
+    // $6000-$7fff can be a rom bank or, when bit 6 is set, prg ram
+    prg_6000: u8,
+    prg_banks: [u8; 3],
+    mirroring: u8,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq: bool,
+
+    audio: Sunsoft5b,
+}
+
+impl Fme7 {
+    pub fn new(rom: Box<Rom>) -> Fme7 {
+        let chr_ram = if rom.chr_rom.is_empty() { vec![0; CHR_RAM_SIZE] } else { Vec::new() };
+
+        Fme7 {
+            rom: rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr_ram: chr_ram,
+
+            command: 0,
+            chr_banks: [0; 8],
+            prg_6000: 0,
+            prg_banks: [0; 3],
+            mirroring: 0,
+
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq: false,
+
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    fn load_prg(&self, bank: usize, addr: u16) -> u8 {
+        let offset = bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1));
+        self.rom.prg_rom[offset % self.rom.prg_rom.len()]
+    }
+
+    fn prg_ram_selected(&self) -> bool {
+        self.prg_6000 & 0x40 != 0
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_6000 & 0xc0 == 0xc0
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_banks[(addr as usize >> 10) & 7] as usize * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn write_parameter(&mut self, val: u8) {
+        match self.command {
+            0 ... 7 => self.chr_banks[self.command as usize] = val,
+            8 => self.prg_6000 = val,
+            9 ... 0xb => self.prg_banks[self.command as usize - 9] = val & 0x3f,
+            0xc => self.mirroring = val & 3,
+            0xd => {
+                self.irq_enabled = val & 1 != 0;
+                self.irq_counter_enabled = val & 0x80 != 0;
+                self.irq = false;
+            },
+            0xe => self.irq_counter = (self.irq_counter & 0xff00) | val as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00ff) | (val as u16) << 8
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn load_byte_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000 ... 0x7fff => {
+                if self.prg_ram_selected() {
+                    self.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)]
+                } else {
+                    self.load_prg((self.prg_6000 & 0x3f) as usize, addr)
+                }
+            },
+            0x8000 ... 0xdfff => {
+                let bank = self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] as usize;
+                self.load_prg(bank, addr)
+            },
+            0xe000 ... 0xffff => {
+                let last = self.rom.prg_rom.len() / PRG_BANK_SIZE - 1;
+                self.load_prg(last, addr)
+            },
+            _ => 0
+        }
+    }
+
+    fn store_byte_prg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x6000 ... 0x7fff => {
+                if self.prg_ram_enabled() {
+                    self.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)] = val;
+                }
+            },
+            0x8000 ... 0x9fff => self.command = val & 0x0f,
+            0xa000 ... 0xbfff => self.write_parameter(val),
+            0xc000 ... 0xdfff => self.audio.select(val),
+            0xe000 ... 0xffff => self.audio.write(val),
+            _ => {}
+        }
+    }
+
+    // selecting prg ram without enabling it leaves $6000-$7fff open bus
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000 || addr >= 0x6000 && (!self.prg_ram_selected() || self.prg_ram_enabled())
+    }
+
+    fn load_byte_chr(&mut self, addr: u16) -> u8 {
+        let offset = self.chr_offset(addr);
+
+        if self.chr_ram.is_empty() {
+            self.rom.chr_rom[offset % self.rom.chr_rom.len()]
+        } else {
+            self.chr_ram[offset % CHR_RAM_SIZE]
+        }
+    }
+    fn store_byte_chr(&mut self, addr: u16, val: u8) {
+        if !self.chr_ram.is_empty() {
+            let offset = self.chr_offset(addr);
+            self.chr_ram[offset % CHR_RAM_SIZE] = val;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xffff && self.irq_enabled {
+                self.irq = true;
+            }
+        }
+
+        self.audio.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_bytes(&self.chr_ram);
+
+        state.write_u8(self.command);
+        state.write_bytes(&self.chr_banks);
+        state.write_u8(self.prg_6000);
+        state.write_bytes(&self.prg_banks);
+        state.write_u8(self.mirroring);
+
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_counter_enabled);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq);
+
+        self.audio.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.prg_ram);
+        state.read_bytes_into(&mut self.chr_ram);
+
+        self.command = state.read_u8();
+        state.read_bytes_into(&mut self.chr_banks);
+        self.prg_6000 = state.read_u8();
+        state.read_bytes_into(&mut self.prg_banks);
+        self.mirroring = state.read_u8();
+
+        self.irq_enabled = state.read_bool();
+        self.irq_counter_enabled = state.read_bool();
+        self.irq_counter = state.read_u16();
+        self.irq = state.read_bool();
+
+        self.audio.load_state(state);
+    }
+}
+
+// an ay-3-8910 with a finer volume curve and envelope: 3 square wave tones, which can each
+// have noise mixed in and their volume driven by the envelope
+struct Sunsoft5b {
+    register: u8,
+    registers: [u8; 16],
+
+    divider: u8,
+    tone_counters: [u16; TONE_CHANNELS],
+    tone_outputs: [bool; TONE_CHANNELS],
+    noise_counter: u8,
+    // 17 bit lfsr
+    noise: u32,
+
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+
+    // the dac is logarithmic, 1.5db a step
+    volume_table: [f32; ENVELOPE_STEPS as usize],
+}
+
+impl Sunsoft5b {
+    fn new() -> Sunsoft5b {
+        let mut volume_table = [0.0; ENVELOPE_STEPS as usize];
+        for (level, volume) in volume_table.iter_mut().enumerate().skip(1) {
+            *volume = 10f32.powf((level as f32 - 31.0) * 1.5 / 20.0);
+        }
+
+        Sunsoft5b {
+            register: 0,
+            registers: [0; 16],
+
+            divider: 0,
+            tone_counters: [0; TONE_CHANNELS],
+            tone_outputs: [false; TONE_CHANNELS],
+            noise_counter: 0,
+            noise: 1,
+
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+
+            volume_table: volume_table,
+        }
+    }
+
+    fn select(&mut self, val: u8) {
+        // the upper bits have to be 0, or the chip ignores the following writes
+        self.register = if val & 0xf0 == 0 { val } else { 0xff };
+    }
+
+    fn write(&mut self, val: u8) {
+        if self.register as usize >= self.registers.len() {
+            return;
+        }
+
+        self.registers[self.register as usize] = val;
+
+        // writing the shape restarts the envelope
+        if self.register == 0x0d {
+            self.envelope_step = 0;
+            self.envelope_counter = 0;
+            self.envelope_attack = val & 4 != 0;
+            self.envelope_holding = false;
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let period = self.registers[channel * 2] as u16 | ((self.registers[channel * 2 + 1] & 0x0f) as u16) << 8;
+        period.max(1)
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack { self.envelope_step } else { ENVELOPE_STEPS - 1 - self.envelope_step }
+    }
+
+    fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider % ENVELOPE_DIVIDER == 0 {
+            self.clock_envelope();
+        }
+        if self.divider == TONE_DIVIDER {
+            self.divider = 0;
+            self.clock_tones();
+        }
+    }
+
+    fn clock_tones(&mut self) {
+        for channel in 0 .. TONE_CHANNELS {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        self.noise_counter += 1;
+        if self.noise_counter >= (self.registers[6] & 0x1f).max(1) {
+            self.noise_counter = 0;
+            let feedback = (self.noise ^ (self.noise >> 3)) & 1;
+            self.noise = (self.noise >> 1) | feedback << 16;
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        let period = (self.registers[0x0b] as u16 | (self.registers[0x0c] as u16) << 8).max(1);
+
+        self.envelope_counter += 1;
+        if self.envelope_counter < period {
+            return;
+        }
+        self.envelope_counter = 0;
+
+        if self.envelope_holding {
+            return;
+        }
+
+        self.envelope_step += 1;
+        if self.envelope_step < ENVELOPE_STEPS {
+            return;
+        }
+
+        // the end of one ramp, what happens next depends on the shape bits
+        let shape = self.registers[0x0d];
+        let (continues, alternate, hold) = (shape & 8 != 0, shape & 2 != 0, shape & 1 != 0);
+
+        if !continues {
+            // drops to 0 and stays there
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+            self.envelope_step = ENVELOPE_STEPS - 1;
+        } else {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+
+            if hold {
+                // holds at the end of the ramp, or at its start if it alternates
+                self.envelope_holding = true;
+                self.envelope_step = ENVELOPE_STEPS - 1;
+            } else {
+                self.envelope_step = 0;
+            }
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let mixer = self.registers[7];
+        let noise = self.noise & 1 != 0;
+        let mut output = 0.0;
+
+        for channel in 0 .. TONE_CHANNELS {
+            // the mixer bits disable the tone and noise, which leaves that part of the signal high
+            let tone_on = self.tone_outputs[channel] || mixer & (1 << channel) != 0;
+            let noise_on = noise || mixer & (8 << channel) != 0;
+            if !(tone_on && noise_on) {
+                continue;
+            }
+
+            let volume = self.registers[8 + channel];
+            let level = if volume & 0x10 != 0 {
+                self.envelope_level()
+            } else if volume & 0x0f == 0 {
+                0
+            } else {
+                // the fixed volumes are every other step of the envelope's
+                (volume & 0x0f) * 2 + 1
+            };
+
+            output += self.volume_table[level as usize];
+        }
+
+        output * SUNSOFT_5B_VOLUME
+    }
+}
+
+impl SaveState for Sunsoft5b {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.register);
+        state.write_bytes(&self.registers);
+
+        state.write_u8(self.divider);
+        for channel in 0 .. TONE_CHANNELS {
+            state.write_u16(self.tone_counters[channel]);
+            state.write_bool(self.tone_outputs[channel]);
+        }
+        state.write_u8(self.noise_counter);
+        state.write_u32(self.noise);
+
+        state.write_u16(self.envelope_counter);
+        state.write_u8(self.envelope_step);
+        state.write_bool(self.envelope_attack);
+        state.write_bool(self.envelope_holding);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.register = state.read_u8();
+        state.read_bytes_into(&mut self.registers);
+
+        self.divider = state.read_u8();
+        for channel in 0 .. TONE_CHANNELS {
+            self.tone_counters[channel] = state.read_u16();
+            self.tone_outputs[channel] = state.read_bool();
+        }
+        self.noise_counter = state.read_u8();
+        self.noise = state.read_u32();
+
+        self.envelope_counter = state.read_u16();
+        self.envelope_step = state.read_u8();
+        self.envelope_attack = state.read_bool();
+        self.envelope_holding = state.read_bool();
+    }
+}
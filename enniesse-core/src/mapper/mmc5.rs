@@ -0,0 +1,629 @@
+use rom::Rom;
+use mapper::{Mapper, Mirroring, PpuFetch};
+use apu;
+use apu::PulseChannel;
+use state::{SaveState, StateWriter, StateReader};
+
+// the largest prg ram any board has, 2 32k chips
+const PRG_RAM_SIZE: usize = 64 * 1024;
+const EXRAM_SIZE: usize = 1024;
+const CIRAM_PAGE_SIZE: usize = 1024;
+const CHR_RAM_SIZE: usize = 8 * 1024;
+const PRG_BANK_SIZE: usize = 0x2000;
+
+// the audio length counters and envelopes run at a fixed 240hz instead of off the apu's frame counter
+const AUDIO_FRAME_PERIOD: u16 = 7457;
+// the pcm channel at full scale is about as loud as the dmc at full scale
+const PCM_VOLUME: f32 = 0.57 / 255.0;
+
+// 34 tiles are fetched per scanline, the first 2 at the end of the scanline before
+const TILES_PER_SCANLINE: u8 = 34;
+const ATTRIBUTE_TABLE_OFFSET: usize = 0x3c0;
+
+pub struct Mmc5 {
+    rom: Box<Rom>,
+    prg_ram: Vec<u8>,
+    exram: Vec<u8>,
+    // only used by boards without chr rom
+    chr_ram: Vec<u8>,
+
+    prg_mode: u8,
+    chr_mode: u8,
+    // both have to be set to the right values before prg ram can be written
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    // 2 bits for each of the 4 nametables: ciram page 0 or 1, exram, or fill mode.
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    // $5113-$5117, 8k bank numbers. bit 7 picks rom over ram in the $8000-$dfff registers
+    prg_banks: [u8; 5],
+    // $5120-$5127 are the sprite set, $5128-$512b the background set. each includes the $5130 bits
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    last_chr_background_set: bool,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    irq_scanline: u8,
+    irq_enabled: bool,
+    scanline_irq: bool,
+    in_frame: bool,
+    scanline_counter: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    // what the ppu is doing, from the hooks and the ppuctrl writes seen on the cpu bus
+    tall_sprites: bool,
+    fetch: PpuFetch,
+    scanline: i16,
+    tile_fetches: u8,
+    nametable_reads: u8,
+    // the exram byte of the tile being fetched in extended attribute mode
+    extended_attribute: u8,
+    // whether the tile being fetched is in the split region, and where in the split it is
+    split_tile: bool,
+    split_column: usize,
+    split_y: usize,
+
+    pulse1: PulseChannel,
+    pulse2: PulseChannel,
+    audio_cycle: u16,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+    pcm: u8,
+}
+
+enum PrgTarget {
+    Rom(usize),
+    Ram(usize),
+}
+
+impl Mmc5 {
+    pub fn new(rom: Box<Rom>) -> Mmc5 {
+        let chr_ram = if rom.chr_rom.is_empty() { vec![0; CHR_RAM_SIZE] } else { Vec::new() };
+
+        let mut mmc5 = Mmc5 {
+            rom: rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            exram: vec![0; EXRAM_SIZE],
+            chr_ram: chr_ram,
+
+            prg_mode: 0,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0; 5],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            last_chr_background_set: false,
+
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+
+            irq_scanline: 0,
+            irq_enabled: false,
+            scanline_irq: false,
+            in_frame: false,
+            scanline_counter: 0,
+
+            multiplicand: 0xff,
+            multiplier: 0xff,
+
+            tall_sprites: false,
+            fetch: PpuFetch::Data,
+            scanline: 0,
+            tile_fetches: 0,
+            nametable_reads: 0,
+            extended_attribute: 0,
+            split_tile: false,
+            split_column: 0,
+            split_y: 0,
+
+            pulse1: PulseChannel::without_sweep(),
+            pulse2: PulseChannel::without_sweep(),
+            audio_cycle: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            pcm: 0,
+        };
+        mmc5.reset();
+
+        mmc5
+    }
+
+    fn prg_target(&self, addr: u16) -> PrgTarget {
+        if addr < 0x8000 {
+            let bank = (self.prg_banks[0] & 7) as usize;
+            return PrgTarget::Ram(bank * PRG_BANK_SIZE + (addr as usize & 0x1fff));
+        }
+
+        // which register covers the address, and how many 8k banks it switches
+        let slot = (addr as usize - 0x8000) / PRG_BANK_SIZE;
+        let (register, banks) = match (self.prg_mode, slot) {
+            (0, _) => (4, 4),
+            (1, 0 ... 1) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 0 ... 1) => (2, 2),
+            (2, 2) => (3, 1),
+            (2, _) => (4, 1),
+            (_, _) => (slot + 1, 1),
+        };
+
+        let value = self.prg_banks[register] as usize;
+        let bank = (value & 0x7f & !(banks - 1)) + slot % banks;
+        let offset = bank * PRG_BANK_SIZE + (addr as usize & 0x1fff);
+
+        // $5117 is always rom
+        if register == 4 || value & 0x80 != 0 {
+            PrgTarget::Rom(offset)
+        } else {
+            PrgTarget::Ram((bank & 7) * PRG_BANK_SIZE + (addr as usize & 0x1fff))
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [2, 1]
+    }
+
+    // which chr bank set a fetch uses. with 8x8 sprites it's always the sprite set
+    fn background_set(&self) -> bool {
+        if !self.tall_sprites {
+            return false;
+        }
+
+        match self.fetch {
+            PpuFetch::Background => true,
+            PpuFetch::Sprite => false,
+            PpuFetch::Data => self.last_chr_background_set
+        }
+    }
+
+    fn chr_address(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1fff;
+
+        if self.fetch == PpuFetch::Background && self.split_tile {
+            // the split has its own 4k page, and its own fine y in place of the one from v
+            return self.split_bank as usize * 0x1000 + (addr & 0x0ff8) + (self.split_y & 7);
+        }
+
+        if self.fetch == PpuFetch::Background && self.exram_mode == 1 {
+            // extended attributes pick a 4k page per tile
+            let bank = (self.extended_attribute & 0x3f) as usize | (self.chr_upper as usize) << 6;
+            return bank * 0x1000 + (addr & 0x0fff);
+        }
+
+        let background = self.background_set();
+        let (register, size) = match self.chr_mode {
+            0 => (if background { 11 } else { 7 }, 0x2000),
+            1 => (if background { 11 } else if addr < 0x1000 { 3 } else { 7 }, 0x1000),
+            2 => {
+                let slot = addr / 0x800;
+                (if background { 9 + (slot & 1) * 2 } else { slot * 2 + 1 }, 0x800)
+            },
+            _ => {
+                let slot = addr / 0x400;
+                (if background { 8 + (slot & 3) } else { slot }, 0x400)
+            }
+        };
+
+        self.chr_banks[register] as usize * size + (addr & (size - 1))
+    }
+
+    fn chr(&mut self) -> &mut [u8] {
+        if self.chr_ram.is_empty() {
+            &mut self.rom.chr_rom
+        } else {
+            &mut self.chr_ram
+        }
+    }
+
+    // which of the nametable sources covers a ppu address
+    fn nametable_source(&self, addr: u16) -> u8 {
+        let nametable = (addr as usize >> 10) & 3;
+        (self.nametable_mapping >> (nametable * 2)) & 3
+    }
+
+    fn split_enabled(&self) -> bool {
+        self.split_control & 0x80 != 0 && self.exram_mode <= 1
+    }
+
+    fn read_status(&mut self) -> u8 {
+        let status = (self.scanline_irq as u8) << 7 | (self.in_frame as u8) << 6;
+        self.scanline_irq = false;
+
+        status
+    }
+
+    fn write_exram(&mut self, addr: u16, val: u8) {
+        let offset = addr as usize & (EXRAM_SIZE - 1);
+
+        match self.exram_mode {
+            // while it's used by the ppu, writes outside of rendering store 0
+            0 | 1 => self.exram[offset] = if self.in_frame { val } else { 0 },
+            2 => self.exram[offset] = val,
+            _ => {}
+        }
+    }
+}
+
+// the 2 bit palette of a tile copied into every quadrant of an attribute byte
+fn attribute_byte(palette: u8) -> u8 {
+    (palette & 3) * 0x55
+}
+
+impl Mapper for Mmc5 {
+    fn load_byte_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5010 => {
+                let status = (self.pcm_irq as u8) << 7 | self.pcm_read_mode as u8;
+                self.pcm_irq = false;
+                status
+            },
+            0x5015 => (self.pulse2.length_active() as u8) << 1 | self.pulse1.length_active() as u8,
+            0x5204 => self.read_status(),
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5c00 ... 0x5fff => self.exram[addr as usize & (EXRAM_SIZE - 1)],
+            0x6000 ... 0xffff => {
+                let val = match self.prg_target(addr) {
+                    PrgTarget::Rom(offset) => self.rom.prg_rom[offset % self.rom.prg_rom.len()],
+                    PrgTarget::Ram(offset) => self.prg_ram[offset]
+                };
+
+                // in read mode the pcm channel plays whatever the cpu reads from $8000-$bfff
+                if self.pcm_read_mode && addr >= 0x8000 && addr < 0xc000 {
+                    if val == 0 {
+                        self.pcm_irq = true;
+                    } else {
+                        self.pcm = val;
+                    }
+                }
+
+                val
+            },
+            _ => 0
+        }
+    }
+
+    fn store_byte_prg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x5000 | 0x5002 | 0x5003 => self.pulse1.write(addr, val),
+            0x5004 | 0x5006 | 0x5007 => self.pulse2.write(addr, val),
+            0x5010 => {
+                self.pcm_read_mode = val & 1 != 0;
+                self.pcm_irq_enabled = val & 0x80 != 0;
+            },
+            0x5011 => {
+                // 0 isn't written in write mode either
+                if !self.pcm_read_mode && val != 0 {
+                    self.pcm = val;
+                }
+            },
+            0x5015 => {
+                self.pulse1.set_enabled(val & 1 != 0);
+                self.pulse2.set_enabled(val & 2 != 0);
+            },
+            0x5100 => self.prg_mode = val & 3,
+            0x5101 => self.chr_mode = val & 3,
+            0x5102 => self.prg_ram_protect[0] = val & 3,
+            0x5103 => self.prg_ram_protect[1] = val & 3,
+            0x5104 => self.exram_mode = val & 3,
+            0x5105 => self.nametable_mapping = val,
+            0x5106 => self.fill_tile = val,
+            0x5107 => self.fill_attribute = val & 3,
+            0x5113 ... 0x5117 => self.prg_banks[addr as usize - 0x5113] = val,
+            0x5120 ... 0x512b => {
+                self.chr_banks[addr as usize - 0x5120] = val as u16 | (self.chr_upper as u16) << 8;
+                self.last_chr_background_set = addr >= 0x5128;
+            },
+            0x5130 => self.chr_upper = val & 3,
+            0x5200 => self.split_control = val,
+            0x5201 => self.split_scroll = val,
+            0x5202 => self.split_bank = val,
+            0x5203 => self.irq_scanline = val,
+            0x5204 => self.irq_enabled = val & 0x80 != 0,
+            0x5205 => self.multiplicand = val,
+            0x5206 => self.multiplier = val,
+            0x5c00 ... 0x5fff => self.write_exram(addr, val),
+            0x6000 ... 0xffff => {
+                if let PrgTarget::Ram(offset) = self.prg_target(addr) {
+                    if self.prg_ram_writable() {
+                        self.prg_ram[offset] = val;
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        match addr {
+            0x5010 | 0x5015 | 0x5204 | 0x5205 | 0x5206 => true,
+            // exram is only readable while the ppu isn't using it
+            0x5c00 ... 0x5fff => self.exram_mode >= 2,
+            0x4020 ... 0x5fff => false,
+            _ => true
+        }
+    }
+
+    fn load_byte_chr(&mut self, addr: u16) -> u8 {
+        let address = self.chr_address(addr);
+        let chr = self.chr();
+        let len = chr.len();
+
+        chr[address % len]
+    }
+
+    fn store_byte_chr(&mut self, addr: u16, val: u8) {
+        if !self.chr_ram.is_empty() {
+            let address = self.chr_address(addr);
+            self.chr_ram[address % CHR_RAM_SIZE] = val;
+        }
+    }
+
+    // the nametables are decoded in load_nametable, this is just the closest standard arrangement
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_mapping {
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            0x44 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal
+        }
+    }
+
+    fn load_nametable(&mut self, addr: u16, ciram: &[u8]) -> u8 {
+        let offset = addr as usize & (EXRAM_SIZE - 1);
+
+        if self.fetch == PpuFetch::Background {
+            // each tile reads its nametable byte then its attribute byte
+            let tile_read = self.nametable_reads == 0;
+            self.nametable_reads += 1;
+
+            if self.split_tile {
+                let row = self.split_y / 8;
+                let column = self.split_column;
+
+                if tile_read {
+                    return self.exram[row * 32 + column];
+                }
+
+                let attribute = self.exram[ATTRIBUTE_TABLE_OFFSET + (row / 4) * 8 + column / 4];
+                let shift = (row & 2) << 1 | (column & 2);
+                return attribute_byte(attribute >> shift);
+            }
+
+            if self.exram_mode == 1 {
+                if tile_read {
+                    // the tile itself still comes from the nametable
+                    self.extended_attribute = self.exram[offset];
+                } else {
+                    return attribute_byte(self.extended_attribute >> 6);
+                }
+            }
+        }
+
+        match self.nametable_source(addr) {
+            source @ 0 ... 1 => ciram[source as usize * CIRAM_PAGE_SIZE + offset],
+            2 => if self.exram_mode <= 1 { self.exram[offset] } else { 0 },
+            _ => if offset >= ATTRIBUTE_TABLE_OFFSET { attribute_byte(self.fill_attribute) } else { self.fill_tile }
+        }
+    }
+
+    fn store_nametable(&mut self, addr: u16, val: u8, ciram: &mut [u8]) {
+        let offset = addr as usize & (EXRAM_SIZE - 1);
+
+        match self.nametable_source(addr) {
+            source @ 0 ... 1 => ciram[source as usize * CIRAM_PAGE_SIZE + offset] = val,
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[offset] = val;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn ppu_fetch(&mut self, fetch: PpuFetch) {
+        self.fetch = fetch;
+
+        if fetch != PpuFetch::Background {
+            return;
+        }
+
+        // the last 2 tiles fetched on a scanline are the first 2 of the next one
+        let fetch_number = self.tile_fetches;
+        self.tile_fetches = self.tile_fetches.wrapping_add(1);
+        self.nametable_reads = 0;
+
+        let column = ((fetch_number + 2) % TILES_PER_SCANLINE) as usize;
+        let scanline = if fetch_number >= TILES_PER_SCANLINE - 2 { self.scanline + 1 } else { self.scanline };
+
+        let split_tiles = (self.split_control & 0x1f) as usize;
+        let in_split = if self.split_control & 0x40 != 0 { column >= split_tiles } else { column < split_tiles };
+        self.split_tile = self.split_enabled() && in_split && scanline >= 0;
+
+        if self.split_tile {
+            self.split_column = column & 31;
+            self.split_y = (self.split_scroll as usize + scanline as usize) % 240;
+        }
+    }
+
+    fn ppu_scanline(&mut self, scanline: i16, rendering: bool) {
+        self.scanline = scanline;
+        self.tile_fetches = 0;
+
+        if rendering && scanline >= 0 && scanline < 240 {
+            if self.in_frame {
+                self.scanline_counter = self.scanline_counter.wrapping_add(1);
+                if self.scanline_counter == self.irq_scanline {
+                    self.scanline_irq = true;
+                }
+            } else {
+                self.in_frame = true;
+                self.scanline_counter = 0;
+                self.scanline_irq = false;
+            }
+        } else {
+            self.in_frame = false;
+        }
+    }
+
+    fn ppu_register_written(&mut self, addr: u16, val: u8) {
+        if addr & 7 == 0 {
+            self.tall_sprites = val & 0x20 != 0;
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.audio_cycle % 2 == 1 {
+            self.pulse1.step_timer();
+            self.pulse2.step_timer();
+        }
+
+        self.audio_cycle += 1;
+        if self.audio_cycle == AUDIO_FRAME_PERIOD {
+            self.audio_cycle = 0;
+
+            for pulse in [&mut self.pulse1, &mut self.pulse2].iter_mut() {
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.scanline_irq && self.irq_enabled || self.pcm_irq && self.pcm_irq_enabled
+    }
+
+    fn audio_output(&self) -> f32 {
+        apu::mix_pulses(self.pulse1.output(), self.pulse2.output()) + self.pcm as f32 * PCM_VOLUME
+    }
+
+    fn reset(&mut self) {
+        // the reset vector has to be in the last bank, which mode 3 with $5117 = $ff gives
+        self.prg_mode = 3;
+        self.prg_banks = [0, 0, 0, 0, 0xff];
+        self.chr_mode = 0;
+        self.exram_mode = 0;
+        self.split_control = 0;
+        self.irq_enabled = false;
+        self.scanline_irq = false;
+        self.in_frame = false;
+        self.pcm_irq_enabled = false;
+        self.pcm_irq = false;
+        self.pulse1.set_enabled(false);
+        self.pulse2.set_enabled(false);
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_bytes(&self.exram);
+        state.write_bytes(&self.chr_ram);
+
+        state.write_u8(self.prg_mode);
+        state.write_u8(self.chr_mode);
+        state.write_bytes(&self.prg_ram_protect);
+        state.write_u8(self.exram_mode);
+        state.write_u8(self.nametable_mapping);
+        state.write_u8(self.fill_tile);
+        state.write_u8(self.fill_attribute);
+        state.write_bytes(&self.prg_banks);
+        for bank in self.chr_banks.iter() {
+            state.write_u16(*bank);
+        }
+        state.write_u8(self.chr_upper);
+        state.write_bool(self.last_chr_background_set);
+
+        state.write_u8(self.split_control);
+        state.write_u8(self.split_scroll);
+        state.write_u8(self.split_bank);
+        state.write_bool(self.split_tile);
+        state.write_u8(self.split_column as u8);
+        state.write_u8(self.split_y as u8);
+
+        state.write_u8(self.nametable_reads);
+        state.write_u8(self.extended_attribute);
+
+        state.write_u8(self.irq_scanline);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.scanline_irq);
+        state.write_bool(self.in_frame);
+        state.write_u8(self.scanline_counter);
+
+        state.write_u8(self.multiplicand);
+        state.write_u8(self.multiplier);
+
+        state.write_bool(self.tall_sprites);
+        state.write_i16(self.scanline);
+        state.write_u8(self.tile_fetches);
+
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        state.write_u16(self.audio_cycle);
+        state.write_bool(self.pcm_read_mode);
+        state.write_bool(self.pcm_irq_enabled);
+        state.write_bool(self.pcm_irq);
+        state.write_u8(self.pcm);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.prg_ram);
+        state.read_bytes_into(&mut self.exram);
+        state.read_bytes_into(&mut self.chr_ram);
+
+        self.prg_mode = state.read_u8();
+        self.chr_mode = state.read_u8();
+        state.read_bytes_into(&mut self.prg_ram_protect);
+        self.exram_mode = state.read_u8();
+        self.nametable_mapping = state.read_u8();
+        self.fill_tile = state.read_u8();
+        self.fill_attribute = state.read_u8();
+        state.read_bytes_into(&mut self.prg_banks);
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.read_u16();
+        }
+        self.chr_upper = state.read_u8();
+        self.last_chr_background_set = state.read_bool();
+
+        self.split_control = state.read_u8();
+        self.split_scroll = state.read_u8();
+        self.split_bank = state.read_u8();
+        self.split_tile = state.read_bool();
+        self.split_column = state.read_u8() as usize;
+        self.split_y = state.read_u8() as usize;
+
+        self.nametable_reads = state.read_u8();
+        self.extended_attribute = state.read_u8();
+
+        self.irq_scanline = state.read_u8();
+        self.irq_enabled = state.read_bool();
+        self.scanline_irq = state.read_bool();
+        self.in_frame = state.read_bool();
+        self.scanline_counter = state.read_u8();
+
+        self.multiplicand = state.read_u8();
+        self.multiplier = state.read_u8();
+
+        self.tall_sprites = state.read_bool();
+        self.scanline = state.read_i16();
+        self.tile_fetches = state.read_u8();
+
+        self.pulse1.load_state(state);
+        self.pulse2.load_state(state);
+        self.audio_cycle = state.read_u16();
+        self.pcm_read_mode = state.read_bool();
+        self.pcm_irq_enabled = state.read_bool();
+        self.pcm_irq = state.read_bool();
+        self.pcm = state.read_u8();
+    }
+}
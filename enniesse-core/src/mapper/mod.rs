@@ -0,0 +1,166 @@
+use std::fmt;
+
+use rom::Rom;
+use state::{StateWriter, StateReader};
+
+mod nrom;
+mod mmc5;
+mod vrc;
+mod n163;
+mod fme7;
+mod fds;
+mod fds_audio;
+mod nsf;
+
+pub use self::nrom::Nrom;
+pub use self::mmc5::Mmc5;
+pub use self::vrc::{Vrc4, Vrc6, Vrc7};
+pub use self::n163::Namco163;
+pub use self::fme7::Fme7;
+pub use self::fds::Fds;
+pub use self::nsf::NsfMapper;
+
+pub trait Mapper {
+    fn load_byte_prg(&mut self, addr: u16) -> u8;
+    fn store_byte_prg(&mut self, addr: u16, val: u8);
+    fn load_byte_chr(&mut self, addr: u16) -> u8;
+    fn store_byte_chr(&mut self, addr: u16, val: u8);
+    
+    fn mirroring(&self) -> Mirroring;
+
+    // whether anything on the cartridge responds to a cpu read, unmapped reads return open bus
+    fn is_prg_mapped(&self, _: u16) -> bool {
+        true
+    }
+
+    // the cartridge decides what's at $2000-$2fff. most boards just wire ciram's address lines one way
+    // or another, mappers that disable ciram or supply their own nametables override these
+    fn load_nametable(&mut self, addr: u16, ciram: &[u8]) -> u8 {
+        ciram[self.mirroring().ciram_address(addr)]
+    }
+    fn store_nametable(&mut self, addr: u16, val: u8, ciram: &mut [u8]) {
+        ciram[self.mirroring().ciram_address(addr)] = val;
+    }
+
+    // the ppu says what it's about to read before it reads it, for mappers that bank per fetch
+    fn ppu_fetch(&mut self, _: PpuFetch) {}
+    // called as the ppu starts each scanline, -1 being the prerender line
+    fn ppu_scanline(&mut self, _: i16, _: bool) {}
+    // writes to the ppu registers, which some mappers watch on the cpu bus
+    fn ppu_register_written(&mut self, _: u16, _: u8) {}
+
+    // called once per cpu cycle, for timers and expansion audio
+    fn clock_cpu(&mut self) {}
+    // level triggered, the cpu keeps seeing it until the mapper clears it
+    fn irq_pending(&self) -> bool {
+        false
+    }
+    // expansion audio, on the same scale as the apu's mixer output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // called on reset and power cycle
+    fn reset(&mut self) {}
+
+    // the disk system's drive. everything else has no disk sides
+    fn disk_side_count(&self) -> usize {
+        0
+    }
+    // the side that's selected, and whether it's in the drive
+    fn disk_side(&self) -> (usize, bool) {
+        (0, false)
+    }
+    fn set_disk_side(&mut self, _: usize, _: bool) {}
+    // an ips patch of everything written to the disk, see DiskImage::changes
+    fn disk_changes(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    // mappers with internal registers or ram need to include them in save states
+    fn save_state(&self, _: &mut StateWriter) {}
+    fn load_state(&mut self, _: &mut StateReader) {}
+}
+
+#[derive(Debug)]
+pub enum MapperError {
+    Unsupported(u8),
+    // a rom that says it's for the disk system without coming from a disk image
+    NoDisk
+}
+
+impl fmt::Display for MapperError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapperError::Unsupported(mapper) => write!(f, "Unknown mapper: {}", mapper),
+            MapperError::NoDisk => write!(f, "The rom is for the disk system, which needs a disk image rather than a cartridge rom")
+        }
+    }
+}
+
+pub fn load_mapper(rom: Box<Rom>) -> Result<Box<Mapper>, MapperError> {
+    if rom.nsf.is_some() {
+        return Ok(Box::new(NsfMapper::new(rom)));
+    }
+
+    Ok(match rom.mapper {
+        0 => Box::new(Nrom::new(rom)),
+        5 => Box::new(Mmc5::new(rom)),
+        19 => Box::new(Namco163::new(rom)),
+        20 => Box::new(Fds::new(rom)?),
+        21 | 22 | 23 | 25 => Box::new(Vrc4::new(rom)),
+        24 | 26 => Box::new(Vrc6::new(rom)),
+        69 => Box::new(Fme7::new(rom)),
+        85 => Box::new(Vrc7::new(rom)),
+        _ => return Err(MapperError::Unsupported(rom.mapper))
+    })
+}
+
+// the console's 2k of nametable ram, followed by the 2k four screen boards add
+pub const CIRAM_SIZE: usize = 0x800;
+pub const NAMETABLE_RAM_SIZE: usize = 0x1000;
+const NAMETABLE_SIZE: usize = 0x400;
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen
+}
+
+impl Mirroring {
+    // the standard arrangements from the ines header
+    pub fn from_header(rom: &Rom) -> Mirroring {
+        if rom.flags6 & 8 != 0 {
+            Mirroring::FourScreen
+        } else if rom.flags6 & 1 == 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    // where a nametable address ends up in nametable ram
+    pub fn ciram_address(self, addr: u16) -> usize {
+        let addr = addr as usize;
+        let page = match self {
+            Mirroring::Horizontal => (addr >> 11) & 1,
+            Mirroring::Vertical => (addr >> 10) & 1,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => (addr >> 10) & 3
+        };
+
+        page * NAMETABLE_SIZE + (addr & (NAMETABLE_SIZE - 1))
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum PpuFetch {
+    Background,
+    Sprite,
+    // reads and writes through ppudata
+    Data
+}
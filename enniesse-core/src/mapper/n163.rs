@@ -0,0 +1,321 @@
+use rom::Rom;
+use mapper::{Mapper, Mirroring};
+use state::{StateWriter, StateReader};
+
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const CHR_RAM_SIZE: usize = 8 * 1024;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+// banks from here up select a page of ciram instead of chr rom
+const CIRAM_BANK: u8 = 0xe0;
+
+// holds the wavetables as well as the channel registers
+const SOUND_RAM_SIZE: usize = 128;
+const CHANNEL_REGISTERS: usize = 0x40;
+const MAX_CHANNELS: usize = 8;
+// the chip only updates one channel at a time, moving to the next every 15 cpu cycles
+const CHANNEL_UPDATE_PERIOD: u8 = 15;
+// a channel at full volume playing a full scale wave is about as loud as an apu pulse
+const N163_VOLUME: f32 = 0.1494 / 120.0;
+
+const IRQ_COUNTER_MAX: u16 = 0x7fff;
+
+// mapper 19
+pub struct Namco163 {
+    rom: Box<Rom>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+    prg_ram_protect: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq: bool,
+
+    sound_ram: Vec<u8>,
+    sound_address: u8,
+    sound_auto_increment: bool,
+    sound_disabled: bool,
+    update_cycle: u8,
+    // counts down from the highest channel
+    current_channel: usize,
+    channel_outputs: [i16; MAX_CHANNELS],
+}
+
+impl Namco163 {
+    pub fn new(rom: Box<Rom>) -> Namco163 {
+        let chr_ram = if rom.chr_rom.is_empty() { vec![0; CHR_RAM_SIZE] } else { Vec::new() };
+
+        Namco163 {
+            rom: rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr_ram: chr_ram,
+
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            nametable_banks: [CIRAM_BANK; 4],
+            prg_ram_protect: 0,
+
+            irq_counter: 0,
+            irq_enabled: false,
+            irq: false,
+
+            sound_ram: vec![0; SOUND_RAM_SIZE],
+            sound_address: 0,
+            sound_auto_increment: false,
+            sound_disabled: false,
+            update_cycle: 0,
+            current_channel: MAX_CHANNELS - 1,
+            channel_outputs: [0; MAX_CHANNELS],
+        }
+    }
+
+    fn load_prg(&self, bank: usize, addr: u16) -> u8 {
+        let offset = bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1));
+        self.rom.prg_rom[offset % self.rom.prg_rom.len()]
+    }
+
+    fn load_chr_bank(&self, bank: u8, addr: u16) -> u8 {
+        let offset = bank as usize * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1));
+
+        if self.chr_ram.is_empty() {
+            self.rom.chr_rom[offset % self.rom.chr_rom.len()]
+        } else {
+            self.chr_ram[offset % CHR_RAM_SIZE]
+        }
+    }
+
+    fn prg_ram_writable(&self, addr: u16) -> bool {
+        // the upper bits have to be $4 to allow writes at all, then each bit protects 2k
+        let window = (addr as usize - 0x6000) / 0x800;
+        self.prg_ram_protect & 0xf0 == 0x40 && self.prg_ram_protect & (1 << window) == 0
+    }
+
+    fn read_sound_data(&mut self) -> u8 {
+        let val = self.sound_ram[self.sound_address as usize];
+        self.advance_sound_address();
+        val
+    }
+
+    fn write_sound_data(&mut self, val: u8) {
+        self.sound_ram[self.sound_address as usize] = val;
+        self.advance_sound_address();
+    }
+
+    fn advance_sound_address(&mut self) {
+        if self.sound_auto_increment {
+            self.sound_address = (self.sound_address + 1) & (SOUND_RAM_SIZE as u8 - 1);
+        }
+    }
+
+    fn enabled_channels(&self) -> usize {
+        ((self.sound_ram[SOUND_RAM_SIZE - 1] >> 4) & 7) as usize + 1
+    }
+
+    // moves one channel's phase along and works out its new sample
+    fn update_channel(&mut self, channel: usize) {
+        let base = CHANNEL_REGISTERS + channel * 8;
+        let registers = &mut self.sound_ram[base .. base + 8];
+
+        let frequency = registers[0] as u32 | (registers[2] as u32) << 8 | ((registers[4] & 3) as u32) << 16;
+        let length = 256 - (registers[4] & 0xfc) as u32;
+        let mut phase = registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+
+        phase = (phase + frequency) % (length << 16);
+        registers[1] = phase as u8;
+        registers[3] = (phase >> 8) as u8;
+        registers[5] = (phase >> 16) as u8;
+
+        // samples are 4 bits, packed 2 to a byte low nibble first
+        let sample_address = (registers[6] as u32 + (phase >> 16)) & 0xff;
+        let volume = (registers[7] & 0x0f) as i16;
+
+        let byte = self.sound_ram[sample_address as usize / 2];
+        let sample = (byte >> ((sample_address & 1) * 4)) & 0x0f;
+
+        self.channel_outputs[channel] = (sample as i16 - 8) * volume;
+    }
+}
+
+impl Mapper for Namco163 {
+    fn load_byte_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800 ... 0x4fff => self.read_sound_data(),
+            0x5000 ... 0x57ff => self.irq_counter as u8,
+            0x5800 ... 0x5fff => (self.irq_enabled as u8) << 7 | (self.irq_counter >> 8) as u8,
+            0x6000 ... 0x7fff => self.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)],
+            0x8000 ... 0xdfff => {
+                let bank = self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] as usize;
+                self.load_prg(bank, addr)
+            },
+            0xe000 ... 0xffff => {
+                let last = self.rom.prg_rom.len() / PRG_BANK_SIZE - 1;
+                self.load_prg(last, addr)
+            },
+            _ => 0
+        }
+    }
+
+    fn store_byte_prg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4800 ... 0x4fff => self.write_sound_data(val),
+            0x5000 ... 0x57ff => {
+                self.irq_counter = (self.irq_counter & 0x7f00) | val as u16;
+                self.irq = false;
+            },
+            0x5800 ... 0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | ((val & 0x7f) as u16) << 8;
+                self.irq_enabled = val & 0x80 != 0;
+                self.irq = false;
+            },
+            0x6000 ... 0x7fff => {
+                if self.prg_ram_writable(addr) {
+                    self.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)] = val;
+                }
+            },
+            0x8000 ... 0xbfff => self.chr_banks[(addr as usize - 0x8000) / 0x800] = val,
+            0xc000 ... 0xdfff => self.nametable_banks[(addr as usize - 0xc000) / 0x800] = val,
+            0xe000 ... 0xe7ff => {
+                self.prg_banks[0] = val & 0x3f;
+                self.sound_disabled = val & 0x40 != 0;
+            },
+            // the upper bits turn off ciram pattern banks, which aren't supported anyway
+            0xe800 ... 0xefff => self.prg_banks[1] = val & 0x3f,
+            0xf000 ... 0xf7ff => self.prg_banks[2] = val & 0x3f,
+            0xf800 ... 0xffff => {
+                self.sound_address = val & 0x7f;
+                self.sound_auto_increment = val & 0x80 != 0;
+                self.prg_ram_protect = val;
+            },
+            _ => {}
+        }
+    }
+
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x4800
+    }
+
+    // pattern table banks pointing at ciram aren't supported, they read chr rom like the rest
+    fn load_byte_chr(&mut self, addr: u16) -> u8 {
+        self.load_chr_bank(self.chr_banks[(addr as usize >> 10) & 7], addr)
+    }
+    fn store_byte_chr(&mut self, addr: u16, val: u8) {
+        if !self.chr_ram.is_empty() {
+            let bank = self.chr_banks[(addr as usize >> 10) & 7] as usize;
+            let offset = bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1));
+            self.chr_ram[offset % CHR_RAM_SIZE] = val;
+        }
+    }
+
+    // each nametable can be either page of ciram or any 1k of chr rom, this is only a rough guess
+    fn mirroring(&self) -> Mirroring {
+        if self.nametable_banks[1] & 1 == 0 { Mirroring::Horizontal } else { Mirroring::Vertical }
+    }
+
+    fn load_nametable(&mut self, addr: u16, ciram: &[u8]) -> u8 {
+        let bank = self.nametable_banks[(addr as usize >> 10) & 3];
+        let offset = addr as usize & (CHR_BANK_SIZE - 1);
+
+        if bank >= CIRAM_BANK {
+            ciram[(bank & 1) as usize * CHR_BANK_SIZE + offset]
+        } else {
+            self.load_chr_bank(bank, addr)
+        }
+    }
+
+    fn store_nametable(&mut self, addr: u16, val: u8, ciram: &mut [u8]) {
+        let bank = self.nametable_banks[(addr as usize >> 10) & 3];
+
+        if bank >= CIRAM_BANK {
+            ciram[(bank & 1) as usize * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))] = val;
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_enabled && self.irq_counter < IRQ_COUNTER_MAX {
+            self.irq_counter += 1;
+            if self.irq_counter == IRQ_COUNTER_MAX {
+                self.irq = true;
+            }
+        }
+
+        self.update_cycle += 1;
+        if self.update_cycle == CHANNEL_UPDATE_PERIOD {
+            self.update_cycle = 0;
+
+            let channel = self.current_channel;
+            self.update_channel(channel);
+
+            // the enabled channels are the highest ones, counting down from 7
+            let lowest = MAX_CHANNELS - self.enabled_channels();
+            self.current_channel = if channel <= lowest { MAX_CHANNELS - 1 } else { channel - 1 };
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq
+    }
+
+    // the real chip plays the channels one after another, which averages out to the same thing
+    // without the whine that comes with more than a few channels
+    fn audio_output(&self) -> f32 {
+        if self.sound_disabled {
+            return 0.0;
+        }
+
+        let channels = self.enabled_channels();
+        let sum: i16 = self.channel_outputs[MAX_CHANNELS - channels ..].iter().sum();
+
+        sum as f32 / channels as f32 * N163_VOLUME
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_bytes(&self.chr_ram);
+
+        state.write_bytes(&self.prg_banks);
+        state.write_bytes(&self.chr_banks);
+        state.write_bytes(&self.nametable_banks);
+        state.write_u8(self.prg_ram_protect);
+
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq);
+
+        state.write_bytes(&self.sound_ram);
+        state.write_u8(self.sound_address);
+        state.write_bool(self.sound_auto_increment);
+        state.write_bool(self.sound_disabled);
+        state.write_u8(self.update_cycle);
+        state.write_u8(self.current_channel as u8);
+        for output in self.channel_outputs.iter() {
+            state.write_i16(*output);
+        }
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.prg_ram);
+        state.read_bytes_into(&mut self.chr_ram);
+
+        state.read_bytes_into(&mut self.prg_banks);
+        state.read_bytes_into(&mut self.chr_banks);
+        state.read_bytes_into(&mut self.nametable_banks);
+        self.prg_ram_protect = state.read_u8();
+
+        self.irq_counter = state.read_u16();
+        self.irq_enabled = state.read_bool();
+        self.irq = state.read_bool();
+
+        state.read_bytes_into(&mut self.sound_ram);
+        self.sound_address = state.read_u8();
+        self.sound_auto_increment = state.read_bool();
+        self.sound_disabled = state.read_bool();
+        self.update_cycle = state.read_u8();
+        self.current_channel = state.read_u8() as usize;
+        for output in self.channel_outputs.iter_mut() {
+            *output = state.read_i16();
+        }
+    }
+}
@@ -1,26 +1,6 @@
 use rom::Rom;
-
-pub trait Mapper {
-    fn load_byte_prg(&mut self, addr: u16) -> u8;
-    fn store_byte_prg(&mut self, addr: u16, val: u8);
-    fn load_byte_chr(&mut self, addr: u16) -> u8;
-    fn store_byte_chr(&mut self, addr: u16, val: u8);
-    
-    fn mirroring(&self) -> Mirroring;
-}
-
-pub fn load_mapper(rom: Box<Rom>) -> Box<Mapper> {
-    match rom.mapper {
-        0 => Box::new(Nrom::new(rom)),
-        _ => panic!("Unknown mapper: {}", rom.mapper)
-    }
-}
-
-#[derive(Eq, PartialEq, Copy, Clone, Debug)]
-pub enum Mirroring {
-    Horizontal,
-    Vertical
-}
+use mapper::{Mapper, Mirroring};
+use state::{StateWriter, StateReader};
 
 const NROM_RAM_SIZE: usize = 4096;
 
@@ -51,6 +31,10 @@ impl Mapper for Nrom {
         }
     }
     fn store_byte_prg(&mut self, _: u16, _: u8) {}
+
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x6000
+    }
     
     fn load_byte_chr(&mut self, addr: u16) -> u8 {
         if self.rom.chr_rom_size != 0 {
@@ -66,10 +50,13 @@ impl Mapper for Nrom {
     }
     
     fn mirroring(&self) -> Mirroring {
-        if self.rom.flags6 & 1 == 0 {
-            Mirroring::Horizontal
-        } else {
-            Mirroring::Vertical
-        }
+        Mirroring::from_header(&self.rom)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.ram);
     }
 }
\ No newline at end of file
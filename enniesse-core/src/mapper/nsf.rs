@@ -0,0 +1,300 @@
+use rom::{Rom, Region};
+use nsf;
+use nsf::Nsf;
+use mapper::{Mapper, Mirroring, Mmc5, Vrc6, Namco163, Fme7};
+use mapper::fds_audio::FdsAudio;
+use state::{SaveState, StateWriter, StateReader};
+
+const BANK_SIZE: usize = 0x1000;
+const RAM_SIZE: usize = 8 * 1024;
+// the fds has ram from $6000 all the way up
+const FDS_RAM_SIZE: usize = 40 * 1024;
+const CHR_RAM_SIZE: usize = 8 * 1024;
+
+// expansion chips, each one a cartridge mapper of its own that only ever sees writes to its sound registers
+struct ExpansionChips {
+    vrc6: Option<Vrc6>,
+    mmc5: Option<Mmc5>,
+    n163: Option<Namco163>,
+    sunsoft_5b: Option<Fme7>,
+    fds: Option<FdsAudio>,
+}
+
+impl ExpansionChips {
+    fn new(expansion: u8) -> ExpansionChips {
+        let chip = |flag: u8| expansion & flag != 0;
+
+        let mmc5 = if chip(nsf::EXPANSION_MMC5) {
+            // exram as plain ram
+            let mut mmc5 = Mmc5::new(chip_rom(5));
+            mmc5.store_byte_prg(0x5104, 2);
+            Some(mmc5)
+        } else {
+            None
+        };
+
+        ExpansionChips {
+            vrc6: if chip(nsf::EXPANSION_VRC6) { Some(Vrc6::new(chip_rom(24))) } else { None },
+            mmc5: mmc5,
+            n163: if chip(nsf::EXPANSION_N163) { Some(Namco163::new(chip_rom(19))) } else { None },
+            sunsoft_5b: if chip(nsf::EXPANSION_SUNSOFT_5B) { Some(Fme7::new(chip_rom(69))) } else { None },
+            fds: if chip(nsf::EXPANSION_FDS) { Some(FdsAudio::new()) } else { None },
+        }
+    }
+
+    fn load_byte(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040 ... 0x4092 => self.fds.as_mut().map(|fds| fds.load_byte(addr)),
+            0x4800 ... 0x4fff => self.n163.as_mut().map(|n163| n163.load_byte_prg(addr)),
+            0x5015 | 0x5205 | 0x5206 | 0x5c00 ... 0x5ff5 => self.mmc5.as_mut().map(|mmc5| mmc5.load_byte_prg(addr)),
+            _ => None
+        }
+    }
+
+    fn store_byte(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4040 ... 0x408a => {
+                if let Some(ref mut fds) = self.fds {
+                    fds.store_byte(addr, val);
+                }
+            },
+            0x4800 ... 0x4fff | 0xf800 ... 0xffff => {
+                if let Some(ref mut n163) = self.n163 {
+                    n163.store_byte_prg(addr, val);
+                }
+            },
+            0x5000 ... 0x5015 | 0x5205 | 0x5206 | 0x5c00 ... 0x5ff5 => {
+                if let Some(ref mut mmc5) = self.mmc5 {
+                    mmc5.store_byte_prg(addr, val);
+                }
+            },
+            0x9000 ... 0x9003 | 0xa000 ... 0xa002 | 0xb000 ... 0xb002 => {
+                if let Some(ref mut vrc6) = self.vrc6 {
+                    vrc6.store_byte_prg(addr, val);
+                }
+            },
+            _ => {}
+        }
+
+        // the 5b shares $f800 and up with the n163
+        if addr >= 0xc000 {
+            if let Some(ref mut sunsoft_5b) = self.sunsoft_5b {
+                sunsoft_5b.store_byte_prg(addr, val);
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if let Some(ref mut vrc6) = self.vrc6 { vrc6.clock_cpu(); }
+        if let Some(ref mut mmc5) = self.mmc5 { mmc5.clock_cpu(); }
+        if let Some(ref mut n163) = self.n163 { n163.clock_cpu(); }
+        if let Some(ref mut sunsoft_5b) = self.sunsoft_5b { sunsoft_5b.clock_cpu(); }
+        if let Some(ref mut fds) = self.fds { fds.clock(); }
+    }
+
+    fn output(&self) -> f32 {
+        self.vrc6.as_ref().map_or(0.0, |vrc6| vrc6.audio_output())
+            + self.mmc5.as_ref().map_or(0.0, |mmc5| mmc5.audio_output())
+            + self.n163.as_ref().map_or(0.0, |n163| n163.audio_output())
+            + self.sunsoft_5b.as_ref().map_or(0.0, |sunsoft_5b| sunsoft_5b.audio_output())
+            + self.fds.as_ref().map_or(0.0, |fds| fds.output())
+    }
+}
+
+impl SaveState for ExpansionChips {
+    fn save_state(&self, state: &mut StateWriter) {
+        if let Some(ref vrc6) = self.vrc6 { vrc6.save_state(state); }
+        if let Some(ref mmc5) = self.mmc5 { mmc5.save_state(state); }
+        if let Some(ref n163) = self.n163 { n163.save_state(state); }
+        if let Some(ref sunsoft_5b) = self.sunsoft_5b { sunsoft_5b.save_state(state); }
+        if let Some(ref fds) = self.fds { fds.save_state(state); }
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        if let Some(ref mut vrc6) = self.vrc6 { vrc6.load_state(state); }
+        if let Some(ref mut mmc5) = self.mmc5 { mmc5.load_state(state); }
+        if let Some(ref mut n163) = self.n163 { n163.load_state(state); }
+        if let Some(ref mut sunsoft_5b) = self.sunsoft_5b { sunsoft_5b.load_state(state); }
+        if let Some(ref mut fds) = self.fds { fds.load_state(state); }
+    }
+}
+
+// an empty cartridge for an expansion chip's mapper to sit on
+fn chip_rom(mapper: u8) -> Box<Rom> {
+    Box::new(Rom {
+        prg_rom_size: 1,
+        chr_rom_size: 0,
+        flags6: 0,
+        flags7: 0,
+        mapper: mapper,
+        submapper: 0,
+        region: Region::Ntsc,
+        prg_rom: vec![0; 0x4000].into_boxed_slice(),
+        chr_rom: Box::new([]),
+        title: None,
+        disk: None,
+        nsf: None
+    })
+}
+
+// the rom in the 4k banks $5ff8-$5fff select, padded so that the load address lands in the right place
+// in its bank. files that don't bankswitch are loaded at the load address as they are
+pub struct NsfMapper {
+    nsf: Nsf,
+    rom: Vec<u8>,
+    // ram at $6000-$7fff, or $6000-$ffff for the fds
+    ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+    banks: [u8; 8],
+    chips: ExpansionChips,
+}
+
+impl NsfMapper {
+    pub fn new(mut rom: Box<Rom>) -> NsfMapper {
+        let nsf = rom.nsf.take().expect("No nsf for the nsf mapper");
+
+        let padding = if nsf.is_bankswitched() {
+            nsf.load_address as usize & (BANK_SIZE - 1)
+        } else {
+            // unbanked files start at $8000, or at $6000 for the fds
+            (nsf.load_address as usize).saturating_sub(if nsf.expansion & nsf::EXPANSION_FDS != 0 { 0x6000 } else { 0x8000 })
+        };
+        let mut data = vec![0; padding];
+        data.extend_from_slice(&nsf.data);
+
+        let ram_size = if nsf.expansion & nsf::EXPANSION_FDS != 0 { FDS_RAM_SIZE } else { RAM_SIZE };
+        let mut mapper = NsfMapper {
+            rom: data,
+            ram: vec![0; ram_size],
+            chr_ram: vec![0; CHR_RAM_SIZE],
+            banks: [0; 8],
+            chips: ExpansionChips::new(nsf.expansion),
+            nsf: nsf,
+        };
+        mapper.reset();
+
+        mapper
+    }
+
+    fn is_fds(&self) -> bool {
+        self.ram.len() == FDS_RAM_SIZE
+    }
+
+    fn load_bank(&self, bank: u8, addr: u16) -> u8 {
+        let offset = bank as usize * BANK_SIZE + (addr as usize & (BANK_SIZE - 1));
+        self.rom.get(offset).cloned().unwrap_or(0)
+    }
+
+    // the fds keeps its banks in ram, so they're copied in when selected
+    fn copy_fds_bank(&mut self, window: usize, bank: u8) {
+        let start = window * BANK_SIZE;
+        for i in 0 .. BANK_SIZE {
+            self.ram[start + i] = self.load_bank(bank, i as u16);
+        }
+    }
+
+    fn write_bank(&mut self, register: usize, bank: u8) {
+        if self.is_fds() {
+            // $5ff6 and $5ff7 are for $6000-$7fff
+            self.copy_fds_bank(register, bank);
+        } else if register >= 2 {
+            self.banks[register - 2] = bank;
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn load_byte_prg(&mut self, addr: u16) -> u8 {
+        if let Some(val) = self.chips.load_byte(addr) {
+            return val;
+        }
+
+        match addr {
+            0x6000 ... 0xffff if self.is_fds() => self.ram[addr as usize - 0x6000],
+            0x6000 ... 0x7fff => self.ram[addr as usize - 0x6000],
+            0x8000 ... 0xffff => {
+                let bank = self.banks[(addr as usize - 0x8000) / BANK_SIZE];
+                self.load_bank(bank, addr)
+            },
+            _ => 0
+        }
+    }
+
+    fn store_byte_prg(&mut self, addr: u16, val: u8) {
+        self.chips.store_byte(addr, val);
+
+        match addr {
+            0x5ff6 ... 0x5fff => self.write_bank(addr as usize - 0x5ff6, val),
+            0x6000 ... 0xffff if self.is_fds() => self.ram[addr as usize - 0x6000] = val,
+            0x6000 ... 0x7fff => self.ram[addr as usize - 0x6000] = val,
+            _ => {}
+        }
+    }
+
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        match addr {
+            0x4040 ... 0x407f | 0x4090 | 0x4092 => self.chips.fds.is_some(),
+            0x4800 ... 0x4fff => self.chips.n163.is_some(),
+            0x5015 | 0x5205 | 0x5206 | 0x5c00 ... 0x5ff5 => self.chips.mmc5.is_some(),
+            _ => addr >= 0x6000
+        }
+    }
+
+    fn load_byte_chr(&mut self, addr: u16) -> u8 {
+        self.chr_ram[addr as usize & (CHR_RAM_SIZE - 1)]
+    }
+    fn store_byte_chr(&mut self, addr: u16, val: u8) {
+        self.chr_ram[addr as usize & (CHR_RAM_SIZE - 1)] = val;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Vertical
+    }
+
+    fn clock_cpu(&mut self) {
+        self.chips.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.chips.output()
+    }
+
+    // everything goes back to how it was loaded before each track starts
+    fn reset(&mut self) {
+        for val in self.ram.iter_mut() {
+            *val = 0;
+        }
+        self.chips = ExpansionChips::new(self.nsf.expansion);
+
+        let is_fds = self.is_fds();
+        if self.nsf.is_bankswitched() {
+            let banks = self.nsf.banks;
+            for (i, &bank) in banks.iter().enumerate() {
+                self.write_bank(i + 2, bank);
+            }
+            if is_fds {
+                self.write_bank(0, banks[6]);
+                self.write_bank(1, banks[7]);
+            }
+        } else {
+            self.banks = [0, 1, 2, 3, 4, 5, 6, 7];
+            if is_fds {
+                for window in 0 .. FDS_RAM_SIZE / BANK_SIZE {
+                    self.copy_fds_bank(window, window as u8);
+                }
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_bytes(&self.chr_ram);
+        state.write_bytes(&self.banks);
+        self.chips.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.ram);
+        state.read_bytes_into(&mut self.chr_ram);
+        state.read_bytes_into(&mut self.banks);
+        self.chips.load_state(state);
+    }
+}
@@ -0,0 +1,856 @@
+// konami's vrc chips. they share the cpu cycle irq counter and the way banks are laid out, but each
+// board wires the register select pins to different cpu address lines
+
+use rom::Rom;
+use mapper::{Mapper, Mirroring};
+use state::{SaveState, StateWriter, StateReader};
+
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const CHR_RAM_SIZE: usize = 8 * 1024;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+// in scanline mode the prescaler takes 3 off per cpu cycle, so the counter ticks once every 341 ppu dots
+const IRQ_PRESCALER_PERIOD: i16 = 341;
+const IRQ_PRESCALER_STEP: i16 = 3;
+
+// a vrc6 pulse at full volume is about as loud as an apu pulse at full volume
+const VRC6_VOLUME: f32 = 0.1494 / 15.0;
+// the sawtooth resets after 7 additions, counting the clocks in between
+const SAWTOOTH_STEPS: u8 = 14;
+
+// the memory every board in the family has, and 1k chr banking
+struct Banks {
+    rom: Box<Rom>,
+    prg_ram: Vec<u8>,
+    // only used by boards without chr rom
+    chr_ram: Vec<u8>,
+}
+
+impl Banks {
+    fn new(rom: Box<Rom>) -> Banks {
+        let chr_ram = if rom.chr_rom.is_empty() { vec![0; CHR_RAM_SIZE] } else { Vec::new() };
+
+        Banks {
+            rom: rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr_ram: chr_ram,
+        }
+    }
+
+    fn last_prg_bank(&self) -> usize {
+        self.rom.prg_rom.len() / PRG_BANK_SIZE - 1
+    }
+
+    fn load_prg(&self, bank: usize, addr: u16) -> u8 {
+        let offset = bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1));
+        self.rom.prg_rom[offset % self.rom.prg_rom.len()]
+    }
+
+    fn load_chr(&self, bank: usize, addr: u16) -> u8 {
+        let offset = bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1));
+
+        if self.chr_ram.is_empty() {
+            self.rom.chr_rom[offset % self.rom.chr_rom.len()]
+        } else {
+            self.chr_ram[offset % CHR_RAM_SIZE]
+        }
+    }
+
+    fn store_chr(&mut self, bank: usize, addr: u16, val: u8) {
+        if !self.chr_ram.is_empty() {
+            let offset = bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1));
+            self.chr_ram[offset % CHR_RAM_SIZE] = val;
+        }
+    }
+}
+
+impl SaveState for Banks {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_bytes(&self.chr_ram);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.prg_ram);
+        state.read_bytes_into(&mut self.chr_ram);
+    }
+}
+
+// the mirroring control bits of the vrc4, vrc6 and vrc7
+fn mirroring_from_bits(val: u8) -> Mirroring {
+    match val & 3 {
+        0 => Mirroring::Vertical,
+        1 => Mirroring::Horizontal,
+        2 => Mirroring::SingleScreenLower,
+        _ => Mirroring::SingleScreenUpper
+    }
+}
+
+fn mirroring_bits(mirroring: Mirroring) -> u8 {
+    match mirroring {
+        Mirroring::Vertical => 0,
+        Mirroring::Horizontal => 1,
+        Mirroring::SingleScreenLower => 2,
+        _ => 3
+    }
+}
+
+// counts up from the latch, either every cpu cycle or every scanline's worth of cycles
+struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    fn new() -> VrcIrq {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: IRQ_PRESCALER_PERIOD,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    fn write_latch_low(&mut self, val: u8) {
+        self.latch = (self.latch & 0xf0) | (val & 0x0f);
+    }
+    fn write_latch_high(&mut self, val: u8) {
+        self.latch = (self.latch & 0x0f) | (val & 0x0f) << 4;
+    }
+
+    fn write_control(&mut self, val: u8) {
+        self.enable_after_ack = val & 1 != 0;
+        self.enabled = val & 2 != 0;
+        self.cycle_mode = val & 4 != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = IRQ_PRESCALER_PERIOD;
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= IRQ_PRESCALER_STEP;
+            if self.prescaler <= 0 {
+                self.prescaler += IRQ_PRESCALER_PERIOD;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl SaveState for VrcIrq {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.latch);
+        state.write_u8(self.counter);
+        state.write_i16(self.prescaler);
+        state.write_bool(self.enabled);
+        state.write_bool(self.enable_after_ack);
+        state.write_bool(self.cycle_mode);
+        state.write_bool(self.pending);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.latch = state.read_u8();
+        self.counter = state.read_u8();
+        self.prescaler = state.read_i16();
+        self.enabled = state.read_bool();
+        self.enable_after_ack = state.read_bool();
+        self.cycle_mode = state.read_bool();
+        self.pending = state.read_bool();
+    }
+}
+
+// mappers 21, 22, 23 and 25. the vrc2 is a vrc4 without the irq, the prg swap mode,
+// single screen mirroring or prg ram, and with a 1 bit latch at $6000 instead
+pub struct Vrc4 {
+    banks: Banks,
+    vrc2: bool,
+    prg_ram: bool,
+    // the cpu address lines the chip's A0 and A1 pins are connected to. when the header doesn't say
+    // which board it is, both of the possible lines are used, which works for all the games
+    a0: u16,
+    a1: u16,
+    // vrc2a leaves out the low bit of the chr bank numbers
+    chr_shift: u8,
+
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    latch: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(rom: Box<Rom>) -> Vrc4 {
+        let (a0, a1, vrc2) = match (rom.mapper, rom.submapper) {
+            (21, 1) => (0x02, 0x04, false), // vrc4a
+            (21, 2) => (0x40, 0x80, false), // vrc4c
+            (21, _) => (0x42, 0x84, false),
+            (22, _) => (0x02, 0x01, true), // vrc2a
+            (23, 1) => (0x01, 0x02, false), // vrc4f
+            (23, 2) => (0x04, 0x08, false), // vrc4e
+            (23, 3) => (0x01, 0x02, true), // vrc2b
+            (23, _) => (0x05, 0x0a, false),
+            (25, 1) => (0x02, 0x01, false), // vrc4b
+            (25, 2) => (0x08, 0x04, false), // vrc4d
+            (25, 3) => (0x02, 0x01, true), // vrc2c
+            (_, _) => (0x0a, 0x05, false)
+        };
+
+        // games on vrc2 boards with a battery have prg ram there, the rest only have the latch
+        let prg_ram = !vrc2 || rom.flags6 & 2 != 0;
+        let chr_shift = if rom.mapper == 22 { 1 } else { 0 };
+
+        Vrc4 {
+            banks: Banks::new(rom),
+            vrc2: vrc2,
+            prg_ram: prg_ram,
+            a0: a0,
+            a1: a1,
+            chr_shift: chr_shift,
+
+            prg_banks: [0; 2],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            latch: 0,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    // the address with the register select lines moved to bits 0 and 1
+    fn register(&self, addr: u16) -> u16 {
+        (addr & 0xf000) | (addr & self.a0 != 0) as u16 | ((addr & self.a1 != 0) as u16) << 1
+    }
+
+    fn write_chr_bank(&mut self, register: u16, val: u8) {
+        // each 1k bank is split over 2 registers, the low 4 bits then the high bits
+        let bank = ((register - 0xb000) >> 12) as usize * 2 + ((register & 2) >> 1) as usize;
+        let old = self.chr_banks[bank];
+
+        self.chr_banks[bank] = if register & 1 == 0 {
+            (old & 0x1f0) | (val & 0x0f) as u16
+        } else {
+            let high_bits = if self.vrc2 { 0x0f } else { 0x1f };
+            (old & 0x0f) | ((val & high_bits) as u16) << 4
+        };
+    }
+
+    fn chr_bank(&self, addr: u16) -> usize {
+        (self.chr_banks[(addr as usize >> 10) & 7] >> self.chr_shift) as usize
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn load_byte_prg(&mut self, addr: u16) -> u8 {
+        let second_last = self.banks.last_prg_bank() - 1;
+
+        match addr {
+            0x6000 ... 0x7fff => {
+                if self.prg_ram {
+                    self.banks.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)]
+                } else {
+                    self.latch
+                }
+            },
+            0x8000 ... 0x9fff => {
+                let bank = if self.prg_swap { second_last } else { self.prg_banks[0] as usize };
+                self.banks.load_prg(bank, addr)
+            },
+            0xa000 ... 0xbfff => self.banks.load_prg(self.prg_banks[1] as usize, addr),
+            0xc000 ... 0xdfff => {
+                let bank = if self.prg_swap { self.prg_banks[0] as usize } else { second_last };
+                self.banks.load_prg(bank, addr)
+            },
+            0xe000 ... 0xffff => {
+                let last = self.banks.last_prg_bank();
+                self.banks.load_prg(last, addr)
+            },
+            _ => 0
+        }
+    }
+
+    fn store_byte_prg(&mut self, addr: u16, val: u8) {
+        if addr < 0x8000 {
+            if self.prg_ram {
+                self.banks.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)] = val;
+            } else if addr < 0x7000 {
+                self.latch = val & 1;
+            }
+            return;
+        }
+
+        let register = self.register(addr);
+        match register {
+            0x8000 ... 0x8003 => self.prg_banks[0] = val & 0x1f,
+            0x9000 | 0x9001 => {
+                self.mirroring = if self.vrc2 { mirroring_from_bits(val & 1) } else { mirroring_from_bits(val) };
+            },
+            0x9002 | 0x9003 => {
+                if !self.vrc2 {
+                    self.prg_swap = val & 2 != 0;
+                }
+            },
+            0xa000 ... 0xa003 => self.prg_banks[1] = val & 0x1f,
+            0xb000 ... 0xefff => self.write_chr_bank(register, val),
+            _ if self.vrc2 => {},
+            0xf000 => self.irq.write_latch_low(val),
+            0xf001 => self.irq.write_latch_high(val),
+            0xf002 => self.irq.write_control(val),
+            0xf003 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        match addr {
+            0x6000 ... 0x6fff => true,
+            0x7000 ... 0x7fff => self.prg_ram,
+            _ => addr >= 0x8000
+        }
+    }
+
+    fn load_byte_chr(&mut self, addr: u16) -> u8 {
+        self.banks.load_chr(self.chr_bank(addr), addr)
+    }
+    fn store_byte_chr(&mut self, addr: u16, val: u8) {
+        let bank = self.chr_bank(addr);
+        self.banks.store_chr(bank, addr, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn reset(&mut self) {
+        self.irq = VrcIrq::new();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.banks.save_state(state);
+        state.write_bytes(&self.prg_banks);
+        state.write_bool(self.prg_swap);
+        for bank in self.chr_banks.iter() {
+            state.write_u16(*bank);
+        }
+        state.write_u8(mirroring_bits(self.mirroring));
+        state.write_u8(self.latch);
+        self.irq.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.banks.load_state(state);
+        state.read_bytes_into(&mut self.prg_banks);
+        self.prg_swap = state.read_bool();
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.read_u16();
+        }
+        self.mirroring = mirroring_from_bits(state.read_u8());
+        self.latch = state.read_u8();
+        self.irq.load_state(state);
+    }
+}
+
+struct Vrc6Pulse {
+    mode: bool,
+    duty: u8,
+    volume: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    // counts down through the 16 steps of the waveform
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn new() -> Vrc6Pulse {
+        Vrc6Pulse {
+            mode: false,
+            duty: 0,
+            volume: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 15,
+        }
+    }
+
+    fn write(&mut self, register: u16, val: u8) {
+        match register & 3 {
+            0 => {
+                self.mode = val & 0x80 != 0;
+                self.duty = (val >> 4) & 7;
+                self.volume = val & 0x0f;
+            },
+            1 => self.period = (self.period & 0xf00) | val as u16,
+            _ => {
+                self.period = (self.period & 0xff) | ((val & 0x0f) as u16) << 8;
+                self.enabled = val & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0f;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // the mode bit turns it into a constant volume, which games use for digitized sound
+    fn output(&self) -> u8 {
+        if self.enabled && (self.mode || self.step <= self.duty) { self.volume } else { 0 }
+    }
+}
+
+impl SaveState for Vrc6Pulse {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.mode);
+        state.write_u8(self.duty);
+        state.write_u8(self.volume);
+        state.write_u16(self.period);
+        state.write_bool(self.enabled);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.mode = state.read_bool();
+        self.duty = state.read_u8();
+        self.volume = state.read_u8();
+        self.period = state.read_u16();
+        self.enabled = state.read_bool();
+        self.timer = state.read_u16();
+        self.step = state.read_u8();
+    }
+}
+
+struct Vrc6Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Sawtooth {
+    fn new() -> Vrc6Sawtooth {
+        Vrc6Sawtooth {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, register: u16, val: u8) {
+        match register & 3 {
+            0 => self.rate = val & 0x3f,
+            1 => self.period = (self.period & 0xf00) | val as u16,
+            _ => {
+                self.period = (self.period & 0xff) | ((val & 0x0f) as u16) << 8;
+                self.enabled = val & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    // the rate is added on every other clock
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+
+            if self.step == SAWTOOTH_STEPS {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step % 2 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+impl SaveState for Vrc6Sawtooth {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rate);
+        state.write_u16(self.period);
+        state.write_bool(self.enabled);
+        state.write_u16(self.timer);
+        state.write_u8(self.step);
+        state.write_u8(self.accumulator);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.rate = state.read_u8();
+        self.period = state.read_u16();
+        self.enabled = state.read_bool();
+        self.timer = state.read_u16();
+        self.step = state.read_u8();
+        self.accumulator = state.read_u8();
+    }
+}
+
+// mappers 24 and 26, which only differ in A0 and A1 being swapped
+pub struct Vrc6 {
+    banks: Banks,
+    swapped_lines: bool,
+
+    prg_16k_bank: u8,
+    prg_8k_bank: u8,
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+
+    pulse1: Vrc6Pulse,
+    pulse2: Vrc6Pulse,
+    sawtooth: Vrc6Sawtooth,
+    audio_halted: bool,
+    // the frequency control register can make every channel run 16 or 256 times faster
+    frequency_shift: u8,
+}
+
+impl Vrc6 {
+    pub fn new(rom: Box<Rom>) -> Vrc6 {
+        let swapped_lines = rom.mapper == 26;
+
+        Vrc6 {
+            banks: Banks::new(rom),
+            swapped_lines: swapped_lines,
+
+            prg_16k_bank: 0,
+            prg_8k_bank: 0,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            prg_ram_enabled: false,
+            irq: VrcIrq::new(),
+
+            pulse1: Vrc6Pulse::new(),
+            pulse2: Vrc6Pulse::new(),
+            sawtooth: Vrc6Sawtooth::new(),
+            audio_halted: false,
+            frequency_shift: 0,
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        if self.swapped_lines {
+            (addr & 0xf000) | (addr & 1) << 1 | (addr & 2) >> 1
+        } else {
+            addr & 0xf003
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn load_byte_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000 ... 0x7fff => self.banks.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)],
+            0x8000 ... 0xbfff => {
+                let bank = self.prg_16k_bank as usize * 2 + (addr as usize - 0x8000) / PRG_BANK_SIZE;
+                self.banks.load_prg(bank, addr)
+            },
+            0xc000 ... 0xdfff => self.banks.load_prg(self.prg_8k_bank as usize, addr),
+            0xe000 ... 0xffff => {
+                let last = self.banks.last_prg_bank();
+                self.banks.load_prg(last, addr)
+            },
+            _ => 0
+        }
+    }
+
+    fn store_byte_prg(&mut self, addr: u16, val: u8) {
+        if addr < 0x8000 {
+            if self.prg_ram_enabled {
+                self.banks.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)] = val;
+            }
+            return;
+        }
+
+        let register = self.register(addr);
+        match register {
+            0x8000 ... 0x8003 => self.prg_16k_bank = val & 0x0f,
+            0x9000 ... 0x9002 => self.pulse1.write(register, val),
+            0x9003 => {
+                self.audio_halted = val & 1 != 0;
+                self.frequency_shift = if val & 4 != 0 { 8 } else if val & 2 != 0 { 4 } else { 0 };
+            },
+            0xa000 ... 0xa002 => self.pulse2.write(register, val),
+            0xb000 ... 0xb002 => self.sawtooth.write(register, val),
+            0xb003 => {
+                // games only ever use the plain 1k chr banking mode, so only mirroring and prg ram are handled
+                self.mirroring = mirroring_from_bits(val >> 2);
+                self.prg_ram_enabled = val & 0x80 != 0;
+            },
+            0xc000 ... 0xc003 => self.prg_8k_bank = val & 0x1f,
+            0xd000 ... 0xe003 => {
+                let bank = ((register - 0xd000) >> 12) as usize * 4 + (register & 3) as usize;
+                self.chr_banks[bank] = val;
+            },
+            0xf000 => self.irq.latch = val,
+            0xf001 => self.irq.write_control(val),
+            0xf002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000 || addr >= 0x6000 && self.prg_ram_enabled
+    }
+
+    fn load_byte_chr(&mut self, addr: u16) -> u8 {
+        self.banks.load_chr(self.chr_banks[(addr as usize >> 10) & 7] as usize, addr)
+    }
+    fn store_byte_chr(&mut self, addr: u16, val: u8) {
+        let bank = self.chr_banks[(addr as usize >> 10) & 7] as usize;
+        self.banks.store_chr(bank, addr, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock();
+
+        if !self.audio_halted {
+            self.pulse1.clock(self.frequency_shift);
+            self.pulse2.clock(self.frequency_shift);
+            self.sawtooth.clock(self.frequency_shift);
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        let output = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+        output as f32 * VRC6_VOLUME
+    }
+
+    fn reset(&mut self) {
+        self.irq = VrcIrq::new();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.banks.save_state(state);
+        state.write_u8(self.prg_16k_bank);
+        state.write_u8(self.prg_8k_bank);
+        state.write_bytes(&self.chr_banks);
+        state.write_u8(mirroring_bits(self.mirroring));
+        state.write_bool(self.prg_ram_enabled);
+        self.irq.save_state(state);
+
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.sawtooth.save_state(state);
+        state.write_bool(self.audio_halted);
+        state.write_u8(self.frequency_shift);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.banks.load_state(state);
+        self.prg_16k_bank = state.read_u8();
+        self.prg_8k_bank = state.read_u8();
+        state.read_bytes_into(&mut self.chr_banks);
+        self.mirroring = mirroring_from_bits(state.read_u8());
+        self.prg_ram_enabled = state.read_bool();
+        self.irq.load_state(state);
+
+        self.pulse1.load_state(state);
+        self.pulse2.load_state(state);
+        self.sawtooth.load_state(state);
+        self.audio_halted = state.read_bool();
+        self.frequency_shift = state.read_u8();
+    }
+}
+
+// mapper 85. the opll fm synth at $9010 and $9030 isn't emulated yet, so vrc7 games play without music
+pub struct Vrc7 {
+    banks: Banks,
+    // the cpu address line that selects the second register at each address, A4 on vrc7a and A3 on vrc7b
+    register_line: u16,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+}
+
+impl Vrc7 {
+    pub fn new(rom: Box<Rom>) -> Vrc7 {
+        let register_line = match rom.submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18
+        };
+
+        Vrc7 {
+            banks: Banks::new(rom),
+            register_line: register_line,
+
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            prg_ram_enabled: false,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        (addr & 0xf000) | if addr & self.register_line != 0 { 0x10 } else { 0 }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn load_byte_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000 ... 0x7fff => self.banks.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)],
+            0x8000 ... 0xdfff => {
+                let bank = self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] as usize;
+                self.banks.load_prg(bank, addr)
+            },
+            0xe000 ... 0xffff => {
+                let last = self.banks.last_prg_bank();
+                self.banks.load_prg(last, addr)
+            },
+            _ => 0
+        }
+    }
+
+    fn store_byte_prg(&mut self, addr: u16, val: u8) {
+        if addr < 0x8000 {
+            if self.prg_ram_enabled {
+                self.banks.prg_ram[addr as usize & (PRG_RAM_SIZE - 1)] = val;
+            }
+            return;
+        }
+
+        let register = self.register(addr);
+        match register {
+            0x8000 => self.prg_banks[0] = val & 0x3f,
+            0x8010 => self.prg_banks[1] = val & 0x3f,
+            0x9000 => self.prg_banks[2] = val & 0x3f,
+            0xa000 ... 0xd010 => {
+                let bank = ((register - 0xa000) >> 12) as usize * 2 + (register >> 4 & 1) as usize;
+                self.chr_banks[bank] = val;
+            },
+            0xe000 => {
+                self.mirroring = mirroring_from_bits(val);
+                self.prg_ram_enabled = val & 0x80 != 0;
+            },
+            0xe010 => self.irq.latch = val,
+            0xf000 => self.irq.write_control(val),
+            0xf010 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000 || addr >= 0x6000 && self.prg_ram_enabled
+    }
+
+    fn load_byte_chr(&mut self, addr: u16) -> u8 {
+        self.banks.load_chr(self.chr_banks[(addr as usize >> 10) & 7] as usize, addr)
+    }
+    fn store_byte_chr(&mut self, addr: u16, val: u8) {
+        let bank = self.chr_banks[(addr as usize >> 10) & 7] as usize;
+        self.banks.store_chr(bank, addr, val);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn reset(&mut self) {
+        self.irq = VrcIrq::new();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.banks.save_state(state);
+        state.write_bytes(&self.prg_banks);
+        state.write_bytes(&self.chr_banks);
+        state.write_u8(mirroring_bits(self.mirroring));
+        state.write_bool(self.prg_ram_enabled);
+        self.irq.save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.banks.load_state(state);
+        state.read_bytes_into(&mut self.prg_banks);
+        state.read_bytes_into(&mut self.chr_banks);
+        self.mirroring = mirroring_from_bits(state.read_u8());
+        self.prg_ram_enabled = state.read_bool();
+        self.irq.load_state(state);
+    }
+}
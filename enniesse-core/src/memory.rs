@@ -1,9 +1,8 @@
-use rom::Rom;
-use mapper;
 use mapper::Mapper;
 use apu::Apu;
 use ppu::Ppu;
 use input::Input;
+use state::{SaveState, StateWriter, StateReader};
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -25,6 +24,9 @@ pub const APU_IO_SHARED_REG: u16 = 0x4017;
 const CART_MAPPER_START: u16     = 0x4020;
 const CART_MAPPER_END: u16       = 0xffff;
 
+// bits of $4016/$4017 driven by the controllers and expansion port
+const INPUT_BITS_MASK: u8 = 0x1f;
+
 pub trait Memory { 
     fn load_byte(&mut self, addr: u16) -> u8;
     fn load_word(&mut self, addr: u16) -> u16 {
@@ -46,12 +48,13 @@ pub struct MemoryInterface {
     pub mapper: Rc<RefCell<Box<Mapper>>>,
     pub apu: Apu,
     pub ppu: Ppu,
-    pub input: Input
+    pub input: Input,
+    // the last value driven on the cpu data bus, read back from anything that doesn't drive it
+    pub open_bus: u8
 }
 
 impl MemoryInterface {
-    pub fn new(rom: Box<Rom>) -> MemoryInterface {
-        let mapper = mapper::load_mapper(rom);
+    pub fn new(mapper: Box<Mapper>) -> MemoryInterface {
         // Rc allows sharing the pointer, RefCell allows mutability
         let shared_mapper = Rc::new(RefCell::new(mapper));
         let ppu = Ppu::new(shared_mapper.clone());
@@ -62,29 +65,68 @@ impl MemoryInterface {
             mapper: shared_mapper,
             apu: apu,
             ppu: ppu,
-            input: Input::new()
+            input: Input::new(),
+            open_bus: 0
         }
     }
 }
 
+impl MemoryInterface {
+    // the reset line is connected to the ppu and apu, and some cartridges watch for it as well
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        self.apu.reset();
+        self.mapper.borrow_mut().reset();
+    }
+
+    pub fn power_cycle(&mut self, ram_pattern: RamPattern) {
+        self.ram.fill(ram_pattern);
+        self.ppu.power_cycle();
+        self.apu.power_cycle();
+        self.input = Input::new();
+        self.open_bus = 0;
+        self.mapper.borrow_mut().reset();
+    }
+}
+
 impl Memory for MemoryInterface {
     fn load_byte(&mut self, addr: u16) -> u8 {
-        match addr {
+        let val = match addr {
             RAM_START ... RAM_END => self.ram.load_byte(addr),
             PPU_REG_START ... PPU_REG_END => self.ppu.load_byte(addr),
-            APU_REG_START ... APU_REG_END => self.apu.load_byte(addr),
-            APU_STATUS_REG => self.apu.load_byte(addr),
-            IO_REG => self.input.load_byte(addr),
-            APU_IO_SHARED_REG => self.input.load_byte(addr), // $4017 on the APU is write only, so we only need to load from IO
-            CART_MAPPER_START ... CART_MAPPER_END => self.mapper.borrow_mut().load_byte_prg(addr),
-            _ => panic!("Read address out of range: {:X}", addr)
-        }
+            APU_REG_START ... APU_REG_END => self.open_bus, // write only
+            PPU_OAM_DMA => self.open_bus, // write only
+            APU_STATUS_REG => {
+                // $4015 is read inside the cpu, so bit 5 is open bus and the bus itself keeps its value
+                return self.apu.load_byte(addr) | self.open_bus & 0x20;
+            },
+            // the controller ports only drive the low bits
+            IO_REG => self.input.load_byte(addr) | self.open_bus & !INPUT_BITS_MASK,
+            APU_IO_SHARED_REG => self.input.load_byte(addr) | self.open_bus & !INPUT_BITS_MASK, // $4017 on the APU is write only, so we only need to load from IO
+            CART_MAPPER_START ... CART_MAPPER_END => {
+                let mut mapper = self.mapper.borrow_mut();
+                if mapper.is_prg_mapped(addr) {
+                    mapper.load_byte_prg(addr)
+                } else {
+                    self.open_bus
+                }
+            },
+            // $4018-$401f is only used in test mode
+            _ => self.open_bus
+        };
+
+        self.open_bus = val;
+        val
     }
     
     fn store_byte(&mut self, addr: u16, val: u8) {
+        self.open_bus = val;
         match addr {
             RAM_START ... RAM_END => self.ram.store_byte(addr, val),
-            PPU_REG_START ... PPU_REG_END => self.ppu.store_byte(addr, val),
+            PPU_REG_START ... PPU_REG_END => {
+                self.mapper.borrow_mut().ppu_register_written(addr, val);
+                self.ppu.store_byte(addr, val);
+            },
             APU_REG_START ... APU_REG_END => self.apu.store_byte(addr, val),
             APU_STATUS_REG => self.apu.store_byte(addr, val),
             IO_REG => self.input.store_byte(addr, val),
@@ -93,11 +135,46 @@ impl Memory for MemoryInterface {
                 self.input.store_byte(addr, val);
             },
             CART_MAPPER_START ... CART_MAPPER_END => self.mapper.borrow_mut().store_byte_prg(addr, val),
-            _ => panic!("Write address out of range: {:X}", addr)
+            _ => {}
         }
     }
 }
 
+impl SaveState for MemoryInterface {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram.ram);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+        self.input.save_state(state);
+        state.write_u8(self.open_bus);
+        self.mapper.borrow().save_state(state);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        state.read_bytes_into(&mut self.ram.ram);
+        self.ppu.load_state(state);
+        self.apu.load_state(state);
+        self.input.load_state(state);
+        self.open_bus = state.read_u8();
+        self.mapper.borrow_mut().load_state(state);
+    }
+}
+
+// contents of ram at power on. real hardware is somewhat random, so this is configurable
+// since some games (and movies) depend on it
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RamPattern {
+    Zeros,
+    Ones,
+    // pseudo-random bytes from the given seed
+    Random(u64)
+}
+
+impl Default for RamPattern {
+    fn default() -> RamPattern {
+        RamPattern::Zeros
+    }
+}
+
 pub struct Ram {
     pub ram: [u8; RAM_SIZE as usize]
 }
@@ -108,6 +185,25 @@ impl Ram {
             ram: [0; RAM_SIZE as usize]
         }
     }
+
+    pub fn fill(&mut self, pattern: RamPattern) {
+        match pattern {
+            RamPattern::Zeros => self.ram = [0x00; RAM_SIZE as usize],
+            RamPattern::Ones => self.ram = [0xff; RAM_SIZE as usize],
+            RamPattern::Random(seed) => {
+                // splitmix64, which takes any seed including 0 and gives each one a different sequence
+                let mut state = seed;
+                for byte in self.ram.iter_mut() {
+                    state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                    let mut x = state;
+                    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    x ^= x >> 31;
+                    *byte = (x >> 32) as u8;
+                }
+            }
+        }
+    }
 }
 
 impl Memory for Ram {
@@ -0,0 +1,327 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// fm2 frame commands
+pub const COMMAND_RESET: u8 = 1 << 0;
+pub const COMMAND_POWER: u8 = 1 << 1;
+pub const COMMAND_FDS_INSERT: u8 = 1 << 2;
+pub const COMMAND_FDS_SELECT: u8 = 1 << 3;
+
+const FM2_VERSION: u32 = 3;
+const FM2_PORT_NONE: u8 = 0;
+const FM2_PORT_GAMEPAD: u8 = 1;
+
+// fm2 writes the buttons as RLDUTSBA, which is the input port state from bit 7 down to bit 0
+const FM2_BUTTONS: &'static [u8; 8] = b"RLDUTSBA";
+
+const BASE64_CHARS: &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub ports: [u8; 2]
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MovieStart {
+    PowerOn,
+    SaveState(Vec<u8>)
+}
+
+#[derive(Clone, Debug)]
+pub struct Movie {
+    pub start: MovieStart,
+    pub frames: Vec<MovieFrame>,
+
+    pub rerecord_count: u32,
+    pub pal: bool,
+    pub rom_filename: String,
+    pub rom_checksum: String,
+    pub guid: String,
+    pub comments: Vec<String>
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    Parse(usize, String),
+    Unsupported(String)
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::Io(ref e) => write!(f, "{}", e),
+            MovieError::Parse(line, ref message) => write!(f, "Line {}: {}", line, message),
+            MovieError::Unsupported(ref message) => write!(f, "Unsupported movie: {}", message)
+        }
+    }
+}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> MovieError {
+        MovieError::Io(e)
+    }
+}
+
+impl Movie {
+    pub fn new(start: MovieStart) -> Movie {
+        Movie {
+            start: start,
+            frames: Vec::new(),
+            rerecord_count: 0,
+            pal: false,
+            rom_filename: String::new(),
+            rom_checksum: String::new(),
+            guid: generate_guid(),
+            comments: Vec::new()
+        }
+    }
+
+    pub fn load_fm2<P: AsRef<Path>>(path: P) -> Result<Movie, MovieError> {
+        let file = fs::File::open(path)?;
+
+        Movie::from_fm2(BufReader::new(file))
+    }
+
+    pub fn save_fm2<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = fs::File::create(path)?;
+
+        self.write_fm2(io::BufWriter::new(file))
+    }
+
+    pub fn from_fm2<R: BufRead>(reader: R) -> Result<Movie, MovieError> {
+        let mut movie = Movie::new(MovieStart::PowerOn);
+        movie.guid = String::new();
+
+        let mut ports = [FM2_PORT_GAMEPAD, FM2_PORT_GAMEPAD];
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line_number = index + 1;
+
+            if line.starts_with('|') {
+                let frame = parse_fm2_frame(&line, &ports).map_err(|e| MovieError::Parse(line_number, e))?;
+                movie.frames.push(frame);
+                continue;
+            }
+
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = match line.find(' ') {
+                Some(split) => (&line[..split], line[split + 1..].trim()),
+                None => (line, "")
+            };
+
+            let parse_error = || MovieError::Parse(line_number, format!("Invalid value for {}: {}", key, value));
+
+            match key {
+                "version" => {
+                    if value != FM2_VERSION.to_string() {
+                        return Err(MovieError::Unsupported(format!("fm2 version {}", value)));
+                    }
+                },
+                "binary" => {
+                    if value != "0" {
+                        return Err(MovieError::Unsupported("binary fm2 input log".to_string()));
+                    }
+                },
+                "fourscore" => {
+                    if value != "0" {
+                        return Err(MovieError::Unsupported("four score input".to_string()));
+                    }
+                },
+                "port0" | "port1" => {
+                    let port_type = value.parse::<u8>().map_err(|_| parse_error())?;
+                    if port_type != FM2_PORT_NONE && port_type != FM2_PORT_GAMEPAD {
+                        return Err(MovieError::Unsupported(format!("{} device type {}", key, port_type)));
+                    }
+                    ports[(key == "port1") as usize] = port_type;
+                },
+                "port2" => {
+                    if value != "0" {
+                        return Err(MovieError::Unsupported(format!("expansion port device type {}", value)));
+                    }
+                },
+                "rerecordCount" => movie.rerecord_count = value.parse().map_err(|_| parse_error())?,
+                "palFlag" => movie.pal = value == "1",
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => movie.rom_checksum = value.to_string(),
+                "guid" => movie.guid = value.to_string(),
+                "comment" => movie.comments.push(value.to_string()),
+                "savestate" => {
+                    let data = if value.starts_with("base64:") {
+                        base64_decode(&value[7..])
+                    } else {
+                        None
+                    };
+                    let data = data.ok_or_else(parse_error)?;
+                    movie.start = MovieStart::SaveState(data);
+                },
+                // remaining keys (emuVersion, NewPPU, FDS, etc) don't affect playback
+                _ => {}
+            }
+        }
+
+        Ok(movie)
+    }
+
+    pub fn write_fm2<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "version {}", FM2_VERSION)?;
+        writeln!(writer, "emuVersion 22020")?;
+        writeln!(writer, "rerecordCount {}", self.rerecord_count)?;
+        writeln!(writer, "palFlag {}", self.pal as u8)?;
+        writeln!(writer, "romFilename {}", self.rom_filename)?;
+        if !self.rom_checksum.is_empty() {
+            writeln!(writer, "romChecksum {}", self.rom_checksum)?;
+        }
+        writeln!(writer, "guid {}", self.guid)?;
+        writeln!(writer, "fourscore 0")?;
+        writeln!(writer, "microphone 0")?;
+        writeln!(writer, "port0 {}", FM2_PORT_GAMEPAD)?;
+        writeln!(writer, "port1 {}", FM2_PORT_GAMEPAD)?;
+        writeln!(writer, "port2 {}", FM2_PORT_NONE)?;
+        writeln!(writer, "FDS 0")?;
+        writeln!(writer, "NewPPU 0")?;
+        for comment in &self.comments {
+            writeln!(writer, "comment {}", comment)?;
+        }
+        if let MovieStart::SaveState(ref data) = self.start {
+            writeln!(writer, "savestate base64:{}", base64_encode(data))?;
+        }
+
+        for frame in &self.frames {
+            writeln!(writer, "|{}|{}|{}||", frame.commands, fm2_buttons(frame.ports[0]), fm2_buttons(frame.ports[1]))?;
+        }
+
+        writer.flush()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    Finished
+}
+
+// an active movie and where the emulator is in it
+#[derive(Debug)]
+pub struct MovieSession {
+    pub movie: Movie,
+    pub mode: MovieMode,
+
+    // frame count of the emulator when the movie started
+    pub start_frame: u64,
+    // commands issued during the current frame that still need to be recorded
+    pub pending_commands: u8
+}
+
+impl MovieSession {
+    pub fn new(movie: Movie, mode: MovieMode, start_frame: u64) -> MovieSession {
+        MovieSession {
+            movie: movie,
+            mode: mode,
+            start_frame: start_frame,
+            pending_commands: 0
+        }
+    }
+}
+
+fn parse_fm2_frame(line: &str, ports: &[u8; 2]) -> Result<MovieFrame, String> {
+    // |commands|port0|port1|port2|
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 5 {
+        return Err(format!("Invalid input log line: {}", line));
+    }
+
+    let mut frame = MovieFrame::default();
+    frame.commands = fields[1].trim().parse().map_err(|_| format!("Invalid commands: {}", fields[1]))?;
+
+    for port in 0 .. 2 {
+        let buttons = fields[port + 2];
+        if ports[port] == FM2_PORT_NONE {
+            continue;
+        }
+
+        if buttons.len() != FM2_BUTTONS.len() {
+            return Err(format!("Invalid gamepad input: {}", buttons));
+        }
+
+        for (i, c) in buttons.bytes().enumerate() {
+            if c != b'.' && c != b' ' {
+                frame.ports[port] |= 1 << (7 - i);
+            }
+        }
+    }
+
+    Ok(frame)
+}
+
+fn fm2_buttons(state: u8) -> String {
+    FM2_BUTTONS.iter().enumerate().map(|(i, &c)| {
+        if state & (1 << (7 - i)) != 0 { c as char } else { '.' }
+    }).collect()
+}
+
+fn generate_guid() -> String {
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() ^ (d.subsec_nanos() as u64) << 20).unwrap_or(0);
+
+    // xorshift is plenty for an identifier
+    let mut x = seed | 1;
+    let mut next = || {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    };
+
+    let a = next();
+    let b = next();
+    format!("{:08X}-{:04X}-{:04X}-{:04X}-{:012X}", a as u32, (a >> 32) as u16, (a >> 48) as u16, b as u16, (b >> 16) & 0xffff_ffff_ffff)
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity((data.len() + 2) / 3 * 4);
+
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0 .. 4 {
+            if i <= chunk.len() {
+                result.push(BASE64_CHARS[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+
+    result
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+
+    for c in text.bytes().filter(|&c| c != b'=') {
+        let val = BASE64_CHARS.iter().position(|&b| b == c)? as u32;
+        bits = (bits << 6) | val;
+        bit_count += 6;
+
+        if bit_count >= 8 {
+            bit_count -= 8;
+            result.push((bits >> bit_count) as u8);
+        }
+    }
+
+    Some(result)
+}
@@ -1,60 +1,388 @@
 use cpu::Cpu;
-use rom::Rom;
+use rom::{Rom, Region};
 use ppu;
+use mapper;
+use mapper::MapperError;
+use movie::{Movie, MovieStart, MovieMode, MovieSession, MovieFrame, COMMAND_RESET, COMMAND_POWER, COMMAND_FDS_INSERT, COMMAND_FDS_SELECT};
+use state::{SaveState, StateWriter, StateReader, StateError};
+use memory::RamPattern;
+
+// what happened during a call to run_frame
+pub struct FrameInfo<'a> {
+    // 9 bit palette indices, see ppu::palette::Palette
+    pub frame_buffer: &'a [u16],
+    // audio at apu::SAMPLE_RATE
+    pub audio_samples: &'a [f32],
+    // cpu cycles run
+    pub cycles: u64,
+}
 
 #[derive(Debug)]
 pub struct Nes {
-    pub cpu: Cpu
+    pub cpu: Cpu,
+
+    // number of frames emulated
+    pub frame: u64,
+
+    // total cpu cycles run
+    pub cycles: u64,
+
+    pub region: Region,
+
+    // what internal ram is filled with on power cycle
+    pub ram_pattern: RamPattern,
+
+    // vblank started during the last instruction, so the nmi is taken before the next one
+    nmi_pending: bool,
+
+    movie: Option<MovieSession>
 }
 
 impl Nes {
-    pub fn new(rom: Box<Rom>) -> Nes {
-        let cpu = Cpu::new(rom);
-        
-        Nes {
-            cpu: cpu
-        }
+    pub fn new(rom: Box<Rom>) -> Result<Nes, MapperError> {
+        let region = rom.region;
+        let mut cpu = Cpu::new(mapper::load_mapper(rom)?);
+        cpu.memory_interface.apu.set_region(region);
+
+        Ok(Nes {
+            cpu: cpu,
+            frame: 0,
+            cycles: 0,
+            region: region,
+            ram_pattern: RamPattern::default(),
+            nmi_pending: false,
+            movie: None
+        })
     }
-    
-    pub fn power_on(&mut self) {
+
+    // soft reset, as if the reset button was pressed
+    pub fn reset(&mut self) {
+        self.cpu.memory_interface.reset();
         self.cpu.reset();
+        self.nmi_pending = false;
+
+        self.record_movie_command(COMMAND_RESET);
+    }
+
+    // turns the console off and back on
+    pub fn power_cycle(&mut self) {
+        let ram_pattern = self.ram_pattern;
+        self.cpu.memory_interface.power_cycle(ram_pattern);
+        self.cpu.power_cycle();
+        self.nmi_pending = false;
+
+        self.record_movie_command(COMMAND_POWER);
+    }
+
+    #[deprecated(note = "use power_cycle")]
+    pub fn power_on(&mut self) {
+        self.power_cycle();
+    }
+
+    // famicom disk system
+
+    pub fn disk_side_count(&self) -> usize {
+        self.cpu.memory_interface.mapper.borrow().disk_side_count()
+    }
+
+    // the selected side, and whether it's in the drive
+    pub fn disk_side(&self) -> (usize, bool) {
+        self.cpu.memory_interface.mapper.borrow().disk_side()
+    }
+
+    pub fn insert_or_eject_disk(&mut self) {
+        if self.disk_side_count() == 0 {
+            return;
+        }
+
+        let (side, inserted) = self.disk_side();
+        self.cpu.memory_interface.mapper.borrow_mut().set_disk_side(side, !inserted);
+
+        self.record_movie_command(COMMAND_FDS_INSERT);
+    }
+
+    // moves on to the next side, ejecting the disk first if it's in the drive
+    pub fn select_disk_side(&mut self) {
+        if self.disk_side_count() == 0 {
+            return;
+        }
+
+        if self.disk_side().1 {
+            self.insert_or_eject_disk();
+        }
+
+        self.flip_disk();
+        self.record_movie_command(COMMAND_FDS_SELECT);
+    }
+
+    // like turning the disk over in your hand, so it only does anything while the disk is out of the drive
+    fn flip_disk(&mut self) {
+        let count = self.disk_side_count();
+        let (side, inserted) = self.disk_side();
+
+        if count > 0 && !inserted {
+            self.cpu.memory_interface.mapper.borrow_mut().set_disk_side((side + 1) % count, false);
+        }
+    }
+
+    // an ips patch of everything written to the disk, None if nothing was
+    pub fn disk_changes(&self) -> Option<Vec<u8>> {
+        self.cpu.memory_interface.mapper.borrow().disk_changes()
+    }
+
+    // runs until the next vblank. audio samples from previous calls are discarded
+    pub fn run_frame(&mut self) -> FrameInfo<'_> {
+        self.cpu.memory_interface.apu.clear_samples();
+
+        let cycles_start = self.cycles;
+        loop {
+            let (_, render) = self.step();
+            if render {
+                break;
+            }
+        }
+
+        FrameInfo {
+            frame_buffer: &self.cpu.memory_interface.ppu.display_buffer[..],
+            audio_samples: self.cpu.memory_interface.apu.samples(),
+            cycles: self.cycles - cycles_start,
+        }
+    }
+
+    // runs at least the given number of cpu cycles, stopping on an instruction boundary.
+    // returns the number of cycles actually run. audio samples build up until taken with take_audio_samples
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        let cycles_start = self.cycles;
+        while self.cycles - cycles_start < cycles {
+            self.step();
+        }
+
+        self.cycles - cycles_start
+    }
+
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        let samples = self.cpu.memory_interface.apu.samples().to_vec();
+        self.cpu.memory_interface.apu.clear_samples();
+
+        samples
     }
 
     pub fn step(&mut self) -> (u16, bool) {
         //self.cpu.trace_state();
         let cycle_start = self.cpu.cycle;
-        self.cpu.step();
+
+        // interrupts are polled between instructions. the irq line stays asserted until its source is
+        // acknowledged, and the cpu keeps taking it whenever the interrupt disable flag is clear
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.cpu.nmi();
+        } else if self.irq_line() && !self.cpu.reg_p.interrupt_disable {
+            self.cpu.irq();
+        } else {
+            self.cpu.step();
+        }
+
         let cycle_end = self.cpu.cycle;
 
         let mut render = false;
 
         if cycle_end >= ppu::CPU_CYCLES_PER_SCANLINE {
             let result = self.cpu.memory_interface.ppu.run(false);
-            
+
             if result.vblank {
-                self.cpu.nmi();
-            }
-            
-            if result.mapper_irq {
-                self.cpu.irq();
+                self.nmi_pending = true;
             }
 
             render = result.render_frame;
-            
+
             self.cpu.cycle = cycle_end % ppu::CPU_CYCLES_PER_SCANLINE;
         } else if cycle_end >= (ppu::SCREEN_WIDTH as u16 / 3) && self.cpu.memory_interface.ppu.cycle == 0 {
             // 3 ppu cycles per cpu cycle, so 256 ppu cycles / 3 (~85 cpu cycles) for the visible pixels
             self.cpu.memory_interface.ppu.run(true);
         }
 
+        self.cycles += (cycle_end - cycle_start) as u64;
+
         for _ in 0 .. cycle_end - cycle_start {
+            self.cpu.memory_interface.mapper.borrow_mut().clock_cpu();
             self.cpu.memory_interface.apu.step();
+        }
 
-            if self.cpu.memory_interface.apu.dmc_interrupt || self.cpu.memory_interface.apu.frame_interrupt {
-                self.cpu.irq();
-            }
+        if render {
+            self.frame += 1;
+            self.update_movie();
         }
 
         (cycle_end, render)
     }
-}
\ No newline at end of file
+
+    fn irq_line(&self) -> bool {
+        let memory = &self.cpu.memory_interface;
+        memory.apu.dmc_interrupt || memory.apu.frame_interrupt || memory.mapper.borrow().irq_pending()
+    }
+
+    // save states
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        self.save_state_to(&mut state);
+
+        state.into_inner()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data)?;
+        // a state that turns out to be truncated has already been partly read in, so put things back
+        let backup = self.save_state();
+        self.load_state_from(&mut state);
+
+        if state.is_overrun() {
+            let mut backup = StateReader::new(&backup)?;
+            self.load_state_from(&mut backup);
+            return Err(StateError::UnexpectedEnd);
+        }
+
+        // loading a state while recording rewinds the movie to that point
+        let frame = self.frame;
+        if let Some(ref mut session) = self.movie {
+            let position = frame.saturating_sub(session.start_frame) as usize;
+
+            match session.mode {
+                MovieMode::Recording => {
+                    session.movie.frames.truncate(position);
+                    session.movie.rerecord_count += 1;
+                    session.pending_commands = 0;
+                },
+                MovieMode::Playing | MovieMode::Finished => {
+                    session.mode = if position < session.movie.frames.len() { MovieMode::Playing } else { MovieMode::Finished };
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // movies
+
+    // starts recording input, either after powering on or from the current state
+    pub fn record_movie(&mut self, from_power_on: bool) {
+        self.movie = None;
+
+        let start = if from_power_on {
+            self.power_cycle();
+            MovieStart::PowerOn
+        } else {
+            MovieStart::SaveState(self.save_state())
+        };
+
+        let movie = Movie::new(start);
+        self.movie = Some(MovieSession::new(movie, MovieMode::Recording, self.frame));
+    }
+
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), StateError> {
+        self.movie = None;
+
+        match movie.start {
+            MovieStart::PowerOn => self.power_cycle(),
+            MovieStart::SaveState(ref data) => self.load_state(data)?
+        }
+
+        let frame = self.frame;
+        self.movie = Some(MovieSession::new(movie, MovieMode::Playing, frame));
+        self.apply_movie_frame(0);
+
+        Ok(())
+    }
+
+    // ends recording or playback, returning the movie
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.movie.take().map(|session| session.movie)
+    }
+
+    pub fn movie_mode(&self) -> Option<MovieMode> {
+        self.movie.as_ref().map(|session| session.mode)
+    }
+
+    // the frame within the movie that is currently being emulated
+    pub fn movie_frame(&self) -> Option<usize> {
+        self.movie.as_ref().map(|session| self.frame.saturating_sub(session.start_frame) as usize)
+    }
+
+    // called at the end of each frame. recording captures the input used for the frame that just finished,
+    // playback applies the input for the next one
+    fn update_movie(&mut self) {
+        let position = match self.movie {
+            Some(ref session) => self.frame.saturating_sub(session.start_frame) as usize,
+            None => return
+        };
+
+        let mode = self.movie.as_ref().unwrap().mode;
+        match mode {
+            MovieMode::Recording => {
+                let input = &self.cpu.memory_interface.input;
+                let session = self.movie.as_mut().unwrap();
+
+                let frame = MovieFrame {
+                    commands: session.pending_commands,
+                    ports: [input.port_state(0), input.port_state(1)]
+                };
+                session.pending_commands = 0;
+                session.movie.frames.push(frame);
+            },
+            MovieMode::Playing => self.apply_movie_frame(position),
+            MovieMode::Finished => {}
+        }
+    }
+
+    fn apply_movie_frame(&mut self, position: usize) {
+        let frame = match self.movie {
+            Some(ref mut session) => match session.movie.frames.get(position) {
+                Some(frame) => *frame,
+                None => {
+                    session.mode = MovieMode::Finished;
+                    return;
+                }
+            },
+            None => return
+        };
+
+        if frame.commands & COMMAND_POWER != 0 {
+            self.power_cycle();
+        } else if frame.commands & COMMAND_RESET != 0 {
+            self.reset();
+        }
+
+        // fceux does these in this order, so a disk can be ejected and flipped in one frame
+        if frame.commands & COMMAND_FDS_INSERT != 0 {
+            self.insert_or_eject_disk();
+        }
+        if frame.commands & COMMAND_FDS_SELECT != 0 {
+            self.flip_disk();
+        }
+
+        self.cpu.memory_interface.input.set_port_state(0, frame.ports[0]);
+        self.cpu.memory_interface.input.set_port_state(1, frame.ports[1]);
+    }
+
+    fn record_movie_command(&mut self, command: u8) {
+        if let Some(ref mut session) = self.movie {
+            if session.mode == MovieMode::Recording {
+                session.pending_commands |= command;
+            }
+        }
+    }
+
+    fn save_state_to(&self, state: &mut StateWriter) {
+        state.write_u64(self.frame);
+        state.write_u64(self.cycles);
+        state.write_bool(self.nmi_pending);
+        self.cpu.save_state(state);
+    }
+
+    fn load_state_from(&mut self, state: &mut StateReader) {
+        self.frame = state.read_u64();
+        self.cycles = state.read_u64();
+        self.nmi_pending = state.read_bool();
+        self.cpu.load_state(state);
+    }
+}
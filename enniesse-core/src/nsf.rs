@@ -0,0 +1,409 @@
+// nsf and nsfe music files, and a player that runs them on the cpu and apu without a game around them
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use cpu::Cpu;
+use mapper::NsfMapper;
+use apu;
+use rom::{Rom, Region};
+use memory::{Memory, RamPattern};
+
+const NSF_HEADER: [u8; 5] = *b"NESM\x1a";
+const NSF_HEADER_SIZE: usize = 0x80;
+const NSFE_HEADER: [u8; 4] = *b"NSFE";
+
+// bits of the expansion chip byte
+pub const EXPANSION_VRC6: u8 = 1 << 0;
+pub const EXPANSION_VRC7: u8 = 1 << 1;
+pub const EXPANSION_FDS: u8 = 1 << 2;
+pub const EXPANSION_MMC5: u8 = 1 << 3;
+pub const EXPANSION_N163: u8 = 1 << 4;
+pub const EXPANSION_SUNSOFT_5B: u8 = 1 << 5;
+
+// how often play is called when the file doesn't say, in microseconds
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+#[derive(Debug)]
+pub enum NsfError {
+    Io(io::Error),
+    InvalidHeader,
+    UnexpectedEnd,
+    MissingChunk(&'static str),
+    UnsupportedChunk(String)
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NsfError::Io(ref e) => write!(f, "{}", e),
+            NsfError::InvalidHeader => write!(f, "Not an nsf or nsfe file"),
+            NsfError::UnexpectedEnd => write!(f, "File is truncated"),
+            NsfError::MissingChunk(id) => write!(f, "Missing the {} chunk", id),
+            NsfError::UnsupportedChunk(ref id) => write!(f, "Unsupported required chunk {}", id)
+        }
+    }
+}
+
+impl From<io::Error> for NsfError {
+    fn from(e: io::Error) -> NsfError {
+        NsfError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Nsf {
+    pub track_count: usize,
+    // 0 based
+    pub starting_track: usize,
+
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+
+    // microseconds between calls to play
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    // initial values for $5ff8-$5fff, all 0 if the file doesn't bankswitch
+    pub banks: [u8; 8],
+    pub pal: bool,
+    // whether it plays on either region, in which case ntsc is used
+    pub dual_region: bool,
+    pub expansion: u8,
+
+    pub data: Vec<u8>,
+
+    // nsfe metadata, which can be shorter than the track count or missing altogether
+    pub track_labels: Vec<String>,
+    // in milliseconds, negative for tracks without a time
+    pub track_times: Vec<i32>,
+    pub track_fades: Vec<i32>,
+    // the order to play tracks in, empty to play them in order
+    pub playlist: Vec<u8>,
+}
+
+impl Nsf {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Nsf, NsfError> {
+        let mut file = File::open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        Nsf::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Nsf, NsfError> {
+        if data.starts_with(&NSF_HEADER) {
+            Nsf::from_nsf(data)
+        } else if data.starts_with(&NSFE_HEADER) {
+            let mut nsf = Nsf::default();
+            nsf.read_chunks(&data[NSFE_HEADER.len() ..], true)?;
+            Ok(nsf)
+        } else {
+            Err(NsfError::InvalidHeader)
+        }
+    }
+
+    pub fn is_nsf(data: &[u8]) -> bool {
+        data.starts_with(&NSF_HEADER) || data.starts_with(&NSFE_HEADER)
+    }
+
+    fn from_nsf(data: &[u8]) -> Result<Nsf, NsfError> {
+        if data.len() < NSF_HEADER_SIZE {
+            return Err(NsfError::UnexpectedEnd);
+        }
+
+        let word = |offset: usize| data[offset] as u16 | (data[offset + 1] as u16) << 8;
+
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&data[0x70 .. 0x78]);
+
+        // nsf2 files can have nsfe metadata after the program data, in which case its length is given
+        let version = data[5];
+        let data_length = data[0x7d] as usize | (data[0x7e] as usize) << 8 | (data[0x7f] as usize) << 16;
+        let data_end = if version >= 2 && data_length > 0 { NSF_HEADER_SIZE + data_length } else { data.len() };
+        if data_end > data.len() {
+            return Err(NsfError::UnexpectedEnd);
+        }
+
+        let mut nsf = Nsf {
+            track_count: data[6] as usize,
+            starting_track: (data[7] as usize).saturating_sub(1),
+            load_address: word(0x08),
+            init_address: word(0x0a),
+            play_address: word(0x0c),
+            title: header_string(&data[0x0e .. 0x2e]),
+            artist: header_string(&data[0x2e .. 0x4e]),
+            copyright: header_string(&data[0x4e .. 0x6e]),
+            ntsc_speed: word(0x6e),
+            pal_speed: word(0x78),
+            banks: banks,
+            pal: data[0x7a] & 1 != 0,
+            dual_region: data[0x7a] & 2 != 0,
+            expansion: data[0x7b],
+            data: data[NSF_HEADER_SIZE .. data_end].to_vec(),
+            .. Nsf::default()
+        };
+
+        if data_end < data.len() {
+            nsf.read_chunks(&data[data_end ..], false)?;
+        }
+
+        Ok(nsf)
+    }
+
+    // nsfe chunks, each a 4 byte length, a 4 byte id and the data. ids starting with a capital letter
+    // are needed to play the file, the rest can be skipped
+    fn read_chunks(&mut self, mut data: &[u8], full: bool) -> Result<(), NsfError> {
+        let mut has_info = !full;
+        let mut has_data = !full;
+
+        while data.len() >= 8 {
+            let length = data[0] as usize | (data[1] as usize) << 8 | (data[2] as usize) << 16 | (data[3] as usize) << 24;
+            let id = &data[4 .. 8];
+            let chunk = data.get(8 .. 8 + length).ok_or(NsfError::UnexpectedEnd)?;
+            data = &data[8 + length ..];
+
+            match id {
+                b"INFO" if full => {
+                    if chunk.len() < 9 {
+                        return Err(NsfError::UnexpectedEnd);
+                    }
+                    self.load_address = chunk[0] as u16 | (chunk[1] as u16) << 8;
+                    self.init_address = chunk[2] as u16 | (chunk[3] as u16) << 8;
+                    self.play_address = chunk[4] as u16 | (chunk[5] as u16) << 8;
+                    self.pal = chunk[6] & 1 != 0;
+                    self.dual_region = chunk[6] & 2 != 0;
+                    self.expansion = chunk[7];
+                    self.track_count = chunk[8] as usize;
+                    self.starting_track = chunk.get(9).cloned().unwrap_or(0) as usize;
+                    has_info = true;
+                },
+                b"DATA" if full => {
+                    self.data = chunk.to_vec();
+                    has_data = true;
+                },
+                b"BANK" if full => {
+                    let length = chunk.len().min(8);
+                    self.banks[.. length].copy_from_slice(&chunk[.. length]);
+                },
+                b"RATE" if full => {
+                    if chunk.len() >= 4 {
+                        self.ntsc_speed = chunk[0] as u16 | (chunk[1] as u16) << 8;
+                        self.pal_speed = chunk[2] as u16 | (chunk[3] as u16) << 8;
+                    }
+                },
+                b"NEND" => break,
+                b"auth" => {
+                    let mut strings = chunk.split(|&b| b == 0).map(|s| String::from_utf8_lossy(s).into_owned());
+                    self.title = strings.next().unwrap_or_default();
+                    self.artist = strings.next().unwrap_or_default();
+                    self.copyright = strings.next().unwrap_or_default();
+                },
+                b"tlbl" => {
+                    self.track_labels = chunk.split(|&b| b == 0).map(|s| String::from_utf8_lossy(s).into_owned()).collect();
+                    // the last label ends with a 0 as well
+                    self.track_labels.pop();
+                },
+                b"time" => self.track_times = chunk.chunks(4).filter(|c| c.len() == 4).map(read_i32).collect(),
+                b"fade" => self.track_fades = chunk.chunks(4).filter(|c| c.len() == 4).map(read_i32).collect(),
+                b"plst" => self.playlist = chunk.to_vec(),
+                _ if id[0].is_ascii_uppercase() => return Err(NsfError::UnsupportedChunk(String::from_utf8_lossy(id).into_owned())),
+                _ => {}
+            }
+        }
+
+        if !has_info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if !has_data {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+        Ok(())
+    }
+
+    pub fn is_bankswitched(&self) -> bool {
+        self.banks.iter().any(|&bank| bank != 0)
+    }
+
+    pub fn region(&self) -> Region {
+        if self.pal && !self.dual_region { Region::Pal } else { Region::Ntsc }
+    }
+
+    pub fn track_label(&self, track: usize) -> Option<&str> {
+        self.track_labels.get(track).map(|label| label.as_str()).filter(|label| !label.is_empty())
+    }
+
+    // in milliseconds
+    pub fn track_time(&self, track: usize) -> Option<u32> {
+        self.track_times.get(track).cloned().filter(|&time| time >= 0).map(|time| time as u32)
+    }
+
+    pub fn track_fade(&self, track: usize) -> Option<u32> {
+        self.track_fades.get(track).cloned().filter(|&fade| fade >= 0).map(|fade| fade as u32)
+    }
+
+    // the tracks in the order they should be played
+    pub fn track_order(&self) -> Vec<usize> {
+        let order: Vec<usize> = self.playlist.iter().map(|&track| track as usize).filter(|&track| track < self.track_count).collect();
+
+        if order.is_empty() { (0 .. self.track_count).collect() } else { order }
+    }
+}
+
+fn header_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[.. end]).into_owned()
+}
+
+fn read_i32(data: &[u8]) -> i32 {
+    (data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16 | (data[3] as u32) << 24) as i32
+}
+
+// where init and play return to. nothing is mapped here, so no real code ends up at it
+const RETURN_ADDRESS: u16 = 0x4100;
+
+pub struct NsfPlayer {
+    pub cpu: Cpu,
+    pub nsf: Nsf,
+    pub region: Region,
+
+    track: usize,
+    // cpu cycles between calls to play, and until the next one
+    play_period: f64,
+    play_countdown: f64,
+    // set while init or play is running
+    in_routine: bool,
+
+    // total cpu cycles run
+    pub cycles: u64,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf) -> NsfPlayer {
+        let region = nsf.region();
+        let mut cpu = Cpu::new(Box::new(NsfMapper::new(Box::new(Rom::from_nsf(nsf.clone())))));
+        cpu.memory_interface.apu.set_region(region);
+
+        let speed = match region {
+            Region::Ntsc => if nsf.ntsc_speed != 0 { nsf.ntsc_speed } else { DEFAULT_NTSC_SPEED },
+            _ => if nsf.pal_speed != 0 { nsf.pal_speed } else { DEFAULT_PAL_SPEED }
+        };
+        let play_period = speed as f64 * region.cpu_clock_rate() / 1_000_000.0;
+
+        let track = nsf.starting_track;
+        let mut player = NsfPlayer {
+            cpu: cpu,
+            nsf: nsf,
+            region: region,
+            track: track,
+            play_period: play_period,
+            play_countdown: play_period,
+            in_routine: false,
+            cycles: 0,
+        };
+        player.start_track(track);
+
+        player
+    }
+
+    pub fn track(&self) -> usize {
+        self.track
+    }
+
+    // resets everything the way the nsf spec asks for, then runs init for the track
+    pub fn start_track(&mut self, track: usize) {
+        self.track = track.min(self.nsf.track_count.saturating_sub(1));
+
+        self.cpu.memory_interface.power_cycle(RamPattern::Zeros);
+        self.cpu.power_cycle();
+        self.cpu.memory_interface.apu.clear_samples();
+
+        for addr in 0x4000 .. 0x4014 {
+            self.cpu.memory_interface.store_byte(addr, 0);
+        }
+        self.cpu.memory_interface.store_byte(0x4015, 0x00);
+        self.cpu.memory_interface.store_byte(0x4015, 0x0f);
+        self.cpu.memory_interface.store_byte(0x4017, 0x40);
+
+        self.cpu.reg_a = self.track as u8;
+        self.cpu.reg_x = (self.region != Region::Ntsc) as u8;
+        self.cpu.reg_sp = 0xfd;
+        self.cpu.call(self.nsf.init_address, RETURN_ADDRESS);
+
+        self.in_routine = true;
+        self.play_countdown = self.play_period;
+    }
+
+    // runs at least the given number of cpu cycles, calling play whenever it's due
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        let cycles_start = self.cycles;
+
+        while self.cycles - cycles_start < cycles {
+            let elapsed = if self.in_routine {
+                let cycle_start = self.cpu.cycle;
+                self.cpu.step();
+                let elapsed = self.cpu.cycle - cycle_start;
+
+                // keeps the parity the cpu uses for dma timing
+                self.cpu.cycle %= 2;
+                if self.cpu.reg_pc == RETURN_ADDRESS {
+                    self.in_routine = false;
+                }
+                elapsed
+            } else {
+                1
+            };
+
+            for _ in 0 .. elapsed {
+                self.cpu.memory_interface.mapper.borrow_mut().clock_cpu();
+                self.cpu.memory_interface.apu.step();
+            }
+            self.cycles += elapsed as u64;
+
+            // play is only called once the last call has returned, as hardware players do
+            self.play_countdown -= elapsed as f64;
+            if self.play_countdown <= 0.0 {
+                self.play_countdown += self.play_period;
+
+                if !self.in_routine {
+                    self.cpu.call(self.nsf.play_address, RETURN_ADDRESS);
+                    self.in_routine = true;
+                }
+            }
+        }
+
+        self.cycles - cycles_start
+    }
+
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        let samples = self.cpu.memory_interface.apu.samples().to_vec();
+        self.cpu.memory_interface.apu.clear_samples();
+
+        samples
+    }
+
+    // plays a track from the start for the given time, fading out over the end of it
+    pub fn render_track(&mut self, track: usize, length_ms: u32, fade_ms: u32) -> Vec<f32> {
+        self.start_track(track);
+
+        let cycles = (length_ms as f64 / 1000.0 * self.region.cpu_clock_rate()) as u64;
+        self.run_cycles(cycles);
+        let mut samples = self.take_audio_samples();
+
+        let fade_samples = (fade_ms as usize * apu::SAMPLE_RATE as usize / 1000).min(samples.len());
+        let fade_start = samples.len() - fade_samples;
+        for (i, sample) in samples[fade_start ..].iter_mut().enumerate() {
+            *sample *= 1.0 - i as f32 / fade_samples as f32;
+        }
+
+        samples
+    }
+}
@@ -0,0 +1,347 @@
+// ips, ups and bps patches for rom hacks and translations. ips is also how the changes games make to
+// fds disks are saved
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use hash;
+
+const IPS_HEADER: &'static [u8] = b"PATCH";
+const IPS_FOOTER: &'static [u8] = b"EOF";
+// a record can't start at this offset, it would read as the footer
+const IPS_EOF_OFFSET: usize = 0x454f46;
+const IPS_MAX_OFFSET: usize = 0xff_ffff;
+const IPS_MAX_RECORD: usize = 0xffff;
+
+const UPS_HEADER: &'static [u8] = b"UPS1";
+const BPS_HEADER: &'static [u8] = b"BPS1";
+// ups and bps both end with the crc32s of the source, the target and the rest of the patch
+const CHECKSUMS_SIZE: usize = 12;
+// well past the largest real rom, so a bad size in a ups or bps patch doesn't allocate whatever it says
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+// patches next to a rom with the same name are applied when it's loaded, in this order
+pub const PATCH_EXTENSIONS: [&'static str; 3] = ["ips", "ups", "bps"];
+
+#[derive(Debug)]
+pub enum PatchError {
+    Io(io::Error),
+    NotAPatch,
+    UnexpectedEnd,
+    TooLarge,
+    TargetTooLarge(usize),
+    Invalid,
+    WrongSource,
+    ChecksumMismatch
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::Io(ref e) => write!(f, "{}", e),
+            PatchError::NotAPatch => write!(f, "Not a patch file"),
+            PatchError::UnexpectedEnd => write!(f, "Patch file is truncated"),
+            PatchError::TooLarge => write!(f, "Too large for an ips patch"),
+            PatchError::TargetTooLarge(size) => write!(f, "Patch makes a {} byte rom, more than the {} MB limit", size, MAX_TARGET_SIZE >> 20),
+            PatchError::Invalid => write!(f, "Patch file is corrupt"),
+            PatchError::WrongSource => write!(f, "Patch is for a different rom"),
+            PatchError::ChecksumMismatch => write!(f, "Patched rom doesn't match the patch's checksum")
+        }
+    }
+}
+
+impl From<io::Error> for PatchError {
+    fn from(e: io::Error) -> PatchError {
+        PatchError::Io(e)
+    }
+}
+
+// an ips patch that turns original into modified, which can't be shorter
+pub fn create_ips(original: &[u8], modified: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if modified.len() > IPS_MAX_OFFSET + 1 {
+        return Err(PatchError::TooLarge);
+    }
+
+    let mut patch = IPS_HEADER.to_vec();
+    let differs = |i: usize| original.get(i) != Some(&modified[i]);
+
+    let mut i = 0;
+    while i < modified.len() {
+        if !differs(i) {
+            i += 1;
+            continue;
+        }
+
+        // an unchanged byte is added before a record that would start at the footer's offset
+        let start = if i == IPS_EOF_OFFSET { i - 1 } else { i };
+        let mut end = i;
+        while end < modified.len() && end - start < IPS_MAX_RECORD && differs(end) {
+            end += 1;
+        }
+
+        patch.extend_from_slice(&[(start >> 16) as u8, (start >> 8) as u8, start as u8]);
+        patch.extend_from_slice(&[((end - start) >> 8) as u8, (end - start) as u8]);
+        patch.extend_from_slice(&modified[start .. end]);
+
+        i = end;
+    }
+
+    patch.extend_from_slice(IPS_FOOTER);
+    Ok(patch)
+}
+
+// applies an ips, ups or bps patch, going by its header
+pub fn apply_patch(data: &mut Vec<u8>, patch: &[u8]) -> Result<(), PatchError> {
+    if patch.starts_with(IPS_HEADER) {
+        apply_ips(data, patch)
+    } else if patch.starts_with(UPS_HEADER) {
+        *data = apply_ups(data, patch)?;
+        Ok(())
+    } else if patch.starts_with(BPS_HEADER) {
+        *data = apply_bps(data, patch)?;
+        Ok(())
+    } else {
+        Err(PatchError::NotAPatch)
+    }
+}
+
+// a patch with the same name as the rom, if there is one
+pub fn patch_path<P: AsRef<Path>>(rom_path: P) -> Option<PathBuf> {
+    PATCH_EXTENSIONS.iter().map(|ext| rom_path.as_ref().with_extension(ext)).find(|path| path.is_file())
+}
+
+// applies the patch next to the rom if there is one, returning its path
+pub fn apply_adjacent<P: AsRef<Path>>(rom_path: P, data: &mut Vec<u8>) -> Result<Option<PathBuf>, PatchError> {
+    let path = match patch_path(rom_path) {
+        Some(path) => path,
+        None => return Ok(None)
+    };
+
+    apply_patch(data, &fs::read(&path)?)?;
+    Ok(Some(path))
+}
+
+// applies an ips patch, growing the data if the patch writes past its end
+pub fn apply_ips(data: &mut Vec<u8>, patch: &[u8]) -> Result<(), PatchError> {
+    if !patch.starts_with(IPS_HEADER) {
+        return Err(PatchError::NotAPatch);
+    }
+
+    let mut pos = IPS_HEADER.len();
+    loop {
+        if patch[pos ..].starts_with(IPS_FOOTER) {
+            // some patches follow the footer with a size to cut the data down to
+            if let Some(size) = patch.get(pos + 3 .. pos + 6) {
+                data.truncate((size[0] as usize) << 16 | (size[1] as usize) << 8 | size[2] as usize);
+            }
+            return Ok(());
+        }
+
+        let record = patch.get(pos .. pos + 5).ok_or(PatchError::UnexpectedEnd)?;
+        let offset = (record[0] as usize) << 16 | (record[1] as usize) << 8 | record[2] as usize;
+        let size = (record[3] as usize) << 8 | record[4] as usize;
+        pos += 5;
+
+        // a size of 0 is a run of one byte, given as a 2 byte length and the byte
+        if size == 0 {
+            let run = patch.get(pos .. pos + 3).ok_or(PatchError::UnexpectedEnd)?;
+            let length = (run[0] as usize) << 8 | run[1] as usize;
+            if data.len() < offset + length {
+                data.resize(offset + length, 0);
+            }
+            for byte in &mut data[offset .. offset + length] {
+                *byte = run[2];
+            }
+            pos += 3;
+            continue;
+        }
+
+        let bytes = patch.get(pos .. pos + size).ok_or(PatchError::UnexpectedEnd)?;
+        if data.len() < offset + size {
+            data.resize(offset + size, 0);
+        }
+        data[offset .. offset + size].copy_from_slice(bytes);
+        pos += size;
+    }
+}
+
+// ups patches xor the changes into the source, skipping over what's the same
+pub fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(UPS_HEADER) {
+        return Err(PatchError::NotAPatch);
+    }
+    let target_crc = check_patch(source, patch)?;
+
+    let mut reader = PatchReader { patch: &patch[.. patch.len() - CHECKSUMS_SIZE], pos: UPS_HEADER.len() };
+    let _source_size = reader.number()?;
+    let target_size = reader.number()?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::TargetTooLarge(target_size));
+    }
+
+    // anything past the end of the source xors with 0
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+
+    let mut pos = 0;
+    while !reader.is_empty() {
+        pos += reader.number()?;
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                pos += 1;
+                break;
+            }
+            *target.get_mut(pos).ok_or(PatchError::Invalid)? ^= byte;
+            pos += 1;
+        }
+    }
+
+    check_target(target, target_crc)
+}
+
+// bps patches build the target from runs copied out of the source, the patch and the target so far
+pub fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(BPS_HEADER) {
+        return Err(PatchError::NotAPatch);
+    }
+    let target_crc = check_patch(source, patch)?;
+
+    let mut reader = PatchReader { patch: &patch[.. patch.len() - CHECKSUMS_SIZE], pos: BPS_HEADER.len() };
+    let _source_size = reader.number()?;
+    let target_size = reader.number()?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::TargetTooLarge(target_size));
+    }
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+
+    while !reader.is_empty() {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+
+        // every action writes to the target, so one that goes past the size in the header is corrupt
+        let end = target.len().checked_add(length).ok_or(PatchError::Invalid)?;
+        if end > target_size {
+            return Err(PatchError::Invalid);
+        }
+
+        match action & 3 {
+            // from the source at the same position
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(source.get(start .. end).ok_or(PatchError::Invalid)?);
+            },
+            // from the patch
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            // from elsewhere in the source
+            2 => {
+                source_offset = relative_offset(source_offset, reader.number()?)?;
+                let source_end = source_offset.checked_add(length).ok_or(PatchError::Invalid)?;
+                target.extend_from_slice(source.get(source_offset .. source_end).ok_or(PatchError::Invalid)?);
+                source_offset = source_end;
+            },
+            // from earlier in the target, which can overlap what's being written
+            _ => {
+                target_offset = relative_offset(target_offset, reader.number()?)?;
+                for _ in 0 .. length {
+                    let byte = *target.get(target_offset).ok_or(PatchError::Invalid)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(PatchError::Invalid);
+    }
+    check_target(target, target_crc)
+}
+
+// checks the patch isn't damaged and is for this source, returning the crc the target should have
+fn check_patch(source: &[u8], patch: &[u8]) -> Result<u32, PatchError> {
+    if patch.len() < CHECKSUMS_SIZE + 4 {
+        return Err(PatchError::UnexpectedEnd);
+    }
+
+    let checksums = &patch[patch.len() - CHECKSUMS_SIZE ..];
+    let source_crc = u32_le(&checksums[0 .. 4]);
+    let target_crc = u32_le(&checksums[4 .. 8]);
+    if hash::crc32(&patch[.. patch.len() - 4]) != u32_le(&checksums[8 .. 12]) {
+        return Err(PatchError::Invalid);
+    }
+    if hash::crc32(source) != source_crc {
+        return Err(PatchError::WrongSource);
+    }
+
+    Ok(target_crc)
+}
+
+fn check_target(target: Vec<u8>, target_crc: u32) -> Result<Vec<u8>, PatchError> {
+    if hash::crc32(&target) != target_crc {
+        return Err(PatchError::ChecksumMismatch);
+    }
+
+    Ok(target)
+}
+
+// the sign is in the low bit, the distance in the rest
+fn relative_offset(offset: usize, number: usize) -> Result<usize, PatchError> {
+    let distance = number >> 1;
+    if number & 1 != 0 {
+        offset.checked_sub(distance).ok_or(PatchError::Invalid)
+    } else {
+        offset.checked_add(distance).ok_or(PatchError::Invalid)
+    }
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+struct PatchReader<'a> {
+    patch: &'a [u8],
+    pos: usize
+}
+
+impl<'a> PatchReader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.patch.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        let byte = *self.patch.get(self.pos).ok_or(PatchError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self.patch.get(self.pos .. self.pos + length).ok_or(PatchError::UnexpectedEnd)?;
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    // 7 bits a byte with the top bit marking the last, and each continuation adding one more so that
+    // every number has only one encoding
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut number: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            let bits = ((byte & 0x7f) as usize).checked_mul(shift).ok_or(PatchError::Invalid)?;
+            number = number.checked_add(bits).ok_or(PatchError::Invalid)?;
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::Invalid)?;
+            number = number.checked_add(shift).ok_or(PatchError::Invalid)?;
+        }
+    }
+}
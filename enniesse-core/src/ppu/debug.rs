@@ -0,0 +1,263 @@
+// views of ppu memory for debugging and rom hacking, drawn straight from vram without affecting emulation
+
+use ppu::{Ppu, SCREEN_WIDTH, SCREEN_HEIGHT};
+use ppu::palette::Palette;
+
+const NAMETABLE_START: u16 = 0x2000;
+const NAMETABLE_SIZE: u16 = 0x400;
+const ATTRIBUTE_TABLE_OFFSET: u16 = 0x3c0;
+const PALETTE_START: u16 = 0x3f00;
+const PATTERN_TABLE_SIZE: u16 = 0x1000;
+
+pub const PALETTE_RAM_SIZE: usize = 32;
+// 4 background palettes followed by 4 sprite palettes
+pub const PALETTE_COUNT: u8 = 8;
+pub const OAM_SPRITES: usize = 64;
+
+const TILE_SIZE: usize = 8;
+const NAMETABLE_COLUMNS: usize = SCREEN_WIDTH / TILE_SIZE;
+const NAMETABLE_ROWS: usize = SCREEN_HEIGHT / TILE_SIZE;
+// tiles across and down each pattern table
+const PATTERN_TABLE_TILES: usize = 16;
+const OAM_COLUMNS: usize = 8;
+const PALETTE_SWATCH_SIZE: usize = 16;
+const PALETTE_COLUMNS: usize = 16;
+
+// 3 bytes per pixel
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Image {
+        Image {
+            width: width,
+            height: height,
+            pixels: vec![0; width * height * 3]
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * self.width + x) * 3;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let i = (y * self.width + x) * 3;
+        self.pixels[i .. i + 3].copy_from_slice(&rgb);
+    }
+
+    // one 0xAARRGGBB word per pixel
+    pub fn to_argb(&self, out: &mut [u32]) {
+        for (rgb, pixel) in self.pixels.chunks(3).zip(out.iter_mut()) {
+            *pixel = 0xff00_0000 | (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+        }
+    }
+}
+
+// an oam entry with its attribute byte split out
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SpriteInfo {
+    pub index: u8,
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    // 0-3, of the sprite palettes
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl SpriteInfo {
+    fn new(index: u8, bytes: &[u8]) -> SpriteInfo {
+        SpriteInfo {
+            index: index,
+            x: bytes[3],
+            y: bytes[0],
+            tile: bytes[1],
+            palette: bytes[2] & 3,
+            behind_background: bytes[2] & 0x20 != 0,
+            flip_horizontal: bytes[2] & 0x40 != 0,
+            flip_vertical: bytes[2] & 0x80 != 0,
+        }
+    }
+}
+
+// the four logical nametables in a 2x2 grid, going through the cartridge's mirroring.
+// the area the next frame scrolls to is outlined when show_scroll is set
+pub fn nametables(ppu: &mut Ppu, palette: &Palette, show_scroll: bool) -> Image {
+    let mut image = Image::new(SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2);
+    let pattern_table = ppu.background_pattern_table();
+
+    for nametable in 0 .. 4 {
+        let base = NAMETABLE_START + nametable as u16 * NAMETABLE_SIZE;
+        let left = (nametable & 1) * SCREEN_WIDTH;
+        let top = (nametable >> 1) * SCREEN_HEIGHT;
+
+        for row in 0 .. NAMETABLE_ROWS {
+            for column in 0 .. NAMETABLE_COLUMNS {
+                let tile = ppu.peek_vram(base + (row * NAMETABLE_COLUMNS + column) as u16);
+
+                // each attribute byte covers 4x4 tiles, 2 bits for each 2x2 quadrant
+                let attribute = ppu.peek_vram(base + ATTRIBUTE_TABLE_OFFSET + ((row / 4) * 8 + column / 4) as u16);
+                let shift = (row & 2) << 1 | (column & 2);
+                let tile_palette = (attribute >> shift) & 3;
+
+                let address = pattern_table | (tile as u16) << 4;
+                draw_tile(ppu, palette, &mut image, TileDraw::new(address, tile_palette, left + column * TILE_SIZE, top + row * TILE_SIZE));
+            }
+        }
+    }
+
+    if show_scroll {
+        let (scroll_x, scroll_y) = ppu.scroll_position();
+        outline(&mut image, scroll_x, scroll_y, SCREEN_WIDTH, SCREEN_HEIGHT);
+    }
+
+    image
+}
+
+// both pattern tables side by side, colored with one of the 8 palettes
+pub fn pattern_tables(ppu: &mut Ppu, palette: &Palette, palette_number: u8) -> Image {
+    let size = PATTERN_TABLE_TILES * TILE_SIZE;
+    let mut image = Image::new(size * 2, size);
+
+    for table in 0 .. 2 {
+        for tile in 0 .. PATTERN_TABLE_TILES * PATTERN_TABLE_TILES {
+            let x = table * size + (tile % PATTERN_TABLE_TILES) * TILE_SIZE;
+            let y = (tile / PATTERN_TABLE_TILES) * TILE_SIZE;
+            let address = table as u16 * PATTERN_TABLE_SIZE | (tile as u16) << 4;
+
+            draw_tile(ppu, palette, &mut image, TileDraw::new(address, palette_number % PALETTE_COUNT, x, y));
+        }
+    }
+
+    image
+}
+
+pub fn oam_sprites(ppu: &Ppu) -> Vec<SpriteInfo> {
+    ppu.oam().chunks(4).enumerate().map(|(index, bytes)| SpriteInfo::new(index as u8, bytes)).collect()
+}
+
+// all 64 sprites in oam order, 8 to a row, drawn with their palettes and flips
+pub fn oam(ppu: &mut Ppu, palette: &Palette) -> Image {
+    let tall = ppu.tall_sprites();
+    let sprite_height = if tall { TILE_SIZE * 2 } else { TILE_SIZE };
+    let rows = OAM_SPRITES / OAM_COLUMNS;
+    let mut image = Image::new(OAM_COLUMNS * TILE_SIZE, rows * sprite_height);
+
+    for sprite in oam_sprites(ppu) {
+        let x = (sprite.index as usize % OAM_COLUMNS) * TILE_SIZE;
+        let y = (sprite.index as usize / OAM_COLUMNS) * sprite_height;
+        let sprite_palette = 4 + sprite.palette;
+
+        if tall {
+            // 8x16 sprites pick their pattern table with bit 0 of the tile
+            let address = (sprite.tile as u16 & 1) * PATTERN_TABLE_SIZE | (sprite.tile as u16 & 0xfe) << 4;
+            let (top, bottom) = if sprite.flip_vertical { (address | 0x10, address) } else { (address, address | 0x10) };
+
+            draw_tile(ppu, palette, &mut image, TileDraw::new(top, sprite_palette, x, y).flipped(&sprite));
+            draw_tile(ppu, palette, &mut image, TileDraw::new(bottom, sprite_palette, x, y + TILE_SIZE).flipped(&sprite));
+        } else {
+            let address = ppu.sprite_pattern_table() | (sprite.tile as u16) << 4;
+            draw_tile(ppu, palette, &mut image, TileDraw::new(address, sprite_palette, x, y).flipped(&sprite));
+        }
+    }
+
+    image
+}
+
+// the 32 bytes of palette ram, backgrounds on the top row and sprites on the bottom
+pub fn palette_ram(ppu: &mut Ppu, palette: &Palette) -> Image {
+    let rows = PALETTE_RAM_SIZE / PALETTE_COLUMNS;
+    let mut image = Image::new(PALETTE_COLUMNS * PALETTE_SWATCH_SIZE, rows * PALETTE_SWATCH_SIZE);
+
+    for entry in 0 .. PALETTE_RAM_SIZE {
+        let color = palette.rgb(ppu.peek_vram(PALETTE_START + entry as u16) as u16 & 0x3f);
+        let left = (entry % PALETTE_COLUMNS) * PALETTE_SWATCH_SIZE;
+        let top = (entry / PALETTE_COLUMNS) * PALETTE_SWATCH_SIZE;
+
+        for y in top .. top + PALETTE_SWATCH_SIZE {
+            for x in left .. left + PALETTE_SWATCH_SIZE {
+                image.set_pixel(x, y, color);
+            }
+        }
+    }
+
+    image
+}
+
+// an 8x8 tile at a pattern table address and where it goes in the image
+struct TileDraw {
+    address: u16,
+    palette_number: u8,
+    left: usize,
+    top: usize,
+    flip_horizontal: bool,
+    flip_vertical: bool
+}
+
+impl TileDraw {
+    fn new(address: u16, palette_number: u8, left: usize, top: usize) -> TileDraw {
+        TileDraw {
+            address: address,
+            palette_number: palette_number,
+            left: left,
+            top: top,
+            flip_horizontal: false,
+            flip_vertical: false
+        }
+    }
+
+    // flipped the way a sprite is
+    fn flipped(self, sprite: &SpriteInfo) -> TileDraw {
+        TileDraw {
+            flip_horizontal: sprite.flip_horizontal,
+            flip_vertical: sprite.flip_vertical,
+            ..self
+        }
+    }
+}
+
+// color 0 is always the backdrop
+fn draw_tile(ppu: &mut Ppu, palette: &Palette, image: &mut Image, tile: TileDraw) {
+    let mut colors = [[0; 3]; 4];
+    for (i, color) in colors.iter_mut().enumerate() {
+        let entry = if i == 0 { 0 } else { tile.palette_number as u16 * 4 + i as u16 };
+        *color = palette.rgb(ppu.peek_vram(PALETTE_START + entry) as u16 & 0x3f);
+    }
+
+    for row in 0 .. TILE_SIZE {
+        let fine_y = if tile.flip_vertical { 7 - row } else { row } as u16;
+        let plane0 = ppu.peek_vram(tile.address | fine_y);
+        let plane1 = ppu.peek_vram(tile.address | fine_y | 8);
+
+        for column in 0 .. TILE_SIZE {
+            let bit = if tile.flip_horizontal { column } else { 7 - column };
+            let pattern_color = ((plane1 >> bit) & 1) << 1 | (plane0 >> bit) & 1;
+
+            image.set_pixel(tile.left + column, tile.top + row, colors[pattern_color as usize]);
+        }
+    }
+}
+
+// inverts a 1 pixel border around a rectangle, wrapping around the edges of the image like scrolling does
+fn outline(image: &mut Image, left: usize, top: usize, width: usize, height: usize) {
+    let invert = |image: &mut Image, x: usize, y: usize| {
+        let (x, y) = (x % image.width, y % image.height);
+        let rgb = image.pixel(x, y);
+        image.set_pixel(x, y, [!rgb[0], !rgb[1], !rgb[2]]);
+    };
+
+    for x in left .. left + width {
+        invert(image, x, top);
+        invert(image, x, top + height - 1);
+    }
+    for y in top + 1 .. top + height - 1 {
+        invert(image, left, y);
+        invert(image, left + width - 1, y);
+    }
+}
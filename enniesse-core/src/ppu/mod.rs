@@ -0,0 +1,6 @@
+mod ppu;
+pub mod palette;
+pub mod ntsc;
+pub mod debug;
+
+pub use self::ppu::{Ppu, PpuRunResult, SCREEN_WIDTH, SCREEN_HEIGHT, CPU_CYCLES_PER_SCANLINE};
\ No newline at end of file
@@ -0,0 +1,216 @@
+use ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+use std::f32::consts::PI;
+
+pub const NTSC_WIDTH: usize = SCREEN_WIDTH * 2;
+pub const NTSC_HEIGHT: usize = SCREEN_HEIGHT;
+
+// the signal is sampled 12 times per cycle of the color carrier, which is 8 samples per pixel
+const PHASES: usize = 12;
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_OUTPUT: usize = SAMPLES_PER_PIXEL * SCREEN_WIDTH / NTSC_WIDTH;
+
+// a scanline is 341 dots, so each one starts 341 * 8 % 12 samples further into the carrier
+const LINE_PHASE_SHIFT: usize = 341 * SAMPLES_PER_PIXEL % PHASES;
+// the dot skipped on odd frames moves the picture a third of a carrier cycle against the even frame before it,
+// which is what makes the dot crawl alternate
+const ODD_FRAME_PHASE_SHIFT: usize = 4;
+
+// black samples around each line so the decoding window never runs off the end
+const LINE_PADDING: usize = PHASES / 2;
+const LINE_SAMPLES: usize = SCREEN_WIDTH * SAMPLES_PER_PIXEL + LINE_PADDING * 2;
+
+// composite signal levels from the nesdev wiki, low and high for each luma level. black is 0.518 and white 1.962
+const SIGNAL_LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f32 = 0.518;
+const SIGNAL_WHITE: f32 = 1.962;
+const SIGNAL_EMPHASIS_ATTENUATION: f32 = 0.746;
+
+// lines the decoded chroma up with the colors most emulators show, in samples
+const HUE_OFFSET: f32 = 4.0;
+
+#[derive(Copy, Clone, Debug)]
+pub struct NtscSettings {
+    // 0.0 is the full blur of the signal, 1.0 is much crisper but with more artifacts
+    pub sharpness: f32,
+    // multiplier for the color signal
+    pub saturation: f32,
+    // in degrees
+    pub hue: f32,
+}
+
+impl Default for NtscSettings {
+    fn default() -> NtscSettings {
+        NtscSettings {
+            sharpness: 0.0,
+            saturation: 1.0,
+            hue: 0.0,
+        }
+    }
+}
+
+// turns frames of palette indices into the picture a tv would show, by building the composite signal
+// the ppu would output and decoding it again
+pub struct NtscFilter {
+    settings: NtscSettings,
+
+    // signal level of every color at every phase of the carrier
+    signal_table: Vec<[f32; PHASES]>,
+    i_carrier: [f32; PHASES],
+    q_carrier: [f32; PHASES],
+    luma_width: usize,
+
+    line: Vec<f32>,
+}
+
+impl NtscFilter {
+    pub fn new(settings: NtscSettings) -> NtscFilter {
+        let signal_table = (0 .. 512).map(|index| {
+            let mut levels = [0.0; PHASES];
+            for phase in 0 .. PHASES {
+                levels[phase] = composite_signal(index as u16, phase);
+            }
+            levels
+        }).collect();
+
+        let mut filter = NtscFilter {
+            settings: settings,
+            signal_table: signal_table,
+            i_carrier: [0.0; PHASES],
+            q_carrier: [0.0; PHASES],
+            luma_width: PHASES,
+            line: vec![0.0; LINE_SAMPLES],
+        };
+        filter.set_settings(settings);
+
+        filter
+    }
+
+    pub fn settings(&self) -> NtscSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: NtscSettings) {
+        let (i_carrier, q_carrier) = carriers(settings.saturation, settings.hue);
+        self.i_carrier = i_carrier;
+        self.q_carrier = q_carrier;
+
+        // luma is averaged over less than a full carrier cycle when sharpening, so some chroma bleeds into it
+        let sharpness = settings.sharpness.max(0.0).min(1.0);
+        self.luma_width = PHASES - (sharpness * 4.0).round() as usize * 2;
+
+        self.settings = settings;
+    }
+
+    // frame is the ppu's display buffer, out is NTSC_WIDTH * NTSC_HEIGHT 0xAARRGGBB words.
+    // odd_frame should come from the ppu for the frame being filtered
+    pub fn apply(&mut self, frame: &[u16], odd_frame: bool, out: &mut [u32]) {
+        let frame_phase = if odd_frame { ODD_FRAME_PHASE_SHIFT } else { 0 };
+
+        for y in 0 .. SCREEN_HEIGHT {
+            // phase of the first sample in the line buffer, padding included
+            let line_phase = (frame_phase + y * LINE_PHASE_SHIFT + PHASES - LINE_PADDING) % PHASES;
+
+            self.encode_line(&frame[y * SCREEN_WIDTH .. (y + 1) * SCREEN_WIDTH], line_phase);
+            self.decode_line(line_phase, &mut out[y * NTSC_WIDTH .. (y + 1) * NTSC_WIDTH]);
+        }
+    }
+
+    fn encode_line(&mut self, pixels: &[u16], line_phase: usize) {
+        for (x, &index) in pixels.iter().enumerate() {
+            let levels = &self.signal_table[index as usize & 0x1ff];
+            let start = LINE_PADDING + x * SAMPLES_PER_PIXEL;
+
+            for sample in start .. start + SAMPLES_PER_PIXEL {
+                self.line[sample] = levels[(line_phase + sample) % PHASES];
+            }
+        }
+    }
+
+    fn decode_line(&self, line_phase: usize, out: &mut [u32]) {
+        for (x, pixel) in out.iter_mut().enumerate() {
+            let center = LINE_PADDING + x * SAMPLES_PER_OUTPUT + SAMPLES_PER_OUTPUT / 2;
+
+            let (mut i, mut q) = (0.0, 0.0);
+            for sample in center - PHASES / 2 .. center + PHASES / 2 {
+                let phase = (line_phase + sample) % PHASES;
+                i += self.line[sample] * self.i_carrier[phase];
+                q += self.line[sample] * self.q_carrier[phase];
+            }
+
+            let mut y = 0.0;
+            for sample in center - self.luma_width / 2 .. center + self.luma_width / 2 {
+                y += self.line[sample];
+            }
+            y /= self.luma_width as f32;
+
+            let rgb = yiq_to_rgb(y, i, q);
+            *pixel = 0xff00_0000 | (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+        }
+    }
+}
+
+// the normalized signal level of a 9 bit color index at a phase of the carrier. the ppu outputs a square wave
+// for each color, high for half of the 12 phases, and emphasis attenuates the part of the cycle for each color
+pub fn composite_signal(index: u16, phase: usize) -> f32 {
+    let color = index & 0x0f;
+    let mut level = (index >> 4) & 3;
+    let emphasis = (index >> 6) & 7;
+
+    // columns $e and $f are forced to black
+    if color > 0x0d {
+        level = 1;
+    }
+
+    let mut low = SIGNAL_LEVELS[level as usize];
+    let mut high = SIGNAL_LEVELS[4 + level as usize];
+    if color == 0 {
+        low = high;
+    } else if color > 0x0c {
+        high = low;
+    }
+
+    let in_phase = |color: u16| (color as usize + phase) % PHASES < PHASES / 2;
+
+    let mut signal = if in_phase(color) { high } else { low };
+    if (emphasis & 1 != 0 && in_phase(0))
+        || (emphasis & 2 != 0 && in_phase(4))
+        || (emphasis & 4 != 0 && in_phase(8)) {
+        signal *= SIGNAL_EMPHASIS_ATTENUATION;
+    }
+
+    (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+// reference waves for demodulating i and q over one carrier cycle, scaled so a cycle's sum is the average
+pub fn carriers(saturation: f32, hue: f32) -> ([f32; PHASES], [f32; PHASES]) {
+    let hue_offset = HUE_OFFSET + hue / 30.0;
+
+    let mut i_carrier = [0.0; PHASES];
+    let mut q_carrier = [0.0; PHASES];
+    for phase in 0 .. PHASES {
+        let angle = PI * (phase as f32 + hue_offset) / 6.0;
+        i_carrier[phase] = angle.cos() * saturation / PHASES as f32;
+        q_carrier[phase] = angle.sin() * saturation / PHASES as f32;
+    }
+
+    (i_carrier, q_carrier)
+}
+
+pub fn yiq_to_rgb(y: f32, i: f32, q: f32) -> [u8; 3] {
+    [
+        gamma_correct(y + 0.946882 * i + 0.623557 * q),
+        gamma_correct(y - 0.274788 * i - 0.635691 * q),
+        gamma_correct(y - 1.108545 * i + 1.709007 * q)
+    ]
+}
+
+// the signal is meant for a crt with a gamma around 2.2, decoded for a display that expects 1.8
+fn gamma_correct(val: f32) -> u8 {
+    if val <= 0.0 {
+        return 0;
+    }
+
+    let val = val.powf(2.2 / 1.8) * 255.0;
+    if val >= 255.0 { 255 } else { (val + 0.5) as u8 }
+}
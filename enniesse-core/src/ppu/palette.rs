@@ -0,0 +1,191 @@
+use ppu::ntsc;
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+// 64 colors for each of the 8 combinations of the emphasis bits
+pub const PALETTE_SIZE: usize = 512;
+
+// nesdev is the measured palette the nesdev wiki lists for the 2C02, and the default. the generated ones
+// are decoded from the model of the composite signal in ntsc.rs, so they're only approximations. other
+// palettes, like FirebrandX's, can be loaded from their .pal files
+pub const BUILTIN_PALETTES: [&'static str; 3] = ["nesdev", "generated", "generated-vivid"];
+
+// how much emphasis darkens the channels it doesn't cover, used when a palette file only has 64 colors
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+// the 2C02 palette from the nesdev wiki's ppu palettes page
+const DEFAULT_COLORS: [u8; 192] = [
+     84,  84,  84,    0,  30, 116,    8,  16, 144,   48,   0, 136,
+     68,   0, 100,   92,   0,  48,   84,   4,   0,   60,  24,   0,
+     32,  42,   0,    8,  58,   0,    0,  64,   0,    0,  60,   0,
+      0,  50,  60,    0,   0,   0,    0,   0,   0,    0,   0,   0,
+      
+    152, 150, 152,    8,  76, 196,   48,  50, 236,   92,  30, 228,
+    136,  20, 176,  160,  20, 100,  152,  34,  32,  120,  60,   0,
+     84,  90,   0,   40, 114,   0,    8, 124,   0,    0, 118,  40,
+      0, 102, 120,    0,   0,   0,    0,   0,   0,    0,   0,   0,
+      
+    236, 238, 236,   76, 154, 236,  120, 124, 236,  176,  98, 236,
+    228,  84, 236,  236,  88, 180,  236, 106, 100,  212, 136,  32,
+    160, 170,   0,  116, 196,   0,   76, 208,  32,   56, 204, 108,
+     56, 180, 204,   60,  60,  60,    0,   0,   0,    0,   0,   0,
+     
+    236, 238, 236,  168, 204, 236,  188, 188, 236,  212, 178, 236,
+    236, 174, 236,  236, 174, 212,  236, 180, 176,  228, 196, 144,
+    204, 210, 120,  180, 222, 120,  168, 226, 144,  152, 226, 180,
+    160, 214, 228,  160, 162, 160,    0,   0,   0,    0,   0,   0,
+];
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(io::Error),
+    InvalidSize(usize)
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PaletteError::Io(ref e) => write!(f, "{}", e),
+            PaletteError::InvalidSize(size) => write!(f, "Palette is {} bytes, expected 192 or 1536", size)
+        }
+    }
+}
+
+impl From<io::Error> for PaletteError {
+    fn from(e: io::Error) -> PaletteError {
+        PaletteError::Io(e)
+    }
+}
+
+// maps the ppu's 9 bit color index (emphasis in the upper 3 bits) to rgb
+#[derive(Clone)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>
+}
+
+impl Palette {
+    pub fn builtin(name: &str) -> Option<Palette> {
+        match name {
+            "nesdev" | "default" => Some(Palette::default()),
+            "generated" => Some(Palette::generate(1.0, 0.0)),
+            "generated-vivid" => Some(Palette::generate(1.4, 0.0)),
+            _ => None
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Palette, PaletteError> {
+        let mut file = File::open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        Palette::from_bytes(&data)
+    }
+
+    // either 64 or 512 rgb triplets
+    pub fn from_bytes(data: &[u8]) -> Result<Palette, PaletteError> {
+        if data.len() != 192 && data.len() != 1536 {
+            return Err(PaletteError::InvalidSize(data.len()));
+        }
+
+        let colors: Vec<[u8; 3]> = data.chunks(3).map(|c| [c[0], c[1], c[2]]).collect();
+
+        if colors.len() == PALETTE_SIZE {
+            Ok(Palette { colors: colors })
+        } else {
+            Ok(Palette::with_emphasis(&colors))
+        }
+    }
+
+    // decodes an approximation of the composite signal for every color. saturation is a multiplier, hue in degrees
+    pub fn generate(saturation: f32, hue: f32) -> Palette {
+        let (i_carrier, q_carrier) = ntsc::carriers(saturation, hue);
+
+        let colors = (0 .. PALETTE_SIZE).map(|index| {
+            let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+            for phase in 0 .. i_carrier.len() {
+                let signal = ntsc::composite_signal(index as u16, phase);
+
+                y += signal;
+                i += signal * i_carrier[phase];
+                q += signal * q_carrier[phase];
+            }
+
+            ntsc::yiq_to_rgb(y / i_carrier.len() as f32, i, q)
+        }).collect();
+
+        Palette {
+            colors: colors
+        }
+    }
+
+    pub fn rgb(&self, index: u16) -> [u8; 3] {
+        self.colors[index as usize % PALETTE_SIZE]
+    }
+
+    // conversions for a whole frame of palette indices
+
+    // 3 bytes per pixel
+    pub fn to_rgb(&self, frame: &[u16], out: &mut [u8]) {
+        for (index, pixel) in frame.iter().zip(out.chunks_mut(3)) {
+            pixel.copy_from_slice(&self.rgb(*index));
+        }
+    }
+
+    // 4 bytes per pixel, alpha is always opaque
+    pub fn to_rgba(&self, frame: &[u16], out: &mut [u8]) {
+        for (index, pixel) in frame.iter().zip(out.chunks_mut(4)) {
+            let rgb = self.rgb(*index);
+
+            pixel[0] = rgb[0];
+            pixel[1] = rgb[1];
+            pixel[2] = rgb[2];
+            pixel[3] = 0xff;
+        }
+    }
+
+    // one 0xAARRGGBB word per pixel
+    pub fn to_argb(&self, frame: &[u16], out: &mut [u32]) {
+        for (index, pixel) in frame.iter().zip(out.iter_mut()) {
+            let rgb = self.rgb(*index);
+
+            *pixel = 0xff00_0000 | (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+        }
+    }
+
+    // builds the emphasis entries for a 64 color palette by dimming the channels that aren't emphasized
+    fn with_emphasis(base: &[[u8; 3]]) -> Palette {
+        let mut colors = Vec::with_capacity(PALETTE_SIZE);
+
+        for emphasis in 0 .. 8 {
+            for color in base {
+                let mut rgb = *color;
+
+                if emphasis != 0 {
+                    for channel in 0 .. 3 {
+                        if emphasis & (1 << channel) == 0 {
+                            rgb[channel] = (rgb[channel] as f32 * EMPHASIS_ATTENUATION) as u8;
+                        }
+                    }
+                }
+
+                colors.push(rgb);
+            }
+        }
+
+        Palette {
+            colors: colors
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        let colors: Vec<[u8; 3]> = DEFAULT_COLORS.chunks(3).map(|c| [c[0], c[1], c[2]]).collect();
+
+        Palette::with_emphasis(&colors)
+    }
+}
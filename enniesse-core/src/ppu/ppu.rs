@@ -1,10 +1,12 @@
 use std::ops::Deref;
 use memory::Memory;
-use mapper::{Mapper, Mirroring};
+use mapper::{Mapper, PpuFetch, CIRAM_SIZE, NAMETABLE_RAM_SIZE};
+use state::{SaveState, StateWriter, StateReader};
 
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::VecDeque;
+use std::mem;
 
 // the PPU register addresses repeat every 8 bits starting at 2000, so mask them to 0-7
 const PPU_CTRL: u16   = 0x2000 & 0x07;
@@ -24,8 +26,6 @@ const NAMETABLE_END: u16   = 0x3eff;
 const PALETTE_START: u16   = 0x3f00;
 const PALETTE_END: u16     = 0x3fff;
 
-const PPU_RAM_SIZE: usize = 0x800;
-
 const CPU_CYCLES_PER_FRAME: u16 = 29781;
 const SCANLINES_PER_FRAME: u16 = 262;
 pub const CPU_CYCLES_PER_SCANLINE: u16 = CPU_CYCLES_PER_FRAME / SCANLINES_PER_FRAME;
@@ -34,31 +34,20 @@ const PPU_CYCLES_PER_SCANLINE: u16 = 341;
 const VBLANK_SCANLINE_START: i16 = 241;
 const VBLANK_SCANLINE_END: i16 = 260;
 
+// bits of the i/o latch fade to 0 after roughly 600ms without being refreshed
+const IO_LATCH_DECAY_FRAMES: u8 = 36;
+
+// secondary oam holds the sprites for the next scanline
+const SECONDARY_OAM_SPRITES: usize = 8;
+// sprite evaluation runs over dots 65-256, reading oam on odd dots and writing secondary oam on even ones
+const SPRITE_EVALUATION_START: u16 = 65;
+// oamaddr is held at 0 while the sprite tiles are fetched
+const SPRITE_FETCH_START: u16 = 257;
+const SPRITE_FETCH_END: u16 = 320;
+
 pub const SCREEN_WIDTH: usize = 256;
 pub const SCREEN_HEIGHT: usize = 240;
 
-static RGB_PALETTE: [u8; 192] = [
-     84,  84,  84,    0,  30, 116,    8,  16, 144,   48,   0, 136,
-     68,   0, 100,   92,   0,  48,   84,   4,   0,   60,  24,   0,
-     32,  42,   0,    8,  58,   0,    0,  64,   0,    0,  60,   0,
-      0,  50,  60,    0,   0,   0,    0,   0,   0,    0,   0,   0,
-      
-    152, 150, 152,    8,  76, 196,   48,  50, 236,   92,  30, 228,
-    136,  20, 176,  160,  20, 100,  152,  34,  32,  120,  60,   0,
-     84,  90,   0,   40, 114,   0,    8, 124,   0,    0, 118,  40,
-      0, 102, 120,    0,   0,   0,    0,   0,   0,    0,   0,   0,
-      
-    236, 238, 236,   76, 154, 236,  120, 124, 236,  176,  98, 236,
-    228,  84, 236,  236,  88, 180,  236, 106, 100,  212, 136,  32,
-    160, 170,   0,  116, 196,   0,   76, 208,  32,   56, 204, 108,
-     56, 180, 204,   60,  60,  60,    0,   0,   0,    0,   0,   0,
-     
-    236, 238, 236,  168, 204, 236,  188, 188, 236,  212, 178, 236,
-    236, 174, 236,  236, 174, 212,  236, 180, 176,  228, 196, 144,
-    204, 210, 120,  180, 222, 120,  168, 226, 144,  152, 226, 180,
-    160, 214, 228,  160, 162, 160,    0,   0,   0,    0,   0,   0,
-];
-
 pub struct Ppu {
     reg_ctrl: CtrlRegister,
     reg_mask: MaskRegister,
@@ -71,17 +60,30 @@ pub struct Ppu {
     temporary_vram_address: u16, // t
     fine_x: u8, // x
     write_toggle: AddressByte, // w
+
+    // after power on or reset, writes to ctrl, mask, scroll and addr are ignored until the end of vblank
+    warming_up: bool,
+
+    // the data bus between the cpu and the ppu registers, returned for anything the ppu doesn't drive itself
+    io_latch: IoLatch,
     
     pub cycle: u16,
     scanline: i16,
+    odd_frame: bool,
     
     vram: Vram,
     oam: Oam,
 
     tiles_to_render: VecDeque<Tile>,
     sprites_to_render: Vec<Sprite>,
+    secondary_oam: Vec<Sprite>,
+    // the dot on the current scanline where evaluation finds an overflow
+    sprite_overflow_dot: Option<u16>,
+    // drawing more than 8 sprites per scanline is an enhancement, it doesn't change what the cpu sees
+    sprite_limit: bool,
     
-    pub display_buffer: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT * 3]>,
+    // 9 bit palette indices, see palette::Palette for turning them into colors
+    pub display_buffer: Box<[u16; SCREEN_WIDTH * SCREEN_HEIGHT]>,
 }
 
 impl Ppu {
@@ -98,20 +100,110 @@ impl Ppu {
             temporary_vram_address: 0,
             fine_x: 0,
             write_toggle: AddressByte::Upper,
+
+            warming_up: true,
+
+            io_latch: IoLatch::default(),
             
             cycle: 0,
             scanline: -1,
+            odd_frame: false,
             
             vram: Vram::new(mapper),
             oam: Oam([0; 256]),
             
             tiles_to_render: VecDeque::with_capacity(2),
-            sprites_to_render: Vec::with_capacity(8),
+            sprites_to_render: Vec::with_capacity(SECONDARY_OAM_SPRITES),
+            secondary_oam: Vec::with_capacity(SECONDARY_OAM_SPRITES),
+            sprite_overflow_dot: None,
+            sprite_limit: true,
 
-            display_buffer: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT * 3])
+            display_buffer: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT])
         }
     }
     
+    pub fn reset(&mut self) {
+        self.reg_ctrl = CtrlRegister(0);
+        self.reg_mask = MaskRegister(0);
+        self.write_toggle = AddressByte::Upper;
+        self.data_read_buffer = 0;
+
+        // scroll is cleared, but the address in v is left alone
+        self.temporary_vram_address = 0;
+        self.fine_x = 0;
+
+        self.warming_up = true;
+    }
+
+    pub fn power_cycle(&mut self) {
+        self.reset();
+
+        self.reg_status = StatusRegister(0);
+        self.reg_oam_addr = 0;
+        self.current_vram_address = 0;
+
+        self.io_latch = IoLatch::default();
+
+        self.cycle = 0;
+        self.scanline = -1;
+        self.odd_frame = false;
+        self.tiles_to_render.clear();
+        self.sprites_to_render.clear();
+        self.secondary_oam.clear();
+        self.sprite_overflow_dot = None;
+    }
+    
+    // whether the frame being drawn, or the one just finished during vblank, is odd.
+    // odd frames are a dot shorter when rendering, which shifts the phase of the color signal
+    pub fn odd_frame(&self) -> bool {
+        self.odd_frame
+    }
+
+    // with the limit off every sprite on a scanline is drawn, avoiding flicker
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+    }
+
+    pub fn sprite_limit(&self) -> bool {
+        self.sprite_limit
+    }
+
+    // access for the debug viewers, none of these have side effects
+
+    // reads ppu memory without going through the data register
+    pub fn peek_vram(&mut self, addr: u16) -> u8 {
+        self.vram.fetch(PpuFetch::Data);
+        self.vram.load_byte(addr & 0x3fff)
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    // the top left of the screen in the 512x480 space of the four nametables, as written through the scroll registers
+    pub fn scroll_position(&self) -> (usize, usize) {
+        let t = self.temporary_vram_address as usize;
+        let x = (t & 0x400) >> 2 | (t & 0x1f) << 3 | self.fine_x as usize;
+        let y = ((t & 0x800) >> 11) * 240 + ((t & 0x3e0) >> 5) * 8 + ((t & 0x7000) >> 12);
+
+        (x, y)
+    }
+
+    pub fn background_pattern_table(&self) -> u16 {
+        self.reg_ctrl.background_pattern_table_address()
+    }
+
+    pub fn sprite_pattern_table(&self) -> u16 {
+        self.reg_ctrl.sprite_pattern_table_address()
+    }
+
+    pub fn tall_sprites(&self) -> bool {
+        match self.reg_ctrl.sprite_size() {
+            SpriteSize::Size8x8 => false,
+            SpriteSize::Size8x16 => true,
+        }
+    }
+
     // run PPU for one scanline
     pub fn run(&mut self, visible_cycles: bool) -> PpuRunResult {
         let mut result = PpuRunResult::default();
@@ -130,6 +222,8 @@ impl Ppu {
             self.scanline += 1;
 
             if self.scanline == VBLANK_SCANLINE_START {
+                self.io_latch.decay();
+
                 self.reg_status.set_vblank(true);
                 if self.reg_ctrl.generate_nmi() {
                     result.vblank = true;
@@ -138,8 +232,13 @@ impl Ppu {
                 //println!("[frame]");
             } else if self.scanline == VBLANK_SCANLINE_END {
                 self.scanline = -1;
+                self.odd_frame = !self.odd_frame;
                 self.reg_status.set_vblank(false);
+                self.warming_up = false;
             }
+
+            let rendering = self.reg_mask.show_background() || self.reg_mask.show_sprites();
+            self.vram.mapper.borrow_mut().ppu_scanline(self.scanline, rendering);
         }
         
         result
@@ -148,8 +247,7 @@ impl Ppu {
     // rendering
     
     fn render_scanline(&mut self, show_background: bool, show_background_left: bool, show_sprites: bool, show_sprites_left: bool, visible_cycles: bool) {
-        let backdrop_index = self.vram.load_byte(PALETTE_START);
-        let backdrop_color = self.color_from_palette(backdrop_index as usize);
+        let backdrop_color = self.vram.load_byte(PALETTE_START);
         
         let y = self.scanline;
 
@@ -181,6 +279,23 @@ impl Ppu {
             let x = cycle - 1;
 
             if show_background || show_sprites {
+                if y == -1 && cycle == 1 && self.reg_oam_addr >= 8 {
+                    // a misaligned oamaddr when rendering starts copies its 8 byte row over the first sprites
+                    let row = (self.reg_oam_addr & 0xf8) as usize;
+                    for i in 0 .. 8 {
+                        self.oam.0[i] = self.oam.0[row + i];
+                    }
+                }
+
+                if cycle >= SPRITE_FETCH_START && cycle <= SPRITE_FETCH_END {
+                    self.reg_oam_addr = 0;
+                }
+
+                if self.sprite_overflow_dot == Some(cycle) {
+                    self.reg_status.set_sprite_overflow(true);
+                    self.sprite_overflow_dot = None;
+                }
+
                 // prefetch for the next scanline is between cycles 321 and 336
                 if cycle > 1 && x % 8 == 0 && (cycle <= SCREEN_WIDTH as u16 || cycle >= 321 && cycle <= 336) {
                     if self.tiles_to_render.len() > 1 {
@@ -227,14 +342,14 @@ impl Ppu {
 
                     // write the pixel to the display buffer
                     if x < SCREEN_WIDTH as u16 {
-                        self.display_buffer[(y as usize * SCREEN_WIDTH + x as usize) * 3 + 0] = color.r;
-                        self.display_buffer[(y as usize * SCREEN_WIDTH + x as usize) * 3 + 1] = color.g;
-                        self.display_buffer[(y as usize * SCREEN_WIDTH + x as usize) * 3 + 2] = color.b;
+                        self.display_buffer[y as usize * SCREEN_WIDTH + x as usize] = self.output_color(color);
                     }
 
-                    if cycle == 257 {
-                        // load sprites to be rendered on the next scanline
+                    if cycle == SPRITE_EVALUATION_START {
+                        // find the sprites to be rendered on the next scanline
                         self.get_sprites_to_render();
+                    } else if cycle == SPRITE_FETCH_START {
+                        mem::swap(&mut self.sprites_to_render, &mut self.secondary_oam);
                     }
                 }
 
@@ -281,6 +396,7 @@ impl Ppu {
     }
 
     fn fetch_tile(&mut self) -> Tile {
+        self.vram.fetch(PpuFetch::Background);
         let v = self.current_vram_address as u16;
         
         // from wiki - pull the tile address bits out of v
@@ -320,7 +436,7 @@ impl Ppu {
         Tile::new(plane0, plane1, attribute_color)
     }
 
-    fn get_background_pixel(&mut self, current_pixel: u8) -> Option<RgbColor> {
+    fn get_background_pixel(&mut self, current_pixel: u8) -> Option<u8> {
         let x = current_pixel % 8;
         let tile_select = (x + self.fine_x) / 8;
 
@@ -340,15 +456,17 @@ impl Ppu {
             let palette_index = (tile.attribute_color << 2) | pattern_color;
             let color_index = self.vram.load_byte(PALETTE_START + palette_index as u16);
             
-            return Some(self.color_from_palette(color_index as usize));
+            return Some(color_index);
         }
 
         None
     }
 
-    fn get_sprite_pixel(&mut self, x: u8) -> (Option<RgbColor>, bool, bool) {
+    fn get_sprite_pixel(&mut self, x: u8) -> (Option<u8>, bool, bool) {
         for sprite in &self.sprites_to_render {
             if x >= sprite.x_position && (x < sprite.x_position + 8 || sprite.x_position >= (SCREEN_WIDTH - 8) as u8) {
+                self.vram.fetch(PpuFetch::Sprite);
+
                 let mut pattern_base = 0x0000;
                 match self.reg_ctrl.sprite_size() {
                     SpriteSize::Size8x16 => {
@@ -393,9 +511,9 @@ impl Ppu {
                     //println!("sprite {} - color index {:02X}", sprite.x_position, color_index);
                     let priority = ((sprite.attributes >> 5) & 1) == 1;
                     
-                    let color = Some(self.color_from_palette(color_index as usize));
+                    let color = Some(color_index);
 
-                    return (color, priority, sprite.index == 0);
+                    return (color, priority, sprite.sprite_zero);
                 }
             }
         }
@@ -403,42 +521,88 @@ impl Ppu {
         (None, false, false)
     }
 
+    // secondary oam evaluation, done in one go but timed as the hardware does it so the
+    // overflow flag is set on the right dot
     fn get_sprites_to_render(&mut self) {
-        self.sprites_to_render.clear();
-        
-        // oam holds 64 4-byte sprites
-        for n in 0 .. 64 {
-            let sprite_index = 4 * n;
-            
-            // grab the 4 bytes from oam
-            let sprite_bytes = &self.oam[sprite_index..sprite_index + 4];
-            let sprite = Sprite::new(sprite_bytes[0], sprite_bytes[1], sprite_bytes[2], sprite_bytes[3], n as u8);
+        self.secondary_oam.clear();
+        self.sprite_overflow_dot = None;
 
-            let size = match self.reg_ctrl.sprite_size() {
-                SpriteSize::Size8x8 => 8,
-                SpriteSize::Size8x16 => 16,
-            };
+        let size = match self.reg_ctrl.sprite_size() {
+            SpriteSize::Size8x8 => 8,
+            SpriteSize::Size8x16 => 16,
+        };
+        let scanline = self.scanline;
+        let in_range = |y: u8| {
+            let row = scanline - y as i16;
+            row >= 0 && row < size
+        };
 
-            let range = self.scanline - sprite.y_position as i16;
+        // evaluation starts wherever oamaddr points, normally 0
+        let mut addr = self.reg_oam_addr as usize;
+        let mut dot = SPRITE_EVALUATION_START;
+
+        // copy sprites until secondary oam is full, a sprite takes 2 dots to check and 6 more to copy
+        while addr < 256 && self.secondary_oam.len() < SECONDARY_OAM_SPRITES {
+            let y_position = self.oam.0[addr];
+            dot += 2;
+
+            if in_range(y_position) {
+                let sprite = Sprite::new(y_position,
+                                         self.oam.0[(addr + 1) & 0xff],
+                                         self.oam.0[(addr + 2) & 0xff],
+                                         self.oam.0[(addr + 3) & 0xff],
+                                         (addr >> 2) as u8,
+                                         dot == SPRITE_EVALUATION_START + 2);
+                self.secondary_oam.push(sprite);
+                dot += 6;
+            }
 
-            if sprite.y_position < 0xef && range >= 0 && range < size {
-                if self.sprites_to_render.len() == 8 {
-                    // note: this isn't quite correct due to a hardware bug
-                    self.reg_status.set_sprite_overflow(true);
-                    break;
+            addr += 4;
+        }
+
+        // once it's full, the hardware keeps looking for a ninth sprite, but increments both the
+        // sprite and the byte within it, so it ends up checking tiles, attributes and x positions as y
+        let mut n = addr >> 2;
+        let mut m = addr & 3;
+        while n < 64 {
+            if in_range(self.oam.0[4 * n + m]) {
+                self.sprite_overflow_dot = Some(dot + 1);
+                break;
+            }
+
+            n += 1;
+            m = (m + 1) & 3;
+            dot += 2;
+        }
+
+        if !self.sprite_limit {
+            // the rest of the sprites on the line are drawn behind the first 8, the flags above are left as they were
+            while addr < 256 {
+                let y_position = self.oam.0[addr];
+                if in_range(y_position) {
+                    let sprite = Sprite::new(y_position,
+                                             self.oam.0[(addr + 1) & 0xff],
+                                             self.oam.0[(addr + 2) & 0xff],
+                                             self.oam.0[(addr + 3) & 0xff],
+                                             (addr >> 2) as u8,
+                                             false);
+                    self.secondary_oam.push(sprite);
                 }
-                self.sprites_to_render.push(sprite);
-                //println!("{:?}", sprite);
+
+                addr += 4;
             }
         }
     }
-    
-    fn color_from_palette(&self, index: usize) -> RgbColor {
-        RgbColor {
-            r: RGB_PALETTE[index * 3],
-            g: RGB_PALETTE[index * 3 + 1],
-            b: RGB_PALETTE[index * 3 + 2]
+
+    // the 9 bit index of a color from palette ram, with greyscale and emphasis from the mask register applied
+    fn output_color(&self, color: u8) -> u16 {
+        let mut color = color & 0x3f;
+        if self.reg_mask.greyscale() {
+            // greyscale forces the color to the grey column of its row
+            color &= 0x30;
         }
+
+        self.reg_mask.emphasis() << 6 | color as u16
     }
 
     fn copy_horizontal(&mut self) {
@@ -470,22 +634,29 @@ impl Ppu {
         self.oam.load_byte(addr as u16)
     }
     
-    fn read_data(&mut self) -> u8 {
+    // returns the data and the bits of it the ppu actually drives
+    fn read_data(&mut self) -> (u8, u8) {
+        self.vram.fetch(PpuFetch::Data);
         let addr = self.current_vram_address;
         self.current_vram_address += self.reg_ctrl.vram_address_increment();
-        let data = self.vram.load_byte(addr);
+        let mut data = self.vram.load_byte(addr);
         
         // reads before the palette are buffered
         if addr < PALETTE_START {
             let buffer = self.data_read_buffer;
             self.data_read_buffer = data;
-            return buffer;
-        } else if addr >= PALETTE_START && addr <= PALETTE_END {
-            // data is still buffered on palette reads from the corresponding nametable bytes
-            self.data_read_buffer = self.vram.load_byte(NAMETABLE_START | (addr & PPU_RAM_SIZE as u16 - 1));
+            return (buffer, 0xff);
         }
-        
-        data
+
+        // data is still buffered on palette reads from the corresponding nametable bytes
+        self.data_read_buffer = self.vram.load_byte(addr & 0x2fff);
+
+        if self.reg_mask.greyscale() {
+            data &= 0x30;
+        }
+
+        // palette ram is only 6 bits wide, the top 2 come from the latch
+        (data, 0x3f)
     }
     
     fn write_ctrl(&mut self, val: u8) {
@@ -549,6 +720,7 @@ impl Ppu {
     }
     
     fn write_data(&mut self, val: u8) {
+        self.vram.fetch(PpuFetch::Data);
         let addr = self.current_vram_address;
         self.vram.store_byte(addr, val);
         self.current_vram_address += self.reg_ctrl.vram_address_increment();
@@ -568,24 +740,31 @@ impl Memory for Ppu {
     fn load_byte(&mut self, addr: u16) -> u8 {
         //Ppu::trace_read(self.scanline, addr);
         
-        // repeats every 8 bytes
-        match addr & 0x07 {
-            PPU_CTRL => 0, // write only
-            PPU_MASK => 0, // write only
-            PPU_STATUS => self.read_status(),
-            OAM_ADDR => 0, // write only
-            OAM_DATA => self.read_oam_data(),
-            PPU_SCROLL => 0, // write only
-            PPU_ADDR => 0, // write only
+        // repeats every 8 bytes. each read gives the value and which bits of it are driven,
+        // the rest come from the latch
+        let (val, driven) = match addr & 0x07 {
+            PPU_CTRL => (0, 0), // write only
+            PPU_MASK => (0, 0), // write only
+            PPU_STATUS => (self.read_status(), 0xe0), // only the top 3 bits exist
+            OAM_ADDR => (0, 0), // write only
+            OAM_DATA => (self.read_oam_data(), 0xff),
+            PPU_SCROLL => (0, 0), // write only
+            PPU_ADDR => (0, 0), // write only
             PPU_DATA => self.read_data(),
             _ => panic!("Unknown PPU register {:04X}", addr)
-        }
+        };
+
+        self.io_latch.drive(val, driven)
     }
     fn store_byte(&mut self, addr: u16, val: u8) {
         //Ppu::trace_write(self.scanline, addr, val);
+
+        // every write fills the latch, even to read only registers
+        self.io_latch.drive(val, 0xff);
         
         // repeats every 8 bytes
         match addr & 0x07 {
+            PPU_CTRL | PPU_MASK | PPU_SCROLL | PPU_ADDR if self.warming_up => {},
             PPU_CTRL => self.write_ctrl(val), 
             PPU_MASK => self.write_mask(val),
             PPU_STATUS => {}, // read only
@@ -599,50 +778,168 @@ impl Memory for Ppu {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-struct RgbColor {
-    r: u8,
-    g: u8,
-    b: u8
+impl SaveState for Ppu {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(*self.reg_ctrl);
+        state.write_u8(*self.reg_mask);
+        state.write_u8(*self.reg_status);
+        state.write_u8(self.reg_oam_addr);
+        state.write_u8(self.data_read_buffer);
+
+        state.write_u16(self.current_vram_address);
+        state.write_u16(self.temporary_vram_address);
+        state.write_u8(self.fine_x);
+        state.write_bool(self.write_toggle == AddressByte::Lower);
+        state.write_bool(self.warming_up);
+        self.io_latch.save_state(state);
+
+        state.write_u16(self.cycle);
+        state.write_i16(self.scanline);
+        state.write_bool(self.odd_frame);
+
+        state.write_bytes(&self.vram.nametable);
+        state.write_bytes(&self.vram.palette);
+        state.write_bytes(&self.oam.0);
+
+        state.write_u8(self.tiles_to_render.len() as u8);
+        for tile in &self.tiles_to_render {
+            state.write_u8(tile.plane0);
+            state.write_u8(tile.plane1);
+            state.write_u8(tile.attribute_color);
+        }
+
+        save_sprites(&self.sprites_to_render, state);
+        save_sprites(&self.secondary_oam, state);
+        state.write_u16(self.sprite_overflow_dot.unwrap_or(0));
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.reg_ctrl = CtrlRegister(state.read_u8());
+        self.reg_mask = MaskRegister(state.read_u8());
+        self.reg_status = StatusRegister(state.read_u8());
+        self.reg_oam_addr = state.read_u8();
+        self.data_read_buffer = state.read_u8();
+
+        self.current_vram_address = state.read_u16();
+        self.temporary_vram_address = state.read_u16();
+        self.fine_x = state.read_u8();
+        self.write_toggle = if state.read_bool() { AddressByte::Lower } else { AddressByte::Upper };
+        self.warming_up = state.read_bool();
+        self.io_latch.load_state(state);
+
+        self.cycle = state.read_u16();
+        self.scanline = state.read_i16();
+        self.odd_frame = state.read_bool();
+
+        state.read_bytes_into(&mut self.vram.nametable);
+        state.read_bytes_into(&mut self.vram.palette);
+        state.read_bytes_into(&mut self.oam.0);
+
+        self.tiles_to_render.clear();
+        for _ in 0 .. state.read_u8() {
+            let tile = Tile::new(state.read_u8(), state.read_u8(), state.read_u8());
+            self.tiles_to_render.push_back(tile);
+        }
+
+        load_sprites(&mut self.sprites_to_render, state);
+        load_sprites(&mut self.secondary_oam, state);
+        self.sprite_overflow_dot = match state.read_u16() {
+            0 => None,
+            dot => Some(dot)
+        };
+    }
+}
+
+fn save_sprites(sprites: &[Sprite], state: &mut StateWriter) {
+    state.write_u8(sprites.len() as u8);
+    for sprite in sprites {
+        state.write_u8(sprite.y_position);
+        state.write_u8(sprite.tile_index);
+        state.write_u8(sprite.attributes);
+        state.write_u8(sprite.x_position);
+        state.write_u8(sprite.index);
+        state.write_bool(sprite.sprite_zero);
+    }
+}
+
+fn load_sprites(sprites: &mut Vec<Sprite>, state: &mut StateReader) {
+    sprites.clear();
+    for _ in 0 .. state.read_u8() {
+        let sprite = Sprite::new(state.read_u8(), state.read_u8(), state.read_u8(), state.read_u8(), state.read_u8(), state.read_bool());
+        sprites.push(sprite);
+    }
+}
+
+#[derive(Default)]
+struct IoLatch {
+    value: u8,
+    // frames left before each bit decays
+    decay_frames: [u8; 8],
+}
+
+impl IoLatch {
+    // puts the driven bits of val on the bus and returns what the cpu sees
+    fn drive(&mut self, val: u8, driven: u8) -> u8 {
+        self.value = (self.value & !driven) | (val & driven);
+
+        for bit in 0 .. 8 {
+            // only bits driven high need refreshing, a 0 has nothing to decay
+            if driven & self.value & (1 << bit) != 0 {
+                self.decay_frames[bit] = IO_LATCH_DECAY_FRAMES;
+            }
+        }
+
+        self.value
+    }
+
+    // called once per frame
+    fn decay(&mut self) {
+        for bit in 0 .. 8 {
+            if self.decay_frames[bit] > 0 {
+                self.decay_frames[bit] -= 1;
+                if self.decay_frames[bit] == 0 {
+                    self.value &= !(1 << bit);
+                }
+            }
+        }
+    }
+}
+
+impl SaveState for IoLatch {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.value);
+        state.write_bytes(&self.decay_frames);
+    }
+    fn load_state(&mut self, state: &mut StateReader) {
+        self.value = state.read_u8();
+        state.read_bytes_into(&mut self.decay_frames);
+    }
 }
 
 #[derive(Default)]
 pub struct PpuRunResult {
     pub vblank: bool,
-    pub mapper_irq: bool,
     pub render_frame: bool
 }
 
 struct Vram {
     mapper: Rc<RefCell<Box<Mapper>>>,
-    nametable: [u8; PPU_RAM_SIZE], // 2kb ram
+    // ciram, plus the ram on four screen boards. what's mapped where is up to the cartridge
+    nametable: [u8; NAMETABLE_RAM_SIZE],
     palette: [u8; 0x20],
-    mirroring: Mirroring
 }
 
 impl Vram {
     fn new(mapper: Rc<RefCell<Box<Mapper>>>) -> Vram {
-        let mirroring = mapper.borrow().mirroring();
         Vram {
             mapper: mapper,
-            nametable: [0; PPU_RAM_SIZE],
+            nametable: [0; NAMETABLE_RAM_SIZE],
             palette: [0; 0x20],
-            mirroring: mirroring
         }
     }
-    
-    fn nametable_addr(&self, addr: u16) -> usize {
-        let mut nametable_addr = addr as usize & 0xfff;
-        
-        nametable_addr = match (self.mirroring, nametable_addr) {
-            (Mirroring::Horizontal, 0x0000 ... 0x07ff) => nametable_addr & !0x400,
-            (Mirroring::Horizontal, 0x0800 ... 0x0fff) => nametable_addr - 0x400,
-            (Mirroring::Vertical, 0x0000 ... 0x07ff) => nametable_addr,
-            (Mirroring::Vertical, 0x0800 ... 0x0fff) => nametable_addr & !(1 << 11),
-            (_, _) => nametable_addr
-        };
-        
-        nametable_addr & (PPU_RAM_SIZE - 1)
+
+    // lets the mapper know what the following reads are for
+    fn fetch(&mut self, fetch: PpuFetch) {
+        self.mapper.borrow_mut().ppu_fetch(fetch);
     }
 }
 
@@ -653,8 +950,7 @@ impl Memory for Vram {
         match addr {
             MAPPER_START ... MAPPER_END => self.mapper.borrow_mut().load_byte_chr(addr),
             NAMETABLE_START ... NAMETABLE_END => {
-                let nametable_addr = self.nametable_addr(addr);
-                self.nametable[nametable_addr]
+                self.mapper.borrow_mut().load_nametable(addr, &self.nametable)
             },
             PALETTE_START ... PALETTE_END => {
                 // handle mirrored addresses
@@ -665,7 +961,7 @@ impl Memory for Vram {
                 
                 self.palette[addr]
             },
-            0x4000 ... 0x7fff => self.nametable[addr as usize & (PPU_RAM_SIZE - 1)],
+            0x4000 ... 0x7fff => self.nametable[addr as usize & (CIRAM_SIZE - 1)],
             _ => panic!("Unknown PPU address {:04X}", addr)
         }
     }
@@ -677,11 +973,8 @@ impl Memory for Vram {
                 self.mapper.borrow_mut().store_byte_chr(addr, val);
             },
             NAMETABLE_START ... NAMETABLE_END => {
-                let nametable_addr = self.nametable_addr(addr);
-                
-                //println!("nametable write {:04X} {:04X} {:02X}", addr, nametable_addr, val);
-                
-                self.nametable[nametable_addr] = val;
+                //println!("nametable write {:04X} {:02X}", addr, val);
+                self.mapper.borrow_mut().store_nametable(addr, val, &mut self.nametable);
             },
             PALETTE_START ... PALETTE_END => {
                 //println!("palette write {:04X} {:02X}", addr, val);
@@ -696,7 +989,7 @@ impl Memory for Vram {
             },
             0x4000 ... 0x7fff => {
                 //println!("nametable write {:04X} {:02X}", addr, val);
-                self.nametable[addr as usize & (PPU_RAM_SIZE - 1)] = val;
+                self.nametable[addr as usize & (CIRAM_SIZE - 1)] = val;
             }
             _ => panic!("Unknown PPU address {:04X}", addr)
         }
@@ -812,22 +1105,14 @@ impl MaskRegister {
         self.0 & 0b0001_0000 != 0
     }
 
-    // color manipulation stuff unused for now
-    // fn greyscale(&self) -> bool {
-    //     self.0 & 0b0000_0001 != 0
-    // }
-    
-    // fn emphasize_red(&self) -> bool {
-    //     self.0 & 0b0010_0000 != 0
-    // }
-    
-    // fn emphasize_green(&self) -> bool {
-    //     self.0 & 0b0100_0000 != 0
-    // }
-    
-    // fn emphasize_blue(&self) -> bool {
-    //     self.0 & 0b1000_0000 != 0
-    // }
+    fn greyscale(&self) -> bool {
+        self.0 & 0b0000_0001 != 0
+    }
+
+    // red, green and blue emphasis in bits 0-2
+    fn emphasis(&self) -> u16 {
+        (self.0 >> 5) as u16
+    }
 }
 
 impl Deref for MaskRegister {
@@ -845,16 +1130,19 @@ struct Sprite {
     attributes: u8,
     x_position: u8,
     index: u8,
+    // the first sprite looked at during evaluation, which is the one that can trigger a sprite 0 hit
+    sprite_zero: bool,
 }
 
 impl Sprite {
-    fn new(y_position: u8, tile_index: u8, attributes: u8, x_position: u8, index: u8) -> Sprite {
+    fn new(y_position: u8, tile_index: u8, attributes: u8, x_position: u8, index: u8, sprite_zero: bool) -> Sprite {
         Sprite {
             y_position: y_position,
             tile_index: tile_index,
             attributes: attributes,
             x_position: x_position,
             index: index,
+            sprite_zero: sprite_zero,
         }
     }
 }
@@ -880,6 +1168,7 @@ enum SpriteSize {
     Size8x16
 }
 
+#[derive(Eq, PartialEq)]
 enum AddressByte {
     Upper,
     Lower
@@ -0,0 +1,257 @@
+use nes::Nes;
+
+use std::collections::VecDeque;
+
+pub const DEFAULT_SNAPSHOT_INTERVAL: u32 = 4;
+pub const DEFAULT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+// keeps a bounded history of save states so gameplay can be stepped backwards a frame at a time.
+// only the newest snapshot is kept whole, every older one is stored as a delta against the one after it,
+// which means the oldest can be dropped without having to re-encode anything
+pub struct Rewind {
+    interval: u32,
+    memory_budget: usize,
+    memory_used: usize,
+
+    snapshots: VecDeque<Snapshot>,
+    // full state of the newest snapshot
+    current: Vec<u8>
+}
+
+struct Snapshot {
+    frame: u64,
+    // delta that turns the next newer snapshot's state into this one, empty for the newest snapshot
+    delta: Vec<u8>,
+    // input for each frame run after this snapshot was taken
+    inputs: Vec<[u8; 2]>
+}
+
+impl Snapshot {
+    fn size(&self) -> usize {
+        self.delta.len() + self.inputs.len() * 2
+    }
+}
+
+impl Rewind {
+    pub fn new(interval: u32, memory_budget: usize) -> Rewind {
+        Rewind {
+            interval: if interval == 0 { 1 } else { interval },
+            memory_budget: memory_budget,
+            memory_used: 0,
+            snapshots: VecDeque::new(),
+            current: Vec::new()
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.current.clear();
+        self.memory_used = 0;
+    }
+
+    // number of frames that can currently be rewound
+    pub fn frames_available(&self) -> u64 {
+        match (self.snapshots.front(), self.snapshots.back()) {
+            (Some(oldest), Some(newest)) => newest.frame + newest.inputs.len() as u64 - oldest.frame,
+            _ => 0
+        }
+    }
+
+    // call once at the end of every frame while running forwards
+    pub fn capture(&mut self, nes: &Nes) {
+        let input = &nes.cpu.memory_interface.input;
+        let frame_input = [input.port_state(0), input.port_state(1)];
+
+        let take_snapshot = match self.snapshots.back_mut() {
+            Some(newest) => {
+                if newest.frame + newest.inputs.len() as u64 + 1 != nes.frame {
+                    // the emulator jumped (state load, etc) so the history no longer lines up
+                    None
+                } else {
+                    newest.inputs.push(frame_input);
+                    self.memory_used += 2;
+                    Some(newest.inputs.len() as u32 >= self.interval)
+                }
+            },
+            None => Some(true)
+        };
+
+        match take_snapshot {
+            Some(true) => self.push_snapshot(nes),
+            Some(false) => {},
+            None => {
+                self.clear();
+                self.push_snapshot(nes);
+            }
+        }
+
+        self.enforce_budget();
+    }
+
+    // moves the emulator back one frame. returns false if there is no history left
+    pub fn step_back(&mut self, nes: &mut Nes) -> bool {
+        if nes.frame == 0 {
+            return false;
+        }
+        let target = nes.frame - 1;
+
+        // restore from a snapshot before the target so at least one frame is re-run and the picture is correct
+        while self.snapshots.back().map_or(false, |newest| newest.frame >= target) {
+            self.pop_snapshot();
+        }
+
+        let (frame, inputs) = match self.snapshots.back_mut() {
+            Some(newest) => {
+                let replay = (target - newest.frame) as usize;
+                if replay > newest.inputs.len() {
+                    return false;
+                }
+
+                self.memory_used -= (newest.inputs.len() - replay) * 2;
+                newest.inputs.truncate(replay);
+                (newest.frame, newest.inputs.clone())
+            },
+            None => return false
+        };
+
+        if nes.load_state(&self.current).is_err() || nes.frame != frame {
+            self.clear();
+            return false;
+        }
+
+        for input in inputs {
+            nes.cpu.memory_interface.input.set_port_state(0, input[0]);
+            nes.cpu.memory_interface.input.set_port_state(1, input[1]);
+            nes.run_frame();
+        }
+
+        true
+    }
+
+    fn push_snapshot(&mut self, nes: &Nes) {
+        let state = nes.save_state();
+
+        if let Some(newest) = self.snapshots.back_mut() {
+            newest.delta = encode_delta(&state, &self.current);
+            self.memory_used += newest.delta.len();
+        }
+
+        self.memory_used -= self.current.len();
+        self.memory_used += state.len();
+        self.current = state;
+
+        self.snapshots.push_back(Snapshot {
+            frame: nes.frame,
+            delta: Vec::new(),
+            inputs: Vec::new()
+        });
+    }
+
+    fn pop_snapshot(&mut self) {
+        if let Some(newest) = self.snapshots.pop_back() {
+            self.memory_used -= newest.size();
+        }
+
+        match self.snapshots.back_mut() {
+            Some(previous) => {
+                let state = decode_delta(&self.current, &previous.delta);
+                self.memory_used -= previous.delta.len();
+                previous.delta = Vec::new();
+
+                self.memory_used -= self.current.len();
+                self.memory_used += state.len();
+                self.current = state;
+            },
+            None => self.clear()
+        }
+    }
+
+    fn enforce_budget(&mut self) {
+        // always keep the newest snapshot, even if it alone is over budget
+        while self.memory_used > self.memory_budget && self.snapshots.len() > 1 {
+            if let Some(oldest) = self.snapshots.pop_front() {
+                self.memory_used -= oldest.size();
+            }
+        }
+    }
+}
+
+// delta format: target length, then runs of (unchanged byte count, changed byte count, changed bytes xor base)
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len());
+
+    let xor = |i: usize| target[i] ^ base.get(i).cloned().unwrap_or(0);
+
+    let mut i = 0;
+    while i < target.len() {
+        let unchanged_start = i;
+        while i < target.len() && xor(i) == 0 {
+            i += 1;
+        }
+        let unchanged = i - unchanged_start;
+
+        // short unchanged runs are cheaper to keep inside the changed bytes
+        let changed_start = i;
+        while i < target.len() && (xor(i) != 0 || (i + 2 < target.len() && (xor(i + 1) != 0 || xor(i + 2) != 0))) {
+            i += 1;
+        }
+
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, i - changed_start);
+        for j in changed_start .. i {
+            delta.push(xor(j));
+        }
+    }
+
+    delta
+}
+
+fn decode_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+
+    let mut target: Vec<u8> = (0 .. len).map(|i| base.get(i).cloned().unwrap_or(0)).collect();
+
+    let mut i = 0;
+    while pos < delta.len() && i < len {
+        i += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+
+        for _ in 0 .. changed {
+            if i < len && pos < delta.len() {
+                target[i] ^= delta[pos];
+            }
+            i += 1;
+            pos += 1;
+        }
+    }
+
+    target
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        buf.push((val as u8) | 0x80);
+        val >>= 7;
+    }
+    buf.push(val as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+
+    while *pos < buf.len() {
+        let byte = buf[*pos];
+        *pos += 1;
+
+        val |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+
+    val
+}
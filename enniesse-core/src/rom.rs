@@ -1,8 +1,139 @@
-use std::io::Read;
-use std::path::Path;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use archive;
+use archive::ArchiveError;
+use disk;
+use disk::{DiskImage, DiskError};
+use nsf::{Nsf, NsfError};
+use patch;
+use patch::PatchError;
+use romdb;
+use romdb::{DbMirroring, GameInfo};
+use unif;
+use unif::UnifError;
 
 const FILE_HEADER: [u8; 4] = *b"NES\x1a";
+const HEADER_SIZE: usize = 16;
+const FDS_MAPPER: u8 = 20;
+
+// flags 6 bits the database can correct
+const FLAG_VERTICAL: u8 = 1 << 0;
+const FLAG_BATTERY: u8 = 1 << 1;
+const FLAG_FOUR_SCREEN: u8 = 1 << 3;
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    Archive(ArchiveError),
+    Patch(PatchError),
+    Unif(UnifError),
+    Disk(DiskError),
+    Nsf(NsfError),
+    Bios(PathBuf, io::Error),
+    InvalidHeader,
+    UnexpectedEnd
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Archive(ref e) => write!(f, "{}", e),
+            RomError::Patch(ref e) => write!(f, "Failed to apply the patch next to it. {}", e),
+            RomError::Unif(ref e) => write!(f, "Invalid UNIF file. {}", e),
+            RomError::Disk(ref e) => write!(f, "Invalid disk image. {}", e),
+            RomError::Nsf(ref e) => write!(f, "Invalid NSF file. {}", e),
+            RomError::Bios(ref path, ref e) => write!(f, "Failed to load the disk system bios {}: {}", path.display(), e),
+            RomError::InvalidHeader => write!(f, "Not a NES ROM"),
+            RomError::UnexpectedEnd => write!(f, "ROM file is truncated")
+        }
+    }
+}
+
+impl From<ArchiveError> for RomError {
+    fn from(e: ArchiveError) -> RomError {
+        RomError::Archive(e)
+    }
+}
+
+impl From<PatchError> for RomError {
+    fn from(e: PatchError) -> RomError {
+        RomError::Patch(e)
+    }
+}
+
+impl From<UnifError> for RomError {
+    fn from(e: UnifError) -> RomError {
+        RomError::Unif(e)
+    }
+}
+
+impl From<DiskError> for RomError {
+    fn from(e: DiskError) -> RomError {
+        RomError::Disk(e)
+    }
+}
+
+impl From<NsfError> for RomError {
+    fn from(e: NsfError) -> RomError {
+        RomError::Nsf(e)
+    }
+}
+
+// a rom file as it's loaded, unpacked from its archive and with a patch next to it applied
+pub struct PatchedFile {
+    // where the rom would be if it were unpacked next to the archive, for finding saves
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+    // the patch that was applied, if there was one
+    pub patch: Option<PathBuf>
+}
+
+impl PatchedFile {
+    pub fn is_disk(&self) -> bool {
+        self.path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("fds")) || DiskImage::is_disk_image(&self.data)
+    }
+}
+
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<PatchedFile, RomError> {
+    let rom_file = archive::read_rom(path)?;
+    let mut data = rom_file.data;
+    let patch = patch::apply_adjacent(&rom_file.path, &mut data)?;
+
+    Ok(PatchedFile {
+        path: rom_file.path,
+        data: data,
+        patch: patch
+    })
+}
+
+impl Region {
+    // frames per second, from the master clock rate divided by the cycles in a frame
+    pub fn frame_rate(&self) -> f64 {
+        match *self {
+            Region::Ntsc => 60.0988,
+            Region::Pal => 50.0070,
+            Region::Dendy => 50.0070
+        }
+    }
+
+    // cpu cycles per second
+    pub fn cpu_clock_rate(&self) -> f64 {
+        match *self {
+            Region::Ntsc => 1789773.0,
+            Region::Pal => 1662607.0,
+            Region::Dendy => 1773448.0
+        }
+    }
+}
 
 pub struct Rom {
     // number of 16k pages
@@ -14,68 +145,174 @@ pub struct Rom {
     pub flags7: u8,
     
     pub mapper: u8,
+    // nes 2.0 only, 0 for ines headers
+    pub submapper: u8,
+
+    pub region: Region,
     
     pub prg_rom: Box<[u8]>,
-    pub chr_rom: Box<[u8]>
+    pub chr_rom: Box<[u8]>,
+
+    // only some formats carry a name, and ines roms get one from the database
+    pub title: Option<String>,
+
+    // the disk in the drive for the famicom disk system, whose bios is in prg_rom
+    pub disk: Option<DiskImage>,
+    // music rather than a game, which gets a mapper of its own
+    pub nsf: Option<Nsf>
 }
 
 impl Rom {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Rom {
-        let rom_buf = read_file(path);
-        
-        Rom::from(rom_buf)
+    // the rom can be in a zip or gzip archive, and a patch with the same name next to it is applied to it.
+    // nsf files are loaded with from_nsf, and disk images with from_disk and the bios next to them
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+        let file = read_file(path)?;
+
+        if Nsf::is_nsf(&file.data) {
+            Ok(Rom::from_nsf(Nsf::from_bytes(&file.data)?))
+        } else if file.is_disk() {
+            let bios_path = file.path.with_file_name(disk::BIOS_NAME);
+            let bios = fs::read(&bios_path).map_err(|e| RomError::Bios(bios_path, e))?;
+
+            Ok(Rom::from_disk(bios.into_boxed_slice(), DiskImage::from_bytes(&file.data)?))
+        } else {
+            Rom::parse(&file.data)
+        }
     }
-}
 
-fn read_file<P: AsRef<Path>>(path: P) -> Box<[u8]> {
-    let mut rom_file = fs::File::open(path).unwrap();
-    let mut rom_buf = Vec::<u8>::new();
-    let _ = rom_file.read_to_end(&mut rom_buf);
-    
-    rom_buf.into_boxed_slice()
-}
+    // an ines, nes 2.0 or unif image
+    pub fn parse(data: &[u8]) -> Result<Rom, RomError> {
+        if data.starts_with(&unif::UNIF_HEADER) {
+            return Ok(unif::parse(data)?);
+        }
 
-// TODO: load from reader rather than a buffer?
-impl From<Box<[u8]>> for Rom {
-    fn from(value: Box<[u8]>) -> Rom {
-        let header = &value[0..4];
-        
-        if header != FILE_HEADER {
-            panic!("Invalid ROM file. {:?}", header);
+        if data.len() < HEADER_SIZE || data[0..4] != FILE_HEADER {
+            return Err(RomError::InvalidHeader);
         }
         
-        let prg_rom_size = value[4];
-        let chr_rom_size = value[5];
-        let flags6 = value[6];
-        let flags7 = value[7];
+        let prg_rom_size = data[4];
+        let chr_rom_size = data[5];
+        let flags6 = data[6];
+        let flags7 = data[7];
         
         // TODO: other flags
         let mapper_lower = flags6 & 0b1111_0000;
         let mapper_upper = flags7 & 0b1111_0000;
+        let submapper = if flags7 & 0x0c == 0x08 { data[8] >> 4 } else { 0 };
+
+        // nes 2.0 headers have the timing in byte 12, ines only has a pal bit in byte 9
+        let region = if flags7 & 0x0c == 0x08 {
+            match data[12] & 3 {
+                1 => Region::Pal,
+                3 => Region::Dendy,
+                _ => Region::Ntsc
+            }
+        } else if data[9] & 1 == 1 {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        };
         
         let prg_rom_bytes: usize = prg_rom_size as usize * 16384;
         let chr_rom_bytes: usize = chr_rom_size as usize * 8192;
         
-        let prg_rom_end = 16 + prg_rom_bytes;
+        let prg_rom_end = HEADER_SIZE + prg_rom_bytes;
         let chr_rom_end = prg_rom_end + chr_rom_bytes;
+        if data.len() < chr_rom_end {
+            return Err(RomError::UnexpectedEnd);
+        }
         
-        // TODO: error handling
-        let mut prg_rom = Vec::<u8>::new();
-        let mut rom_data = &value[16..prg_rom_end]; 
-        rom_data.read_to_end(&mut prg_rom).unwrap();
-        
-        let mut chr_rom = Vec::<u8>::new();
-        let mut rom_data = &value[prg_rom_end..chr_rom_end];
-        rom_data.read_to_end(&mut chr_rom).unwrap();
-        
-        Rom {
+        let mut rom = Rom {
             prg_rom_size: prg_rom_size,
             chr_rom_size: chr_rom_size,
             flags6: flags6,
             flags7: flags7,
             mapper: mapper_upper | (mapper_lower >> 4),
-            prg_rom: prg_rom.into_boxed_slice(),
-            chr_rom: chr_rom.into_boxed_slice()
+            submapper: submapper,
+            region: region,
+            prg_rom: data[HEADER_SIZE .. prg_rom_end].to_vec().into_boxed_slice(),
+            chr_rom: data[prg_rom_end .. chr_rom_end].to_vec().into_boxed_slice(),
+            title: None,
+            disk: None,
+            nsf: None
+        };
+
+        // plenty of dumps are floating around with bad headers
+        if let Some(game) = romdb::lookup(&rom.prg_rom, &rom.chr_rom) {
+            rom.apply_game_info(game);
         }
+
+        Ok(rom)
     }
-}
\ No newline at end of file
+
+    // the disk system is treated as a cartridge with the bios as its only rom
+    pub fn from_disk(bios: Box<[u8]>, disk: DiskImage) -> Rom {
+        Rom {
+            prg_rom_size: (bios.len() / 16384) as u8,
+            chr_rom_size: 0,
+            flags6: 0,
+            flags7: 0,
+            mapper: FDS_MAPPER,
+            submapper: 0,
+            region: Region::Ntsc,
+            prg_rom: bios,
+            chr_rom: Box::new([]),
+            title: None,
+            disk: Some(disk),
+            nsf: None
+        }
+    }
+
+    pub fn from_nsf(nsf: Nsf) -> Rom {
+        Rom {
+            prg_rom_size: 0,
+            chr_rom_size: 0,
+            flags6: 0,
+            flags7: 0,
+            mapper: 0,
+            submapper: 0,
+            region: nsf.region(),
+            prg_rom: Box::new([]),
+            chr_rom: Box::new([]),
+            title: None,
+            disk: None,
+            nsf: Some(nsf)
+        }
+    }
+
+    // replaces what the header says with the database's entry for the rom
+    fn apply_game_info(&mut self, game: GameInfo) {
+        // mappers past 255 need a nes 2.0 header and none of them are supported anyway
+        if let Some(mapper) = game.mapper {
+            if mapper <= 0xff {
+                self.mapper = mapper as u8;
+                self.flags6 = (self.flags6 & 0x0f) | (self.mapper & 0x0f) << 4;
+                self.flags7 = (self.flags7 & 0x0f) | (self.mapper & 0xf0);
+            }
+        }
+        if let Some(submapper) = game.submapper {
+            self.submapper = submapper;
+        }
+        if let Some(mirroring) = game.mirroring {
+            self.flags6 &= !(FLAG_VERTICAL | FLAG_FOUR_SCREEN);
+            self.flags6 |= match mirroring {
+                DbMirroring::Horizontal => 0,
+                DbMirroring::Vertical => FLAG_VERTICAL,
+                DbMirroring::FourScreen => FLAG_FOUR_SCREEN
+            };
+        }
+        if let Some(battery) = game.battery {
+            self.flags6 = if battery { self.flags6 | FLAG_BATTERY } else { self.flags6 & !FLAG_BATTERY };
+        }
+        if let Some(region) = game.region {
+            self.region = region;
+        }
+        self.title = game.title;
+    }
+}
+
+impl From<Box<[u8]>> for Rom {
+    fn from(value: Box<[u8]>) -> Rom {
+        Rom::parse(&value).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
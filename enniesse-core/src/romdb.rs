@@ -0,0 +1,169 @@
+// a database of games for fixing bad ines headers, in the nes 2.0 xml format. games are matched on the
+// prg and chr rom without the header, since that's the part that's the same in every dump
+
+use std::sync::OnceLock;
+
+use hash;
+use rom::Region;
+
+const DATABASE: &'static str = include_str!("nes20db.xml");
+
+// parsed the first time a rom is looked up
+static GAMES: OnceLock<Vec<GameInfo>> = OnceLock::new();
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum DbMirroring {
+    Horizontal,
+    Vertical,
+    FourScreen
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GameInfo {
+    // from the comment at the start of each game, which holds the file name it was dumped as
+    pub title: Option<String>,
+    pub crc32: Option<u32>,
+    pub sha1: Option<[u8; 20]>,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    // None when the mapper controls it
+    pub mirroring: Option<DbMirroring>,
+    pub battery: Option<bool>,
+    pub region: Option<Region>
+}
+
+// the entry for a rom's prg and chr data, if there is one
+pub fn lookup(prg_rom: &[u8], chr_rom: &[u8]) -> Option<GameInfo> {
+    let games = GAMES.get_or_init(games);
+
+    let crc32 = hash::crc32_update(hash::crc32(prg_rom), chr_rom);
+    if let Some(game) = games.iter().find(|game| game.crc32 == Some(crc32)) {
+        return Some(game.clone());
+    }
+
+    // the sha-1 is much slower to work out, and only matters for entries that don't have a crc32
+    if !games.iter().any(|game| game.crc32.is_none() && game.sha1.is_some()) {
+        return None;
+    }
+
+    let mut data = prg_rom.to_vec();
+    data.extend_from_slice(chr_rom);
+    let sha1 = hash::sha1(&data);
+
+    games.iter().find(|game| game.crc32.is_none() && game.sha1 == Some(sha1)).cloned()
+}
+
+pub fn games() -> Vec<GameInfo> {
+    let mut games = Vec::new();
+    let mut game: Option<GameInfo> = None;
+
+    let mut rest = DATABASE;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start ..];
+
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").unwrap_or(rest.len());
+            if let Some(ref mut game) = game {
+                if game.title.is_none() {
+                    game.title = Some(comment_title(rest[4 .. end].trim()));
+                }
+            }
+            rest = &rest[(end + 3).min(rest.len()) ..];
+            continue;
+        }
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break
+        };
+        let tag = rest[1 .. end].trim_end_matches('/').trim();
+        rest = &rest[end + 1 ..];
+
+        let name = tag.split_whitespace().next().unwrap_or("");
+        match name {
+            "game" => game = Some(GameInfo::default()),
+            "/game" => games.extend(game.take()),
+            "rom" | "pcb" | "console" => {
+                if let Some(ref mut game) = game {
+                    for (key, value) in attributes(tag) {
+                        match (name, key) {
+                            ("rom", "crc32") => game.crc32 = u32::from_str_radix(value, 16).ok(),
+                            ("rom", "sha1") => game.sha1 = parse_sha1(value),
+                            ("pcb", "mapper") => game.mapper = value.parse().ok(),
+                            ("pcb", "submapper") => game.submapper = value.parse().ok(),
+                            ("pcb", "mirroring") => {
+                                game.mirroring = match value {
+                                    "H" => Some(DbMirroring::Horizontal),
+                                    "V" => Some(DbMirroring::Vertical),
+                                    "4" => Some(DbMirroring::FourScreen),
+                                    _ => None
+                                };
+                            },
+                            ("pcb", "battery") => game.battery = Some(value == "1"),
+                            ("console", "region") => {
+                                // 2 is a game that works on both, which runs as ntsc
+                                game.region = match value {
+                                    "0" | "2" => Some(Region::Ntsc),
+                                    "1" => Some(Region::Pal),
+                                    "3" => Some(Region::Dendy),
+                                    _ => None
+                                };
+                            },
+                            _ => {}
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    games
+}
+
+// name="value" pairs after the element name
+fn attributes(tag: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    let mut rest = &tag[tag.find(char::is_whitespace).unwrap_or(tag.len()) ..];
+
+    while let Some(equals) = rest.find('=') {
+        let key = rest[.. equals].trim();
+        let value = rest[equals + 1 ..].trim_start();
+        let quote = match value.chars().next() {
+            Some(quote @ '"') | Some(quote @ '\'') => quote,
+            _ => break
+        };
+        let end = match value[1 ..].find(quote) {
+            Some(end) => end + 1,
+            None => break
+        };
+
+        attributes.push((key, &value[1 .. end]));
+        rest = &value[end + 1 ..];
+    }
+
+    attributes
+}
+
+// the comments are paths like "Games\Title (USA).nes"
+fn comment_title(comment: &str) -> String {
+    let name = comment.rsplit(|c| c == '\\' || c == '/').next().unwrap_or(comment);
+    let name = match name.rfind('.') {
+        Some(dot) => &name[.. dot],
+        None => name
+    };
+
+    name.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+fn parse_sha1(value: &str) -> Option<[u8; 20]> {
+    if value.len() != 40 {
+        return None;
+    }
+
+    let mut sha1 = [0; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(value.get(i * 2 .. i * 2 + 2)?, 16).ok()?;
+    }
+    Some(sha1)
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+
+const STATE_MAGIC: [u8; 4] = *b"ENSS";
+const STATE_VERSION: u8 = 11;
+
+// implemented by each component that makes up the machine state
+pub trait SaveState {
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader);
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum StateError {
+    InvalidHeader,
+    UnsupportedVersion(u8),
+    UnexpectedEnd
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::InvalidHeader => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "Unsupported save state version {}", version),
+            StateError::UnexpectedEnd => write!(f, "Save state is truncated")
+        }
+    }
+}
+
+pub struct StateWriter {
+    buf: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&STATE_MAGIC);
+        buf.push(STATE_VERSION);
+
+        StateWriter {
+            buf: buf
+        }
+    }
+
+    pub fn write_u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.buf.push(val as u8);
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        self.buf.push(val as u8);
+        self.buf.push((val >> 8) as u8);
+    }
+
+    pub fn write_i16(&mut self, val: i16) {
+        self.write_u16(val as u16);
+    }
+
+    pub fn write_u32(&mut self, val: u32) {
+        self.write_u16(val as u16);
+        self.write_u16((val >> 16) as u16);
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.write_u32(val as u32);
+        self.write_u32((val >> 32) as u32);
+    }
+
+    // length prefixed so variable sized blocks can be read back safely
+    pub fn write_bytes(&mut self, val: &[u8]) {
+        self.write_u32(val.len() as u32);
+        self.buf.extend_from_slice(val);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    overrun: bool
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Result<StateReader<'a>, StateError> {
+        if buf.len() < 5 || buf[0..4] != STATE_MAGIC {
+            return Err(StateError::InvalidHeader);
+        }
+
+        if buf[4] != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(buf[4]));
+        }
+
+        Ok(StateReader {
+            buf: buf,
+            pos: 5,
+            overrun: false
+        })
+    }
+
+    // reads past the end return zeroes, check this after loading
+    pub fn is_overrun(&self) -> bool {
+        self.overrun
+    }
+
+    pub fn read_u8(&mut self) -> u8 {
+        if self.pos >= self.buf.len() {
+            self.overrun = true;
+            return 0;
+        }
+
+        let val = self.buf[self.pos];
+        self.pos += 1;
+
+        val
+    }
+
+    pub fn read_bool(&mut self) -> bool {
+        self.read_u8() != 0
+    }
+
+    pub fn read_u16(&mut self) -> u16 {
+        self.read_u8() as u16 | (self.read_u8() as u16) << 8
+    }
+
+    pub fn read_i16(&mut self) -> i16 {
+        self.read_u16() as i16
+    }
+
+    pub fn read_u32(&mut self) -> u32 {
+        self.read_u16() as u32 | (self.read_u16() as u32) << 16
+    }
+
+    pub fn read_u64(&mut self) -> u64 {
+        self.read_u32() as u64 | (self.read_u32() as u64) << 32
+    }
+
+    // reads a block written with write_bytes into a fixed size buffer
+    pub fn read_bytes_into(&mut self, out: &mut [u8]) {
+        let len = self.read_u32() as usize;
+
+        if len != out.len() || self.pos + len > self.buf.len() {
+            self.overrun = true;
+            return;
+        }
+
+        out.copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+    }
+
+    pub fn read_bytes(&mut self) -> Vec<u8> {
+        let len = self.read_u32() as usize;
+
+        if self.pos + len > self.buf.len() {
+            self.overrun = true;
+            return Vec::new();
+        }
+
+        let val = self.buf[self.pos..self.pos + len].to_vec();
+        self.pos += len;
+
+        val
+    }
+}
@@ -0,0 +1,154 @@
+// unif images, which name the board instead of giving a mapper number. the rom and everything else
+// comes in chunks, each a 4 byte id and a 4 byte length followed by the data
+
+use std::fmt;
+
+use rom::{Rom, Region};
+
+pub const UNIF_HEADER: [u8; 4] = *b"UNIF";
+const UNIF_HEADER_SIZE: usize = 32;
+
+// ines flags 6 bits the chunks are turned into
+const FLAG_VERTICAL: u8 = 1 << 0;
+const FLAG_BATTERY: u8 = 1 << 1;
+const FLAG_FOUR_SCREEN: u8 = 1 << 3;
+
+// boards are named after the cartridge pcb, with a prefix for who made it that doesn't change the wiring
+const BOARD_PREFIXES: [&'static str; 6] = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-", "IREM-"];
+
+// board name, mapper, submapper. the konami names don't say how the address lines are wired, so vrc4
+// boards decode both of the usual arrangements and vrc2 takes the most common one
+const BOARDS: [(&'static str, u8, u8); 18] = [
+    ("NROM", 0, 0),
+    ("NROM-128", 0, 0),
+    ("NROM-256", 0, 0),
+    ("HROM", 0, 0),
+    ("RROM", 0, 0),
+    ("RROM-128", 0, 0),
+    ("ELROM", 5, 0),
+    ("EKROM", 5, 0),
+    ("ETROM", 5, 0),
+    ("EWROM", 5, 0),
+    ("NAMCOT-163", 19, 0),
+    ("KONAMI-VRC-2", 23, 3),
+    ("KONAMI-VRC-4", 23, 0),
+    ("KONAMI-VRC-6", 24, 0),
+    ("KONAMI-VRC-7", 85, 0),
+    ("JLROM", 69, 0),
+    ("JSROM", 69, 0),
+    ("SUNSOFT-5B", 69, 0),
+];
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum UnifError {
+    NotUnif,
+    UnexpectedEnd,
+    MissingBoard,
+    UnknownBoard(String),
+    NoPrgRom
+}
+
+impl fmt::Display for UnifError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnifError::NotUnif => write!(f, "Not a unif file"),
+            UnifError::UnexpectedEnd => write!(f, "Unif file is truncated"),
+            UnifError::MissingBoard => write!(f, "Unif file has no MAPR chunk"),
+            UnifError::UnknownBoard(ref name) => write!(f, "Unsupported board {}", name),
+            UnifError::NoPrgRom => write!(f, "Unif file has no PRG chunks")
+        }
+    }
+}
+
+// the mapper and submapper for a unif board name
+pub fn board_mapper(name: &str) -> Option<(u8, u8)> {
+    let name = BOARD_PREFIXES.iter().fold(name, |name, prefix| if name.starts_with(prefix) { &name[prefix.len() ..] } else { name });
+
+    BOARDS.iter().find(|&&(board, _, _)| board == name).map(|&(_, mapper, submapper)| (mapper, submapper))
+}
+
+pub fn parse(data: &[u8]) -> Result<Rom, UnifError> {
+    if !data.starts_with(&UNIF_HEADER) {
+        return Err(UnifError::NotUnif);
+    }
+    let mut data = data.get(UNIF_HEADER_SIZE ..).ok_or(UnifError::UnexpectedEnd)?;
+
+    let mut board = None;
+    let mut title = None;
+    let mut prg: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr: [Option<&[u8]>; 16] = [None; 16];
+    let mut flags6 = 0;
+    let mut region = Region::Ntsc;
+
+    while !data.is_empty() {
+        let header = data.get(.. 8).ok_or(UnifError::UnexpectedEnd)?;
+        let length = header[4] as usize | (header[5] as usize) << 8 | (header[6] as usize) << 16 | (header[7] as usize) << 24;
+        let chunk = data.get(8 .. 8 + length).ok_or(UnifError::UnexpectedEnd)?;
+        data = &data[8 + length ..];
+
+        match &header[.. 4] {
+            b"MAPR" => board = Some(chunk_string(chunk)),
+            b"NAME" => title = Some(chunk_string(chunk)),
+            b"MIRR" => {
+                // 0 and 1 are hardwired, 4 is four screen, the rest are up to the mapper
+                match chunk.first() {
+                    Some(&1) => flags6 |= FLAG_VERTICAL,
+                    Some(&4) => flags6 |= FLAG_FOUR_SCREEN,
+                    _ => {}
+                }
+            },
+            b"BATR" => flags6 |= FLAG_BATTERY,
+            b"TVCI" => {
+                if chunk.first() == Some(&1) {
+                    region = Region::Pal;
+                }
+            },
+            id if id.starts_with(b"PRG") => {
+                if let Some(i) = chunk_index(id[3]) {
+                    prg[i] = Some(chunk);
+                }
+            },
+            id if id.starts_with(b"CHR") => {
+                if let Some(i) = chunk_index(id[3]) {
+                    chr[i] = Some(chunk);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    let board = board.ok_or(UnifError::MissingBoard)?;
+    let (mapper, submapper) = board_mapper(&board).ok_or_else(|| UnifError::UnknownBoard(board.clone()))?;
+
+    // the numbered chunks go one after another
+    let prg_rom: Vec<u8> = prg.iter().filter_map(|&chunk| chunk).flat_map(|chunk| chunk.iter().cloned()).collect();
+    let chr_rom: Vec<u8> = chr.iter().filter_map(|&chunk| chunk).flat_map(|chunk| chunk.iter().cloned()).collect();
+    if prg_rom.is_empty() {
+        return Err(UnifError::NoPrgRom);
+    }
+
+    Ok(Rom {
+        prg_rom_size: ((prg_rom.len() + 0x3fff) / 0x4000) as u8,
+        chr_rom_size: ((chr_rom.len() + 0x1fff) / 0x2000) as u8,
+        flags6: flags6 | (mapper & 0x0f) << 4,
+        flags7: mapper & 0xf0,
+        mapper: mapper,
+        submapper: submapper,
+        region: region,
+        prg_rom: prg_rom.into_boxed_slice(),
+        chr_rom: chr_rom.into_boxed_slice(),
+        title: title,
+        disk: None,
+        nsf: None
+    })
+}
+
+fn chunk_string(chunk: &[u8]) -> String {
+    let end = chunk.iter().position(|&b| b == 0).unwrap_or(chunk.len());
+    String::from_utf8_lossy(&chunk[.. end]).trim().to_string()
+}
+
+// the hex digit at the end of PRGn and CHRn
+fn chunk_index(digit: u8) -> Option<usize> {
+    (digit as char).to_digit(16).map(|i| i as usize)
+}
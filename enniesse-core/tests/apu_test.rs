@@ -0,0 +1,108 @@
+extern crate enniesse_core;
+
+use enniesse_core::apu::Apu;
+use enniesse_core::nes::Nes;
+use enniesse_core::memory::Memory;
+use enniesse_core::rom::{Rom, Region};
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+fn new_nes() -> Nes {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    nes
+}
+
+fn run(apu: &mut Apu, cycles: u32) {
+    for _ in 0 .. cycles {
+        apu.step();
+    }
+}
+
+#[test]
+fn test_frame_interrupt() {
+    let mut nes = new_nes();
+    let apu = &mut nes.cpu.memory_interface.apu;
+
+    // four step mode raises the interrupt at the end of each sequence
+    apu.store_byte(0x4017, 0x00);
+    run(apu, 29828);
+    assert!(!apu.frame_interrupt);
+    run(apu, 1);
+    assert!(apu.frame_interrupt);
+
+    // until it's read, and again the next time around
+    assert_eq!(apu.load_byte(0x4015) & 0x40, 0x40);
+    assert!(!apu.frame_interrupt);
+    run(apu, 29829);
+    assert!(apu.frame_interrupt);
+
+    // setting the inhibit flag clears it and keeps it off
+    apu.store_byte(0x4017, 0x40);
+    assert!(!apu.frame_interrupt);
+    run(apu, 60000);
+    assert!(!apu.frame_interrupt);
+
+    // five step mode never raises it
+    apu.store_byte(0x4017, 0x80);
+    run(apu, 80000);
+    assert!(!apu.frame_interrupt);
+}
+
+#[test]
+fn test_frame_interrupt_pal() {
+    let mut nes = new_nes();
+    let apu = &mut nes.cpu.memory_interface.apu;
+    apu.set_region(Region::Pal);
+
+    apu.store_byte(0x4017, 0x00);
+    run(apu, 33252);
+    assert!(!apu.frame_interrupt);
+    run(apu, 1);
+    assert!(apu.frame_interrupt);
+}
+
+#[test]
+fn test_length_counter() {
+    let mut nes = new_nes();
+    let apu = &mut nes.cpu.memory_interface.apu;
+    apu.store_byte(0x4017, 0x40);
+
+    // pulse 1 with a length of 10, clocked twice per four step sequence
+    apu.store_byte(0x4015, 0x01);
+    apu.store_byte(0x4000, 0x00);
+    apu.store_byte(0x4003, 0x00);
+    assert_eq!(apu.load_byte(0x4015) & 0x01, 0x01);
+
+    run(apu, 29829 * 5 - 1);
+    assert_eq!(apu.load_byte(0x4015) & 0x01, 0x01);
+    run(apu, 1);
+    assert_eq!(apu.load_byte(0x4015) & 0x01, 0x00);
+
+    // the halt flag stops it counting down
+    apu.store_byte(0x4000, 0x20);
+    apu.store_byte(0x4003, 0x00);
+    run(apu, 29829 * 6);
+    assert_eq!(apu.load_byte(0x4015) & 0x01, 0x01);
+}
+
+#[test]
+fn test_dmc_interrupt() {
+    let mut nes = new_nes();
+    let apu = &mut nes.cpu.memory_interface.apu;
+
+    // a one byte sample at $c000 with the interrupt enabled
+    apu.store_byte(0x4010, 0x8f);
+    apu.store_byte(0x4012, 0x00);
+    apu.store_byte(0x4013, 0x00);
+    apu.store_byte(0x4015, 0x10);
+    assert_eq!(apu.load_byte(0x4015) & 0x10, 0x10);
+
+    run(apu, 1);
+    assert_eq!(apu.load_byte(0x4015) & 0x90, 0x80);
+    assert!(apu.dmc_interrupt);
+
+    // writing the status register acknowledges it
+    apu.store_byte(0x4015, 0x00);
+    assert!(!apu.dmc_interrupt);
+}
@@ -0,0 +1,102 @@
+extern crate enniesse_core;
+extern crate flate2;
+extern crate zip;
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use zip::ZipWriter;
+use zip::write::FileOptions;
+
+use enniesse_core::archive;
+use enniesse_core::archive::ArchiveError;
+use enniesse_core::rom::{Rom, RomError};
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+    let mut zip = ZipWriter::new(File::create(path).unwrap());
+    for &(name, data) in files {
+        zip.start_file(name, FileOptions::default()).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[test]
+fn test_archives() {
+    let dir = env::temp_dir().join("enniesse_archive_test");
+    fs::create_dir_all(&dir).unwrap();
+    let nestest = fs::read(TEST_ROM_PATH).unwrap();
+
+    // the readme is skipped for the first rom
+    let zip_path = dir.join("roms.zip");
+    write_zip(&zip_path, &[("readme.txt", b"hello"), ("games/nestest.nes", &nestest), ("other.NES", b"NES\x1a")]);
+
+    let rom_file = archive::read_rom(&zip_path).unwrap();
+    assert_eq!(rom_file.path, dir.join("nestest.nes"));
+    assert_eq!(rom_file.data, nestest);
+
+    let rom_file = archive::read_rom(dir.join("roms.zip#other.NES")).unwrap();
+    assert_eq!(rom_file.data, b"NES\x1a");
+
+    let rom = Rom::from_file(dir.join("roms.zip#games/nestest.nes")).unwrap();
+    assert_eq!(rom.title, Some("nestest".to_string()));
+
+    match archive::read_rom(dir.join("roms.zip#missing.nes")) {
+        Err(ArchiveError::MissingEntry(name)) => assert_eq!(name, "missing.nes"),
+        _ => panic!("found a file that isn't in the archive")
+    }
+
+    let empty_path = dir.join("empty.zip");
+    write_zip(&empty_path, &[("readme.txt", b"hello")]);
+    match archive::read_rom(&empty_path) {
+        Err(ArchiveError::NoRom) => {},
+        _ => panic!("found a rom in an archive without one")
+    }
+
+    let gzip_path = dir.join("nestest.nes.gz");
+    let mut gzip = GzEncoder::new(File::create(&gzip_path).unwrap(), Compression::default());
+    gzip.write_all(&nestest).unwrap();
+    gzip.finish().unwrap();
+
+    let rom_file = archive::read_rom(&gzip_path).unwrap();
+    assert_eq!(rom_file.path, dir.join("nestest.nes"));
+    assert_eq!(rom_file.data, nestest);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_rom_formats() {
+    let dir = env::temp_dir().join("enniesse_format_test");
+    fs::create_dir_all(&dir).unwrap();
+
+    // an nsf with one track, all the way through to its program
+    let mut nsf = b"NESM\x1a\x01\x01\x01\x00\x80\x00\x80\x00\x80".to_vec();
+    nsf.resize(0x80, 0);
+    nsf.push(0x60);
+    let zip_path = dir.join("music.zip");
+    write_zip(&zip_path, &[("music.nsf", &nsf), ("short.nes", b"NES\x1a\x02\x01")]);
+
+    let rom = Rom::from_file(&zip_path).unwrap();
+    assert_eq!(rom.nsf.map(|nsf| nsf.track_count), Some(1));
+
+    assert!(matches!(Rom::from_file(dir.join("music.zip#short.nes")), Err(RomError::InvalidHeader)));
+
+    let truncated = dir.join("truncated.nes");
+    fs::write(&truncated, b"NES\x1a\x02\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00").unwrap();
+    assert!(matches!(Rom::from_file(&truncated), Err(RomError::UnexpectedEnd)));
+
+    // disks need the bios next to them
+    let disk = dir.join("game.fds");
+    fs::write(&disk, b"FDS\x1a\x01").unwrap();
+    assert!(matches!(Rom::from_file(&disk), Err(RomError::Bios(ref path, _)) if path == &dir.join("disksys.rom")));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -6,6 +6,7 @@ use std::io::{BufReader, BufRead};
 use std::fmt;
 
 use enniesse_core::cpu::Cpu;
+use enniesse_core::mapper;
 use enniesse_core::memory::Memory;
 use enniesse_core::rom::Rom;
 
@@ -16,8 +17,11 @@ const LOG_REGEX: &'static str =
 
 #[test]
 fn test_cpu() {
-    let rom = Rom::from_file(TEST_ROM_PATH);
-    let mut cpu = Cpu::new(Box::new(rom));
+    let rom = Rom::from_file(TEST_ROM_PATH).unwrap();
+    let mut cpu = Cpu::new(mapper::load_mapper(Box::new(rom)).unwrap());
+    // nestest's automated mode starts at c000 with the state the cpu has after reset
+    cpu.reg_pc = 0xc000;
+    cpu.reg_sp = 0xfd;
     let test = CpuTest::new();
     
     let log = File::open(LOG_FILE_PATH).unwrap();
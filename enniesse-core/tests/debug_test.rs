@@ -0,0 +1,54 @@
+extern crate enniesse_core;
+
+use enniesse_core::nes::Nes;
+use enniesse_core::memory::Memory;
+use enniesse_core::ppu::debug;
+use enniesse_core::ppu::palette::Palette;
+use enniesse_core::rom::Rom;
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+#[test]
+fn test_debug_views() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    let palette = Palette::default();
+    let ppu = &mut nes.cpu.memory_interface.ppu;
+
+    // address writes are ignored until the ppu has warmed up
+    for _ in 0 .. 262 {
+        ppu.run(false);
+    }
+
+    // backdrop and the last color of the third background palette
+    ppu.store_byte(0x2006, 0x3f);
+    ppu.store_byte(0x2006, 0x00);
+    ppu.store_byte(0x2007, 0x0f);
+    ppu.store_byte(0x2006, 0x3f);
+    ppu.store_byte(0x2006, 0x0b);
+    ppu.store_byte(0x2007, 0x16);
+
+    ppu.store_byte(0x2003, 0x04);
+    for &byte in &[0x20, 0x02, 0xc1, 0x30] {
+        ppu.store_byte(0x2004, byte);
+    }
+
+    // tile 2 starts with a solid pixel in color 3
+    let patterns = debug::pattern_tables(ppu, &palette, 2);
+    assert_eq!((patterns.width, patterns.height), (256, 128));
+    assert_eq!(patterns.pixel(16, 0), palette.rgb(0x16));
+    assert_eq!(patterns.pixel(17, 0), palette.rgb(0x0f));
+
+    let sprite = debug::oam_sprites(ppu)[1];
+    assert_eq!((sprite.x, sprite.y, sprite.tile, sprite.palette), (0x30, 0x20, 0x02, 1));
+    assert!(sprite.flip_vertical && sprite.flip_horizontal && !sprite.behind_background);
+
+    let palette_ram = debug::palette_ram(ppu, &palette);
+    assert_eq!(palette_ram.pixel(11 * 16, 0), palette.rgb(0x16));
+    // $3f10 mirrors the backdrop
+    assert_eq!(palette_ram.pixel(0, 16), palette.rgb(0x0f));
+
+    let nametables = debug::nametables(ppu, &palette, false);
+    assert_eq!((nametables.width, nametables.height), (512, 480));
+    assert_eq!(debug::oam(ppu, &palette).height, 64);
+}
@@ -0,0 +1,102 @@
+extern crate enniesse_core;
+
+use enniesse_core::disk::{DiskImage, DiskError, SIDE_SIZE};
+use enniesse_core::rom::Rom;
+use enniesse_core::mapper;
+
+// a side with the disk info block, a file count block and one file
+fn disk_side(file: &[u8]) -> Vec<u8> {
+    let mut side = vec![1];
+    side.extend_from_slice(b"*NINTENDO-HVC*");
+    side.resize(56, 0);
+
+    side.extend_from_slice(&[2, 1]);
+
+    let mut header = vec![3, 0, 0];
+    header.extend_from_slice(b"FILENAME");
+    header.extend_from_slice(&[0x00, 0x60, file.len() as u8, (file.len() >> 8) as u8, 0]);
+    side.extend(header);
+
+    side.push(4);
+    side.extend_from_slice(file);
+
+    side.resize(SIDE_SIZE, 0);
+    side
+}
+
+#[test]
+fn test_disk_image() {
+    let mut image = b"FDS\x1a\x02".to_vec();
+    image.resize(16, 0);
+    image.extend(disk_side(&[0x11; 4]));
+    image.extend(disk_side(&[0x22; 4]));
+
+    let mut disk = DiskImage::from_bytes(&image).unwrap();
+    assert_eq!(disk.sides.len(), 2);
+    assert!(DiskImage::is_disk_image(&image));
+    assert!(disk.changes().is_none());
+
+    match DiskImage::from_bytes(&image[.. image.len() - 1]) {
+        Err(DiskError::InvalidSize(_)) => {},
+        _ => panic!("expected an invalid size")
+    }
+
+    // changes come out as a patch that can be put back on a fresh copy
+    disk.sides[1][100] = 0x33;
+    let changes = disk.changes().unwrap();
+
+    let mut reloaded = DiskImage::from_bytes(&image).unwrap();
+    reloaded.apply_changes(&changes).unwrap();
+    assert_eq!(reloaded.sides[1][100], 0x33);
+    assert_eq!(reloaded.to_bytes(), disk.to_bytes());
+}
+
+#[test]
+fn test_fds_drive() {
+    let disk = DiskImage::from_bytes(&disk_side(&[0x11; 4])).unwrap();
+    let mut fds = mapper::load_mapper(Box::new(Rom::from_disk(vec![0xea; 0x2000].into_boxed_slice(), disk))).unwrap();
+
+    assert_eq!(fds.load_byte_prg(0xfffc), 0xea);
+    assert_eq!(fds.disk_side(), (0, true));
+
+    // motor on, reading, with the byte transfer irq
+    fds.store_byte_prg(0x4023, 1);
+    fds.store_byte_prg(0x4025, 0xc5);
+
+    // the gap and the start mark are skipped, so the first irq is for the block type
+    let mut bytes = Vec::new();
+    for _ in 0 .. 1000000 {
+        fds.clock_cpu();
+        if fds.irq_pending() {
+            bytes.push(fds.load_byte_prg(0x4031));
+            if bytes.len() == 4 {
+                break;
+            }
+        }
+    }
+    assert_eq!(bytes, vec![1, b'*', b'N', b'I']);
+
+    // ejected, the drive reports no disk
+    fds.set_disk_side(0, false);
+    assert_eq!(fds.load_byte_prg(0x4032) & 7, 7);
+}
+
+#[test]
+fn test_fds_mod_table_odd_position() {
+    let disk = DiskImage::from_bytes(&disk_side(&[0x11; 4])).unwrap();
+    let mut fds = mapper::load_mapper(Box::new(Rom::from_disk(vec![0xea; 0x2000].into_boxed_slice(), disk))).unwrap();
+    fds.store_byte_prg(0x4023, 3);
+
+    // the modulator steps once every 17 cycles at $fff, so halting it after 17 leaves it at position 1
+    fds.store_byte_prg(0x4086, 0xff);
+    fds.store_byte_prg(0x4087, 0x0f);
+    for _ in 0 .. 17 {
+        fds.clock_cpu();
+    }
+    fds.store_byte_prg(0x4087, 0x80);
+
+    // enough writes to go past the end of the table from there
+    for _ in 0 .. 40 {
+        fds.store_byte_prg(0x4088, 1);
+    }
+}
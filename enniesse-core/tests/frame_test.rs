@@ -0,0 +1,36 @@
+extern crate enniesse_core;
+
+use enniesse_core::nes::Nes;
+use enniesse_core::apu;
+use enniesse_core::ppu;
+use enniesse_core::rom::{Rom, Region};
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+#[test]
+fn test_run_frame() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    nes.run_frame();
+
+    let info = nes.run_frame();
+
+    // roughly 262 scanlines of 341 ppu cycles, 3 ppu cycles per cpu cycle
+    assert!(info.cycles > 29000 && info.cycles < 30000, "Unexpected frame length {}", info.cycles);
+    assert_eq!(info.frame_buffer.len(), ppu::SCREEN_WIDTH * ppu::SCREEN_HEIGHT);
+
+    let expected_samples = info.cycles as f64 * apu::SAMPLE_RATE as f64 / Region::Ntsc.cpu_clock_rate();
+    assert!((info.audio_samples.len() as f64 - expected_samples).abs() <= 1.0);
+}
+
+#[test]
+fn test_run_cycles() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+
+    let cycles = nes.run_cycles(1000);
+
+    // stops at the end of the instruction that crosses the target
+    assert!(cycles >= 1000 && cycles < 1010);
+    assert_eq!(nes.cycles, cycles);
+}
@@ -0,0 +1,86 @@
+extern crate enniesse_core;
+
+use enniesse_core::nes::Nes;
+use enniesse_core::memory::Memory;
+use enniesse_core::rom::Rom;
+
+const PRG_SIZE: usize = 0x8000;
+
+// a vrc6 cartridge with everything in the fixed bank at $e000
+fn program_image(reset: &[u8], irq: &[u8], nmi: &[u8]) -> Vec<u8> {
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, 2, 1, 0x80, 0x10];
+    image.resize(16, 0);
+
+    let mut prg = vec![0xea; PRG_SIZE];
+    prg[0x6000 .. 0x6000 + reset.len()].copy_from_slice(reset);
+    prg[0x6100 .. 0x6100 + irq.len()].copy_from_slice(irq);
+    prg[0x6200 .. 0x6200 + nmi.len()].copy_from_slice(nmi);
+    prg[0x7ffa .. 0x8000].copy_from_slice(&[0x00, 0xe2, 0x00, 0xe0, 0x00, 0xe1]);
+
+    image.extend(prg);
+    image.extend(vec![0; 0x2000]);
+    image
+}
+
+#[test]
+fn test_level_irq() {
+    let reset = [
+        0xa9, 0xf0,       // lda #$f0
+        0x8d, 0x00, 0xf0, // sta $f000, irq latch
+        0xa9, 0x06,       // lda #$06
+        0x8d, 0x01, 0xf0, // sta $f001, irq on in cycle mode
+        0x58,             // cli
+        0xe6, 0x00,       // inc $00
+        0x4c, 0x0b, 0xe0, // jmp $e00b
+    ];
+    // never acknowledges the mapper, so the line stays asserted
+    let irq = [
+        0xe6, 0x01,       // inc $01
+        0xad, 0x15, 0x40, // lda $4015
+        0x40,             // rti
+    ];
+    let nmi = [0x40];
+
+    let mut nes = Nes::new(Box::new(Rom::from(program_image(&reset, &irq, &nmi).into_boxed_slice()))).unwrap();
+    nes.power_cycle();
+    nes.cpu.memory_interface.apu.frame_interrupt = true;
+
+    for _ in 0 .. 5 {
+        nes.run_frame();
+
+        // at most one interrupt on the stack at a time
+        assert!(nes.cpu.reg_sp == 0xfd || nes.cpu.reg_sp == 0xfa, "Unexpected stack pointer {:02X}", nes.cpu.reg_sp);
+    }
+
+    // the handler ran, and reading the status acknowledged the frame interrupt
+    assert!(nes.cpu.memory_interface.load_byte(0x0001) > 0);
+    assert!(!nes.cpu.memory_interface.apu.frame_interrupt);
+}
+
+#[test]
+fn test_interrupt_sequence() {
+    let reset = [
+        0xa9, 0xf0,       // lda #$f0
+        0x8d, 0x00, 0xf0, // sta $f000
+        0xa9, 0x06,       // lda #$06
+        0x8d, 0x01, 0xf0, // sta $f001
+        0x58,             // cli
+        0x4c, 0x0b, 0xe0, // jmp $e00b
+    ];
+    let irq = [0x4c, 0x00, 0xe1]; // jmp $e100
+    let nmi = [0x40];
+
+    let mut nes = Nes::new(Box::new(Rom::from(program_image(&reset, &irq, &nmi).into_boxed_slice()))).unwrap();
+    nes.power_cycle();
+
+    while nes.cpu.reg_pc < 0xe100 {
+        nes.step();
+    }
+
+    // pc, then the flags with interrupts enabled and the break flag clear
+    assert!(nes.cpu.reg_p.interrupt_disable);
+    assert_eq!(nes.cpu.reg_sp, 0xfa);
+    assert_eq!(nes.cpu.memory_interface.load_byte(0x01fb) & 0x34, 0x20);
+    assert_eq!(nes.cpu.memory_interface.load_byte(0x01fc), 0x0b);
+    assert_eq!(nes.cpu.memory_interface.load_byte(0x01fd), 0xe0);
+}
@@ -0,0 +1,288 @@
+extern crate enniesse_core;
+
+use enniesse_core::rom::Rom;
+use enniesse_core::mapper;
+use enniesse_core::mapper::{Mirroring, MapperError, NAMETABLE_RAM_SIZE};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+
+// an ines image with every 8k prg bank filled with its own number
+fn numbered_image(mapper: u8, prg_banks_16k: u8, chr_banks_8k: u8) -> Vec<u8> {
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, prg_banks_16k, chr_banks_8k, (mapper & 0x0f) << 4, mapper & 0xf0];
+    image.resize(16, 0);
+
+    for bank in 0 .. prg_banks_16k as usize * 2 {
+        image.extend(vec![bank as u8; PRG_BANK_SIZE]);
+    }
+    image.extend(vec![0; chr_banks_8k as usize * 0x2000]);
+
+    image
+}
+
+fn numbered_rom(mapper: u8, prg_banks_16k: u8, chr_banks_8k: u8) -> Box<Rom> {
+    Box::new(Rom::from(numbered_image(mapper, prg_banks_16k, chr_banks_8k).into_boxed_slice()))
+}
+
+fn nes2_rom(mapper: u8, submapper: u8, prg_banks_16k: u8, chr_banks_8k: u8) -> Box<Rom> {
+    let mut image = numbered_image(mapper, prg_banks_16k, chr_banks_8k);
+    image[7] |= 0x08;
+    image[8] = submapper << 4;
+
+    Box::new(Rom::from(image.into_boxed_slice()))
+}
+
+#[test]
+fn test_mmc5() {
+    let mut mmc5 = mapper::load_mapper(numbered_rom(5, 8, 1)).unwrap();
+
+    // powers on with the last bank everywhere
+    assert_eq!(mmc5.load_byte_prg(0xe000), 15);
+
+    // 8k banks
+    mmc5.store_byte_prg(0x5114, 0x83);
+    mmc5.store_byte_prg(0x5115, 0x85);
+    mmc5.store_byte_prg(0x5116, 0x87);
+    assert_eq!(mmc5.load_byte_prg(0x8000), 3);
+    assert_eq!(mmc5.load_byte_prg(0xa000), 5);
+    assert_eq!(mmc5.load_byte_prg(0xc000), 7);
+
+    // one 32k bank, the low bits of the bank number are ignored
+    mmc5.store_byte_prg(0x5100, 0);
+    mmc5.store_byte_prg(0x5117, 0x05);
+    assert_eq!(mmc5.load_byte_prg(0x8000), 4);
+    assert_eq!(mmc5.load_byte_prg(0xe000), 7);
+
+    // prg ram at $8000 only takes writes once both protect registers are set
+    mmc5.store_byte_prg(0x5100, 3);
+    mmc5.store_byte_prg(0x5114, 0x01);
+    mmc5.store_byte_prg(0x8000, 0x42);
+    assert_eq!(mmc5.load_byte_prg(0x8000), 0);
+    mmc5.store_byte_prg(0x5102, 2);
+    mmc5.store_byte_prg(0x5103, 1);
+    mmc5.store_byte_prg(0x8000, 0x42);
+    assert_eq!(mmc5.load_byte_prg(0x8000), 0x42);
+    mmc5.store_byte_prg(0x5113, 0x01);
+    assert_eq!(mmc5.load_byte_prg(0x6000), 0x42);
+
+    mmc5.store_byte_prg(0x5205, 200);
+    mmc5.store_byte_prg(0x5206, 150);
+    assert_eq!(mmc5.load_byte_prg(0x5205), (30000 & 0xff) as u8);
+    assert_eq!(mmc5.load_byte_prg(0x5206), (30000 >> 8) as u8);
+
+    // the scanline irq goes off when the counter reaches $5203
+    mmc5.store_byte_prg(0x5203, 2);
+    mmc5.store_byte_prg(0x5204, 0x80);
+    for scanline in 0 .. 2 {
+        mmc5.ppu_scanline(scanline, true);
+        assert!(!mmc5.irq_pending());
+    }
+    mmc5.ppu_scanline(2, true);
+    assert!(mmc5.irq_pending());
+    assert_eq!(mmc5.load_byte_prg(0x5204), 0xc0);
+    assert!(!mmc5.irq_pending());
+}
+
+#[test]
+fn test_mirroring() {
+    let nametables = [0x2000, 0x2400, 0x2800, 0x2c00];
+    let pages = |mirroring: Mirroring| -> Vec<usize> {
+        nametables.iter().map(|&addr| mirroring.ciram_address(addr + 0x15) / 0x400).collect()
+    };
+
+    assert_eq!(pages(Mirroring::Horizontal), vec![0, 0, 1, 1]);
+    assert_eq!(pages(Mirroring::Vertical), vec![0, 1, 0, 1]);
+    assert_eq!(pages(Mirroring::SingleScreenLower), vec![0, 0, 0, 0]);
+    assert_eq!(pages(Mirroring::SingleScreenUpper), vec![1, 1, 1, 1]);
+    assert_eq!(pages(Mirroring::FourScreen), vec![0, 1, 2, 3]);
+    assert_eq!(Mirroring::Vertical.ciram_address(0x2c15), 0x415);
+
+    let mut rom = numbered_rom(0, 1, 1);
+    rom.flags6 |= 8;
+    assert_eq!(mapper::load_mapper(rom).unwrap().mirroring(), Mirroring::FourScreen);
+}
+
+#[test]
+fn test_mmc5_nametables() {
+    let mut mmc5 = mapper::load_mapper(numbered_rom(5, 8, 1)).unwrap();
+    let mut ciram = [0; NAMETABLE_RAM_SIZE];
+
+    // ciram page 1, ciram page 0, exram and fill mode
+    mmc5.store_byte_prg(0x5105, 0b11_10_00_01);
+    mmc5.store_byte_prg(0x5106, 0x33);
+    mmc5.store_byte_prg(0x5107, 2);
+
+    mmc5.store_nametable(0x2005, 0x11, &mut ciram);
+    mmc5.store_nametable(0x2405, 0x22, &mut ciram);
+    assert_eq!(ciram[0x405], 0x11);
+    assert_eq!(ciram[0x005], 0x22);
+    assert_eq!(mmc5.load_nametable(0x2005, &ciram), 0x11);
+
+    // in exram mode 0 the ppu can use exram as a nametable
+    mmc5.store_nametable(0x2805, 0x44, &mut ciram);
+    assert_eq!(mmc5.load_nametable(0x2805, &ciram), 0x44);
+
+    assert_eq!(mmc5.load_nametable(0x2c05, &ciram), 0x33);
+    assert_eq!(mmc5.load_nametable(0x2fc5, &ciram), 0xaa);
+}
+
+#[test]
+fn test_vrc4_address_lines() {
+    // vrc4a selects registers with A1 and A2, vrc4c with A6 and A7
+    let mut vrc4a = mapper::load_mapper(nes2_rom(21, 1, 8, 1)).unwrap();
+    let mut vrc4c = mapper::load_mapper(nes2_rom(21, 2, 8, 1)).unwrap();
+
+    for vrc4 in [&mut vrc4a, &mut vrc4c].iter_mut() {
+        vrc4.store_byte_prg(0x8000, 4);
+        vrc4.store_byte_prg(0xa000, 5);
+        assert_eq!(vrc4.load_byte_prg(0x8000), 4);
+        assert_eq!(vrc4.load_byte_prg(0xa000), 5);
+        assert_eq!(vrc4.load_byte_prg(0xc000), 14);
+        assert_eq!(vrc4.load_byte_prg(0xe000), 15);
+    }
+
+    // the prg swap mode is at $9004 on vrc4a and $9080 on vrc4c
+    vrc4a.store_byte_prg(0x9004, 2);
+    vrc4c.store_byte_prg(0x9004, 2);
+    assert_eq!(vrc4a.load_byte_prg(0x8000), 14);
+    assert_eq!(vrc4a.load_byte_prg(0xc000), 4);
+    assert_eq!(vrc4c.load_byte_prg(0x8000), 4);
+    vrc4c.store_byte_prg(0x9080, 2);
+    assert_eq!(vrc4c.load_byte_prg(0x8000), 14);
+
+    vrc4a.store_byte_prg(0x9000, 3);
+    assert_eq!(vrc4a.mirroring(), Mirroring::SingleScreenUpper);
+}
+
+#[test]
+fn test_vrc_irq() {
+    let mut vrc4 = mapper::load_mapper(nes2_rom(25, 1, 8, 1)).unwrap();
+
+    // cycle mode, 4 cycles until the counter overflows. vrc4b has A0 and A1 swapped
+    vrc4.store_byte_prg(0xf000, 0x0c);
+    vrc4.store_byte_prg(0xf002, 0x0f);
+    vrc4.store_byte_prg(0xf001, 0x07);
+    for _ in 0 .. 3 {
+        vrc4.clock_cpu();
+        assert!(!vrc4.irq_pending());
+    }
+    vrc4.clock_cpu();
+    assert!(vrc4.irq_pending());
+
+    // acknowledging keeps it enabled when bit 0 was set, and it reloads from the latch
+    vrc4.store_byte_prg(0xf003, 0);
+    assert!(!vrc4.irq_pending());
+    for _ in 0 .. 4 {
+        vrc4.clock_cpu();
+    }
+    assert!(vrc4.irq_pending());
+
+    // scanline mode counts every 341 / 3 cpu cycles
+    let mut vrc6 = mapper::load_mapper(numbered_rom(24, 8, 1)).unwrap();
+    vrc6.store_byte_prg(0xf000, 0xff);
+    vrc6.store_byte_prg(0xf001, 0x02);
+    for _ in 0 .. 113 {
+        vrc6.clock_cpu();
+    }
+    assert!(!vrc6.irq_pending());
+    vrc6.clock_cpu();
+    assert!(vrc6.irq_pending());
+}
+
+#[test]
+fn test_namco163() {
+    let mut n163 = mapper::load_mapper(numbered_rom(19, 8, 1)).unwrap();
+
+    n163.store_byte_prg(0xe000, 3);
+    n163.store_byte_prg(0xe800, 4);
+    n163.store_byte_prg(0xf000, 5);
+    assert_eq!(n163.load_byte_prg(0x8000), 3);
+    assert_eq!(n163.load_byte_prg(0xa000), 4);
+    assert_eq!(n163.load_byte_prg(0xc000), 5);
+    assert_eq!(n163.load_byte_prg(0xe000), 15);
+
+    // sound ram through the data port, with auto increment
+    n163.store_byte_prg(0xf800, 0x80 | 0x10);
+    n163.store_byte_prg(0x4800, 0x12);
+    n163.store_byte_prg(0x4800, 0x34);
+    n163.store_byte_prg(0xf800, 0x10);
+    assert_eq!(n163.load_byte_prg(0x4800), 0x12);
+    assert_eq!(n163.load_byte_prg(0x4800), 0x12);
+
+    // one channel playing a wave that's all 15s from address 0
+    n163.store_byte_prg(0xf800, 0x80);
+    for _ in 0 .. 0x10 {
+        n163.store_byte_prg(0x4800, 0xff);
+    }
+    n163.store_byte_prg(0xf800, 0x80 | 0x78);
+    for &val in [0, 0, 0, 0, 0xe0, 0, 0, 0x0f].iter() {
+        n163.store_byte_prg(0x4800, val);
+    }
+    for _ in 0 .. 15 {
+        n163.clock_cpu();
+    }
+    assert!(n163.audio_output() > 0.0);
+
+    // the irq counter counts up to $7fff
+    n163.store_byte_prg(0x5000, 0xfd);
+    n163.store_byte_prg(0x5800, 0x80 | 0x7f);
+    n163.clock_cpu();
+    assert!(!n163.irq_pending());
+    n163.clock_cpu();
+    assert!(n163.irq_pending());
+    assert_eq!(n163.load_byte_prg(0x5000), 0xff);
+}
+
+#[test]
+fn test_fme7() {
+    let mut fme7 = mapper::load_mapper(numbered_rom(69, 8, 1)).unwrap();
+
+    for (command, bank) in [(9, 1), (0xa, 2), (0xb, 3)].iter() {
+        fme7.store_byte_prg(0x8000, *command);
+        fme7.store_byte_prg(0xa000, *bank);
+    }
+    assert_eq!(fme7.load_byte_prg(0x8000), 1);
+    assert_eq!(fme7.load_byte_prg(0xa000), 2);
+    assert_eq!(fme7.load_byte_prg(0xc000), 3);
+    assert_eq!(fme7.load_byte_prg(0xe000), 15);
+
+    // $6000 is a rom bank until ram is selected
+    fme7.store_byte_prg(0x8000, 8);
+    fme7.store_byte_prg(0xa000, 6);
+    assert_eq!(fme7.load_byte_prg(0x6000), 6);
+    fme7.store_byte_prg(0xa000, 0xc0);
+    fme7.store_byte_prg(0x6000, 0x42);
+    assert_eq!(fme7.load_byte_prg(0x6000), 0x42);
+
+    // the irq fires when the counter goes past 0
+    fme7.store_byte_prg(0x8000, 0xe);
+    fme7.store_byte_prg(0xa000, 1);
+    fme7.store_byte_prg(0x8000, 0xf);
+    fme7.store_byte_prg(0xa000, 0);
+    fme7.store_byte_prg(0x8000, 0xd);
+    fme7.store_byte_prg(0xa000, 0x81);
+    fme7.clock_cpu();
+    assert!(!fme7.irq_pending());
+    fme7.clock_cpu();
+    assert!(fme7.irq_pending());
+
+    // channel a with the tone and noise turned off plays its volume as a constant level
+    assert_eq!(fme7.audio_output(), 0.0);
+    fme7.store_byte_prg(0xc000, 7);
+    fme7.store_byte_prg(0xe000, 0x3f);
+    fme7.store_byte_prg(0xc000, 8);
+    fme7.store_byte_prg(0xe000, 0x0f);
+    assert!(fme7.audio_output() > 0.0);
+}
+
+#[test]
+fn test_load_errors() {
+    // the disk system only comes from a disk image, never from an ines header
+    match mapper::load_mapper(numbered_rom(20, 1, 0)) {
+        Err(MapperError::NoDisk) => {},
+        other => panic!("expected NoDisk, got {:?}", other.err())
+    }
+    match mapper::load_mapper(numbered_rom(255, 1, 0)) {
+        Err(MapperError::Unsupported(255)) => {},
+        other => panic!("expected Unsupported, got {:?}", other.err())
+    }
+}
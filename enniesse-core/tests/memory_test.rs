@@ -0,0 +1,35 @@
+extern crate enniesse_core;
+
+use enniesse_core::nes::Nes;
+use enniesse_core::memory::Memory;
+use enniesse_core::rom::Rom;
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+#[test]
+fn test_open_bus() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    let memory = &mut nes.cpu.memory_interface;
+
+    // unmapped reads return whatever was last on the bus
+    memory.store_byte(0x0000, 0xa5);
+    assert_eq!(memory.load_byte(0x4018), 0xa5);
+    assert_eq!(memory.load_byte(0x5000), 0xa5);
+    memory.load_byte(0x0000);
+    assert_eq!(memory.load_byte(0x4000), 0xa5);
+
+    // the controllers only drive the low bits
+    memory.store_byte(0x0000, 0x40);
+    memory.load_byte(0x0000);
+    assert_eq!(memory.load_byte(0x4016), 0x40);
+    memory.store_byte(0x0000, 0xff);
+    memory.load_byte(0x0000);
+    assert_eq!(memory.load_byte(0x4017) & 0xe0, 0xe0);
+
+    // $4015 doesn't update the bus
+    memory.store_byte(0x0000, 0x20);
+    memory.load_byte(0x0000);
+    assert_eq!(memory.load_byte(0x4015) & 0x20, 0x20);
+    assert_eq!(memory.load_byte(0x4018), 0x20);
+}
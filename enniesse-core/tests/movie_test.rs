@@ -0,0 +1,78 @@
+extern crate enniesse_core;
+
+use enniesse_core::nes::Nes;
+use enniesse_core::movie::{Movie, MovieMode, MovieStart, MovieFrame, COMMAND_RESET};
+use enniesse_core::rom::Rom;
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+const FM2_MOVIE: &'static str = "version 3
+emuVersion 22020
+rerecordCount 7
+palFlag 0
+romFilename nestest
+guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+fourscore 0
+port0 1
+port1 1
+port2 0
+comment author test
+|0|........|........||
+|1|R......A|........||
+|0|.L..T...|..D.....||
+";
+
+#[test]
+fn test_fm2_round_trip() {
+    let movie = Movie::from_fm2(FM2_MOVIE.as_bytes()).unwrap();
+
+    assert_eq!(movie.rerecord_count, 7);
+    assert_eq!(movie.rom_filename, "nestest");
+    assert_eq!(movie.comments, vec!["author test".to_string()]);
+    assert_eq!(movie.start, MovieStart::PowerOn);
+    assert_eq!(movie.frames, vec![
+        MovieFrame { commands: 0, ports: [0x00, 0x00] },
+        MovieFrame { commands: COMMAND_RESET, ports: [0x81, 0x00] },
+        MovieFrame { commands: 0, ports: [0x48, 0x20] },
+    ]);
+
+    let mut output = Vec::new();
+    movie.write_fm2(&mut output).unwrap();
+    let reloaded = Movie::from_fm2(&output[..]).unwrap();
+
+    assert_eq!(reloaded.frames, movie.frames);
+    assert_eq!(reloaded.guid, movie.guid);
+}
+
+#[test]
+fn test_movie_playback_matches_recording() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    for _ in 0 .. 10 {
+        nes.run_frame();
+    }
+
+    // start from a save state so the state path is covered too
+    nes.record_movie(false);
+    for frame in 0 .. 120 {
+        nes.cpu.memory_interface.input.set_port_state(0, (frame * 37) as u8);
+        nes.run_frame();
+    }
+    let recorded_state = nes.save_state();
+    let movie = nes.stop_movie().unwrap();
+    assert_eq!(movie.frames.len(), 120);
+
+    let mut output = Vec::new();
+    movie.write_fm2(&mut output).unwrap();
+    let movie = Movie::from_fm2(&output[..]).unwrap();
+
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    nes.play_movie(movie).unwrap();
+    for _ in 0 .. 120 {
+        nes.run_frame();
+    }
+
+    assert_eq!(nes.movie_mode(), Some(MovieMode::Finished));
+    assert!(nes.save_state() == recorded_state, "Playback diverged from the recording");
+}
@@ -0,0 +1,115 @@
+extern crate enniesse_core;
+
+use enniesse_core::nsf::{Nsf, NsfPlayer};
+use enniesse_core::rom::Rom;
+use enniesse_core::mapper;
+use enniesse_core::memory::Memory;
+
+// init stores the track number at $6001, play counts its calls at $6000
+const PROGRAM: [u8; 8] = [0x8d, 0x01, 0x60, 0x60, 0xee, 0x00, 0x60, 0x60];
+
+fn nsf_image(banks: [u8; 8], data: &[u8]) -> Vec<u8> {
+    let mut image = b"NESM\x1a\x01\x03\x02".to_vec();
+    image.extend_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x04, 0x80]);
+    image.extend_from_slice(b"Test");
+    image.resize(0x6e, 0);
+    image.extend_from_slice(&[0x1a, 0x41]);
+    image.extend_from_slice(&banks);
+    image.resize(0x80, 0);
+    image.extend_from_slice(data);
+
+    image
+}
+
+#[test]
+fn test_nsf_player() {
+    let nsf = Nsf::from_bytes(&nsf_image([0; 8], &PROGRAM)).unwrap();
+    assert_eq!(nsf.title, "Test");
+    assert_eq!(nsf.track_count, 3);
+    assert_eq!(nsf.starting_track, 1);
+
+    let mut player = NsfPlayer::new(nsf);
+    assert_eq!(player.track(), 1);
+
+    let clock_rate = player.region.cpu_clock_rate() as u64;
+    player.run_cycles(clock_rate);
+    assert_eq!(player.cpu.memory_interface.load_byte(0x6001), 1);
+    let plays = player.cpu.memory_interface.load_byte(0x6000);
+    assert!(plays >= 59 && plays <= 61, "play called {} times", plays);
+
+    // a new track starts from cleared ram
+    player.start_track(2);
+    player.run_cycles(clock_rate / 10);
+    assert_eq!(player.cpu.memory_interface.load_byte(0x6001), 2);
+    assert!(player.cpu.memory_interface.load_byte(0x6000) <= 7);
+}
+
+#[test]
+fn test_nsf_banks() {
+    let mut data = PROGRAM.to_vec();
+    data.resize(0x1000, 0);
+    data.push(0x42);
+
+    let nsf = Nsf::from_bytes(&nsf_image([0, 1, 0, 0, 0, 0, 0, 0], &data)).unwrap();
+    assert!(nsf.is_bankswitched());
+
+    let mut mapper = mapper::load_mapper(Box::new(Rom::from_nsf(nsf))).unwrap();
+    assert_eq!(mapper.load_byte_prg(0x8000), 0x8d);
+    assert_eq!(mapper.load_byte_prg(0x9000), 0x42);
+
+    mapper.store_byte_prg(0x5ff8, 1);
+    assert_eq!(mapper.load_byte_prg(0x8000), 0x42);
+}
+
+#[test]
+fn test_nsf_expansion() {
+    let mut image = nsf_image([0; 8], &PROGRAM);
+    image[0x7b] = 0x3f;
+    let nsf = Nsf::from_bytes(&image).unwrap();
+
+    // fds ram stretches from $6000 to the top, with the program copied in
+    let mut mapper = mapper::load_mapper(Box::new(Rom::from_nsf(nsf))).unwrap();
+    assert_eq!(mapper.load_byte_prg(0x8000), 0x8d);
+    mapper.store_byte_prg(0xa000, 0x12);
+    assert_eq!(mapper.load_byte_prg(0xa000), 0x12);
+
+    // the mmc5's multiplier and the fds's volume gain read back through the nsf mapper
+    mapper.store_byte_prg(0x5205, 6);
+    mapper.store_byte_prg(0x5206, 7);
+    assert_eq!(mapper.load_byte_prg(0x5205), 42);
+    mapper.store_byte_prg(0x4080, 0x80 | 0x15);
+    assert_eq!(mapper.load_byte_prg(0x4090) & 0x3f, 0x15);
+}
+
+#[test]
+fn test_nsfe() {
+    let mut image = b"NSFE".to_vec();
+    let mut chunk = |id: &[u8], data: &[u8]| {
+        image.extend_from_slice(&[data.len() as u8, 0, 0, 0]);
+        image.extend_from_slice(id);
+        image.extend_from_slice(data);
+    };
+
+    chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x04, 0x80, 0, 0, 2, 0]);
+    chunk(b"DATA", &PROGRAM);
+    chunk(b"auth", b"Title\0Artist\0\0\0");
+    chunk(b"tlbl", b"First\0Second\0");
+    chunk(b"time", &[0x10, 0x27, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+    chunk(b"plst", &[1, 0]);
+    chunk(b"NEND", &[]);
+
+    let nsf = Nsf::from_bytes(&image).unwrap();
+    assert_eq!(nsf.track_count, 2);
+    assert_eq!(nsf.title, "Title");
+    assert_eq!(nsf.artist, "Artist");
+    assert_eq!(nsf.track_label(1), Some("Second"));
+    assert_eq!(nsf.track_time(0), Some(10000));
+    assert_eq!(nsf.track_time(1), None);
+    assert_eq!(nsf.track_order(), vec![1, 0]);
+    assert_eq!(nsf.data, PROGRAM);
+
+    // a capitalised chunk is one the file can't be played without
+    let mut unknown = b"NSFE\x00\x00\x00\x00ZZZZ".to_vec();
+    unknown.extend_from_slice(&image[4 ..]);
+    assert!(Nsf::from_bytes(&unknown).is_err());
+}
@@ -0,0 +1,57 @@
+extern crate enniesse_core;
+
+use enniesse_core::ppu::palette::{Palette, PaletteError, BUILTIN_PALETTES};
+
+#[test]
+fn test_palette_files() {
+    let colors: Vec<u8> = (0 .. 192).map(|i| i as u8).collect();
+    let palette = Palette::from_bytes(&colors).unwrap();
+
+    assert_eq!(palette.rgb(0x01), [3, 4, 5]);
+    // emphasis entries are derived for 64 color files, red emphasis dims green and blue
+    let emphasized = palette.rgb(0x40 | 0x3f);
+    assert_eq!(emphasized[0], 189);
+    assert!(emphasized[1] < 190 && emphasized[2] < 191);
+
+    let colors: Vec<u8> = (0 .. 1536).map(|i| (i / 3) as u8).collect();
+    let palette = Palette::from_bytes(&colors).unwrap();
+    assert_eq!(palette.rgb(0x1ff), [0xff, 0xff, 0xff]);
+
+    match Palette::from_bytes(&[0; 100]) {
+        Err(PaletteError::InvalidSize(100)) => {},
+        _ => panic!("Expected an invalid size error")
+    }
+}
+
+#[test]
+fn test_builtin_palettes() {
+    for name in BUILTIN_PALETTES.iter() {
+        let palette = Palette::builtin(name).unwrap();
+
+        // $0f is black and $30 is white or close to it in every palette
+        assert_eq!(palette.rgb(0x0f), [0, 0, 0]);
+        assert!(palette.rgb(0x30).iter().all(|&c| c > 200));
+    }
+
+    // the old name of the nesdev palette still works
+    assert_eq!(Palette::builtin("default").unwrap().rgb(0x16), Palette::builtin("nesdev").unwrap().rgb(0x16));
+    assert!(Palette::builtin("missing").is_none());
+}
+
+#[test]
+fn test_frame_conversions() {
+    let palette = Palette::default();
+    let frame = [0x0f, 0x30, 0x16];
+
+    let mut rgb = [0; 9];
+    palette.to_rgb(&frame, &mut rgb);
+    assert_eq!(&rgb[3..6], &palette.rgb(0x30)[..]);
+
+    let mut rgba = [0; 12];
+    palette.to_rgba(&frame, &mut rgba);
+    assert_eq!(&rgba[8..12], &[palette.rgb(0x16)[0], palette.rgb(0x16)[1], palette.rgb(0x16)[2], 0xff]);
+
+    let mut argb = [0; 3];
+    palette.to_argb(&frame, &mut argb);
+    assert_eq!(argb[0], 0xff00_0000);
+}
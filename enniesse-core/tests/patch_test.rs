@@ -0,0 +1,158 @@
+extern crate enniesse_core;
+
+use std::env;
+use std::fs;
+
+use enniesse_core::hash;
+use enniesse_core::patch;
+use enniesse_core::patch::PatchError;
+use enniesse_core::rom;
+use enniesse_core::rom::Rom;
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+// the variable length numbers ups and bps use
+fn number(mut value: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let bits = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(bits | 0x80);
+            return bytes;
+        }
+        bytes.push(bits);
+        value -= 1;
+    }
+}
+
+fn u32_le(value: u32) -> [u8; 4] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+fn finish_patch(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&u32_le(hash::crc32(source)));
+    patch.extend_from_slice(&u32_le(hash::crc32(target)));
+    let patch_crc = hash::crc32(&patch);
+    patch.extend_from_slice(&u32_le(patch_crc));
+    patch
+}
+
+#[test]
+fn test_ips() {
+    // a run of 3 0xaa bytes, a normal record, then cut down to 6 bytes after the footer
+    let ips = b"PATCH\x00\x00\x02\x00\x00\x00\x03\xaa\x00\x00\x00\x00\x01\x05EOF\x00\x00\x06";
+
+    let mut data = vec![0; 8];
+    patch::apply_patch(&mut data, ips).unwrap();
+    assert_eq!(data, vec![5, 0, 0xaa, 0xaa, 0xaa, 0]);
+
+    assert!(matches!(patch::apply_ips(&mut data, b"PATCH\x00\x00\x02\x00"), Err(PatchError::UnexpectedEnd)));
+    assert!(matches!(patch::apply_patch(&mut data, b"NOT A PATCH"), Err(PatchError::NotAPatch)));
+}
+
+#[test]
+fn test_ups() {
+    let source = [1, 2, 3, 4, 5];
+    let target = [1, 9, 3, 4, 5, 7];
+
+    let mut ups = b"UPS1".to_vec();
+    ups.extend(number(source.len()));
+    ups.extend(number(target.len()));
+    // skip 1 and change a byte, then skip the 2 after the hunk's terminator and add one
+    ups.extend(number(1));
+    ups.extend_from_slice(&[2 ^ 9, 0]);
+    ups.extend(number(2));
+    ups.extend_from_slice(&[7, 0]);
+    let ups = finish_patch(ups, &source, &target);
+
+    assert_eq!(patch::apply_ups(&source, &ups).unwrap(), target.to_vec());
+    assert!(matches!(patch::apply_ups(&[1, 2, 3], &ups), Err(PatchError::WrongSource)));
+
+    let mut damaged = ups.clone();
+    damaged[8] ^= 1;
+    assert!(matches!(patch::apply_ups(&source, &damaged), Err(PatchError::Invalid)));
+
+    // a target size that's far too big is turned down before anything is allocated for it
+    let mut huge = b"UPS1".to_vec();
+    huge.extend(number(source.len()));
+    huge.extend(number(1 << 30));
+    let huge = finish_patch(huge, &source, &target);
+    assert!(matches!(patch::apply_ups(&source, &huge), Err(PatchError::TargetTooLarge(size)) if size == 1 << 30));
+}
+
+#[test]
+fn test_bps() {
+    let source = b"hello world";
+    let target = b"hello hello world!";
+
+    let mut bps = b"BPS1".to_vec();
+    bps.extend(number(source.len()));
+    bps.extend(number(target.len()));
+    bps.extend(number(0));
+    // "hello " from the source, again from the target, "world" from 6 on in the source, then "!"
+    bps.extend(number(5 << 2));
+    bps.extend(number((5 << 2) | 3));
+    bps.extend(number(0));
+    bps.extend(number((4 << 2) | 2));
+    bps.extend(number(6 << 1));
+    bps.extend(number(1));
+    bps.push(b'!');
+    let bps = finish_patch(bps, source, target);
+
+    let mut data = source.to_vec();
+    patch::apply_patch(&mut data, &bps).unwrap();
+    assert_eq!(&data[..], &target[..]);
+    assert!(matches!(patch::apply_bps(b"goodbye", &bps), Err(PatchError::WrongSource)));
+}
+
+#[test]
+fn test_bps_bounds() {
+    let source = b"hello world";
+    let target = b"hello";
+
+    let bps_with = |actions: &[usize]| {
+        let mut bps = b"BPS1".to_vec();
+        bps.extend(number(source.len()));
+        bps.extend(number(target.len()));
+        bps.extend(number(0));
+        for &action in actions {
+            bps.extend(number(action));
+        }
+        finish_patch(bps, source, target)
+    };
+
+    // one byte from the source, then a gigabyte copied from it in the target
+    let target_copy = bps_with(&[0, ((1 << 30) - 1) << 2 | 3, 0]);
+    assert!(matches!(patch::apply_bps(source, &target_copy), Err(PatchError::Invalid)));
+
+    // more from the source than the target has room for
+    let source_read = bps_with(&[((1 << 30) - 1) << 2]);
+    assert!(matches!(patch::apply_bps(source, &source_read), Err(PatchError::Invalid)));
+
+    // a source copy from far past the end of the source
+    let source_copy = bps_with(&[2, (1 << 30) << 1]);
+    assert!(matches!(patch::apply_bps(source, &source_copy), Err(PatchError::Invalid)));
+}
+
+#[test]
+fn test_patch_next_to_rom() {
+    let dir = env::temp_dir().join("enniesse_patch_test");
+    fs::create_dir_all(&dir).unwrap();
+    let rom_path = dir.join("game.nes");
+    fs::copy(TEST_ROM_PATH, &rom_path).unwrap();
+    fs::write(dir.join("game.ips"), b"PATCH\x00\x00\x10\x00\x01\x42EOF").unwrap();
+
+    let file = rom::read_file(&rom_path).unwrap();
+    let rom = Rom::from_file(&rom_path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    // the loader says which patch it used
+    assert_eq!(file.patch, Some(dir.join("game.ips")));
+    assert_eq!(file.data[16], 0x42);
+    assert!(!file.is_disk());
+
+    assert_eq!(rom.prg_rom[0], 0x42);
+    // it's no longer the rom in the database
+    assert_eq!(rom.title, None);
+}
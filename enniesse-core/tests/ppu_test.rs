@@ -0,0 +1,180 @@
+extern crate enniesse_core;
+
+use enniesse_core::nes::Nes;
+use enniesse_core::memory::Memory;
+use enniesse_core::ppu::{Ppu, SCREEN_WIDTH};
+use enniesse_core::ppu::palette::Palette;
+use enniesse_core::rom::Rom;
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+fn run_ppu_frames(ppu: &mut Ppu, frames: usize) {
+    for _ in 0 .. frames * 262 {
+        ppu.run(false);
+    }
+}
+
+#[test]
+fn test_io_latch() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    // address writes are ignored until the ppu has warmed up
+    nes.run_frame();
+    nes.run_frame();
+    let memory = &mut nes.cpu.memory_interface;
+
+    // write only registers read back the last value on the bus, even one written to a read only register
+    memory.store_byte(0x2002, 0x5a);
+    assert_eq!(memory.load_byte(0x2000), 0x5a);
+    assert_eq!(memory.load_byte(0x2005), 0x5a);
+
+    // status only drives its top 3 bits
+    memory.store_byte(0x2003, 0x1f);
+    assert_eq!(memory.load_byte(0x2002) & 0x1f, 0x1f);
+
+    // palette reads fill the top 2 bits from the latch
+    memory.store_byte(0x2006, 0x3f);
+    memory.store_byte(0x2006, 0x00);
+    memory.store_byte(0x2007, 0x0f);
+
+    memory.store_byte(0x2006, 0x3f);
+    memory.store_byte(0x2006, 0x00);
+    assert_eq!(memory.load_byte(0x2007), 0x0f);
+
+    memory.store_byte(0x2006, 0x3f);
+    memory.store_byte(0x2006, 0x00);
+    memory.store_byte(0x2003, 0xc0);
+    assert_eq!(memory.load_byte(0x2007), 0xcf);
+}
+
+#[test]
+fn test_io_latch_decays() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+
+    // run the ppu on its own so the program doesn't touch the bus
+    let ppu = &mut nes.cpu.memory_interface.ppu;
+    ppu.store_byte(0x2002, 0xff);
+    run_ppu_frames(ppu, 10);
+    assert_eq!(ppu.load_byte(0x2000), 0xff);
+
+    run_ppu_frames(ppu, 30);
+    assert_eq!(ppu.load_byte(0x2000), 0x00);
+}
+
+// puts the given sprites in oam, with the rest hidden, and runs up to scanline 50
+fn setup_sprites(sprites: &[[u8; 4]], sprite_limit: bool) -> Nes {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    {
+        let ppu = &mut nes.cpu.memory_interface.ppu;
+        ppu.set_sprite_limit(sprite_limit);
+
+        // the first frame ignores mask writes, this leaves the ppu at the start of scanline 0
+        run_ppu_frames(ppu, 1);
+
+        ppu.store_byte(0x2003, 0x00);
+        for n in 0 .. 64 {
+            let bytes = sprites.get(n).cloned().unwrap_or([0xff; 4]);
+            for &byte in &bytes {
+                ppu.store_byte(0x2004, byte);
+            }
+        }
+
+        // color 3 of the first sprite palette
+        ppu.store_byte(0x2006, 0x3f);
+        ppu.store_byte(0x2006, 0x13);
+        ppu.store_byte(0x2007, 0x16);
+
+        ppu.store_byte(0x2001, 0x18);
+
+        for _ in 0 .. 50 {
+            ppu.run(false);
+        }
+    }
+    nes
+}
+
+// the overflow flag before and after evaluating scanline 50
+fn sprite_overflow_on_line_50(sprites: &[[u8; 4]], sprite_limit: bool) -> (bool, bool) {
+    let mut nes = setup_sprites(sprites, sprite_limit);
+    let ppu = &mut nes.cpu.memory_interface.ppu;
+
+    let before = ppu.load_byte(0x2002) & 0x20 != 0;
+    // the flag goes up partway through the scanline
+    ppu.run(true);
+    let after = ppu.load_byte(0x2002) & 0x20 != 0;
+
+    (before, after)
+}
+
+#[test]
+fn test_sprite_overflow() {
+    let mut sprites = vec![[50, 0, 0, 0]; 9];
+    assert_eq!(sprite_overflow_on_line_50(&sprites, true), (false, true));
+
+    // with 8 sprites found the hardware checks the 10th sprite's tile index as a y position instead
+    sprites[8] = [0xff; 4];
+    sprites.push([0xff, 50, 0xff, 0xff]);
+    assert_eq!(sprite_overflow_on_line_50(&sprites, true), (false, true));
+
+    // so a real 10th sprite on the line is missed
+    sprites[9] = [50, 0xff, 0xff, 0xff];
+    assert_eq!(sprite_overflow_on_line_50(&sprites, true), (false, false));
+
+    // the limit doesn't change any of that
+    assert_eq!(sprite_overflow_on_line_50(&sprites, false), (false, false));
+}
+
+#[test]
+fn test_no_sprite_limit() {
+    // 8 blank sprites followed by one with a solid top left pixel
+    let mut sprites = vec![[50, 0, 0, 0]; 8];
+    sprites.push([50, 2, 0, 200]);
+
+    for &sprite_limit in &[true, false] {
+        let mut nes = setup_sprites(&sprites, sprite_limit);
+        let ppu = &mut nes.cpu.memory_interface.ppu;
+        ppu.run(false);
+        ppu.run(false);
+
+        let drawn = ppu.display_buffer[51 * SCREEN_WIDTH + 200] & 0x3f == 0x16;
+        assert_eq!(drawn, !sprite_limit);
+    }
+}
+
+#[test]
+fn test_greyscale_and_emphasis() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    let ppu = &mut nes.cpu.memory_interface.ppu;
+    run_ppu_frames(ppu, 1);
+
+    // every palette entry red so whatever the background tiles are, they come out the same
+    ppu.store_byte(0x2006, 0x3f);
+    ppu.store_byte(0x2006, 0x00);
+    for _ in 0 .. 32 {
+        ppu.store_byte(0x2007, 0x16);
+    }
+
+    let palette = Palette::default();
+    // mask bits on top of showing the background, the 9 bit index drawn and its color
+    let cases: [(u8, u16, [u8; 3]); 6] = [
+        (0x00, 0x016, [152, 34, 32]),
+        // greyscale keeps the brightness row and drops the hue
+        (0x01, 0x010, [152, 150, 152]),
+        // each emphasis bit dims the other two channels
+        (0x20, 0x056, [152, 27, 26]),
+        (0x40, 0x096, [124, 34, 26]),
+        (0x80, 0x116, [124, 27, 32]),
+        (0x21, 0x050, [152, 122, 124]),
+    ];
+
+    for &(mask, index, rgb) in cases.iter() {
+        ppu.store_byte(0x2001, 0x0a | mask);
+        run_ppu_frames(ppu, 1);
+
+        assert!(ppu.display_buffer.iter().all(|&color| color == index), "mask {:02x}", mask);
+        assert_eq!(palette.rgb(index), rgb, "mask {:02x}", mask);
+    }
+}
@@ -0,0 +1,126 @@
+extern crate enniesse_core;
+
+use enniesse_core::nes::Nes;
+use enniesse_core::memory::{Memory, MemoryInterface, RamPattern};
+use enniesse_core::rom::Rom;
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+fn run_ppu_frame(nes: &mut Nes) {
+    for _ in 0 .. 262 {
+        nes.cpu.memory_interface.ppu.run(false);
+    }
+}
+
+fn set_palette(memory: &mut MemoryInterface, color: u8) {
+    memory.store_byte(0x2006, 0x3f);
+    memory.store_byte(0x2006, 0x00);
+    for _ in 0 .. 32 {
+        memory.store_byte(0x2007, color);
+    }
+}
+
+fn ram(nes: &mut Nes) -> Vec<u8> {
+    (0 .. 0x800).map(|addr| nes.cpu.memory_interface.load_byte(addr)).collect()
+}
+
+#[test]
+fn test_reset() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+    assert_eq!(nes.cpu.reg_sp, 0xfd);
+    run_ppu_frame(&mut nes);
+
+    nes.cpu.reg_sp = 0x80;
+    nes.cpu.reg_p.interrupt_disable = false;
+    {
+        let memory = &mut nes.cpu.memory_interface;
+        memory.store_byte(0x0000, 0x42);
+
+        // pulse 1 playing
+        memory.store_byte(0x4015, 0x01);
+        memory.store_byte(0x4003, 0x08);
+        assert_eq!(memory.load_byte(0x4015) & 0x01, 0x01);
+
+        // a frame drawn in red with every emphasis bit
+        set_palette(memory, 0x16);
+        memory.store_byte(0x2001, 0xea);
+    }
+    run_ppu_frame(&mut nes);
+    let red_frame = nes.cpu.memory_interface.ppu.display_buffer.to_vec();
+    assert!(red_frame.iter().any(|&color| color != 0));
+    {
+        let memory = &mut nes.cpu.memory_interface;
+        // anything drawn from here on comes out blue
+        set_palette(memory, 0x12);
+
+        // background at $1000 and the first of the two scroll writes
+        memory.store_byte(0x2000, 0x10);
+        memory.store_byte(0x2005, 0x08);
+        assert_eq!(memory.ppu.background_pattern_table(), 0x1000);
+    }
+
+    nes.reset();
+
+    assert_eq!(nes.cpu.reg_sp, 0x7d);
+    assert!(nes.cpu.reg_p.interrupt_disable);
+    let reset_vector = nes.cpu.memory_interface.load_byte(0xfffc) as u16 | (nes.cpu.memory_interface.load_byte(0xfffd) as u16) << 8;
+    assert_eq!(nes.cpu.reg_pc, reset_vector);
+    assert_eq!(ram(&mut nes)[0], 0x42);
+
+    {
+        let memory = &mut nes.cpu.memory_interface;
+        assert_eq!(memory.load_byte(0x4015) & 0x1f, 0);
+
+        assert_eq!(memory.ppu.background_pattern_table(), 0);
+        assert_eq!(memory.ppu.scroll_position(), (0, 0));
+
+        // ignored until the end of vblank
+        memory.store_byte(0x2000, 0x10);
+        assert_eq!(memory.ppu.background_pattern_table(), 0);
+    }
+
+    run_ppu_frame(&mut nes);
+    let memory = &mut nes.cpu.memory_interface;
+
+    // the mask was cleared, so rendering is off and the red frame is still there
+    assert_eq!(memory.ppu.display_buffer.to_vec(), red_frame);
+
+    // the write toggle was cleared, so these are x then y
+    memory.store_byte(0x2005, 0x10);
+    memory.store_byte(0x2005, 0x20);
+    assert_eq!(memory.ppu.scroll_position(), (0x10, 0x20));
+}
+
+#[test]
+fn test_ram_patterns() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+
+    nes.ram_pattern = RamPattern::Zeros;
+    nes.power_cycle();
+    assert!(ram(&mut nes).iter().all(|&b| b == 0x00));
+
+    nes.ram_pattern = RamPattern::Ones;
+    nes.power_cycle();
+    assert!(ram(&mut nes).iter().all(|&b| b == 0xff));
+
+    let mut random = Vec::new();
+    for &seed in &[0, 1, 1] {
+        nes.ram_pattern = RamPattern::Random(seed);
+        nes.power_cycle();
+        random.push(ram(&mut nes));
+    }
+    assert!(random[0].iter().any(|&b| b != random[0][0]));
+    assert_ne!(random[0], random[1]);
+    assert_eq!(random[1], random[2]);
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_power_on() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.cpu.reg_a = 0x12;
+    nes.power_on();
+    assert_eq!(nes.cpu.reg_a, 0);
+    assert_eq!(nes.cpu.reg_sp, 0xfd);
+}
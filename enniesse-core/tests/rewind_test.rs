@@ -0,0 +1,80 @@
+extern crate enniesse_core;
+
+use enniesse_core::nes::Nes;
+use enniesse_core::rewind::Rewind;
+use enniesse_core::rom::Rom;
+use enniesse_core::state::StateError;
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+#[test]
+fn test_rewind_restores_previous_frames() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+
+    let mut rewind = Rewind::new(5, 1024 * 1024);
+    let mut states = Vec::new();
+
+    for frame in 0 .. 60 {
+        nes.cpu.memory_interface.input.set_port_state(0, (frame * 13) as u8);
+        nes.run_frame();
+        rewind.capture(&nes);
+        states.push((nes.frame, nes.save_state()));
+    }
+
+    // step back one frame at a time across several snapshots
+    for i in 1 .. 20 {
+        assert!(rewind.step_back(&mut nes));
+
+        let (frame, ref state) = states[states.len() - 1 - i];
+        assert_eq!(nes.frame, frame);
+        assert!(nes.save_state() == *state, "State differs after rewinding {} frames", i);
+    }
+
+    // running forwards again continues recording history from the rewound point
+    nes.run_frame();
+    rewind.capture(&nes);
+    assert!(rewind.step_back(&mut nes));
+    assert!(nes.save_state() == states[states.len() - 20].1);
+}
+
+#[test]
+fn test_rewind_stays_within_budget() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+
+    let mut rewind = Rewind::new(1, 64 * 1024);
+    for _ in 0 .. 200 {
+        nes.run_frame();
+        rewind.capture(&nes);
+    }
+
+    let available = rewind.frames_available();
+    assert!(available > 0 && available < 200);
+
+    for _ in 0 .. available - 1 {
+        assert!(rewind.step_back(&mut nes));
+    }
+}
+
+#[test]
+fn test_truncated_state_leaves_machine_unchanged() {
+    let mut nes = Nes::new(Box::new(Rom::from_file(TEST_ROM_PATH).unwrap())).unwrap();
+    nes.power_cycle();
+
+    for _ in 0 .. 10 {
+        nes.run_frame();
+    }
+    let truncated = nes.save_state();
+
+    for _ in 0 .. 10 {
+        nes.run_frame();
+    }
+    let before = nes.save_state();
+
+    // cut off partway through, after the cpu registers have been read
+    let result = nes.load_state(&truncated[.. truncated.len() / 2]);
+    assert_eq!(result, Err(StateError::UnexpectedEnd));
+    assert!(nes.save_state() == before, "A failed load changed the machine");
+    assert_eq!(nes.frame, 20);
+}
@@ -0,0 +1,38 @@
+extern crate enniesse_core;
+
+use std::fs;
+
+use enniesse_core::hash;
+use enniesse_core::mapper::Mirroring;
+use enniesse_core::rom::{Rom, Region};
+use enniesse_core::romdb;
+
+const TEST_ROM_PATH: &'static str = "tests/nestest.nes";
+
+#[test]
+fn test_hashes() {
+    assert_eq!(hash::crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(hash::crc32_update(hash::crc32(b"1234"), b"56789"), 0xcbf4_3926);
+
+    let sha1 = hash::sha1(b"abc");
+    assert_eq!(&sha1[.. 4], &[0xa9, 0x99, 0x3e, 0x36]);
+    assert_eq!(&sha1[16 ..], &[0x9c, 0xd0, 0xd8, 0x9d]);
+}
+
+#[test]
+fn test_header_fixed_from_database() {
+    // a header claiming mmc5, vertical mirroring and pal is put right from the prg and chr rom
+    let mut data = fs::read(TEST_ROM_PATH).unwrap();
+    data[6] = 0x51;
+    data[9] = 1;
+
+    let rom = Rom::from(data.into_boxed_slice());
+    assert_eq!(rom.mapper, 0);
+    assert_eq!(rom.flags6, 0);
+    assert_eq!(rom.region, Region::Ntsc);
+    assert_eq!(rom.title, Some("nestest".to_string()));
+    assert_eq!(Mirroring::from_header(&rom), Mirroring::Horizontal);
+
+    assert!(romdb::games().iter().all(|game| game.crc32.is_some() && game.sha1.is_some()));
+    assert!(romdb::lookup(&[0; 0x4000], &[]).is_none());
+}
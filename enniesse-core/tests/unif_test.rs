@@ -0,0 +1,53 @@
+extern crate enniesse_core;
+
+use enniesse_core::rom::Rom;
+use enniesse_core::unif;
+use enniesse_core::unif::UnifError;
+use enniesse_core::mapper;
+use enniesse_core::mapper::Mirroring;
+
+fn unif_image(chunks: &[(&[u8], Vec<u8>)]) -> Vec<u8> {
+    let mut image = b"UNIF\x07".to_vec();
+    image.resize(32, 0);
+
+    for &(id, ref data) in chunks {
+        let length = data.len() as u32;
+        image.extend_from_slice(id);
+        image.extend_from_slice(&[length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8]);
+        image.extend_from_slice(data);
+    }
+
+    image
+}
+
+#[test]
+fn test_unif() {
+    // the chunks don't have to be in order
+    let image = unif_image(&[
+        (b"NAME", b"Test Game\0".to_vec()),
+        (b"PRG1", vec![2; 0x4000]),
+        (b"MAPR", b"NES-NROM-256\0".to_vec()),
+        (b"PRG0", vec![1; 0x4000]),
+        (b"CHR0", vec![3; 0x2000]),
+        (b"MIRR", vec![1]),
+    ]);
+
+    let rom = Rom::from(image.into_boxed_slice());
+    assert_eq!(rom.mapper, 0);
+    assert_eq!(rom.prg_rom_size, 2);
+    assert_eq!(rom.chr_rom_size, 1);
+    assert_eq!(rom.title, Some("Test Game".to_string()));
+    assert_eq!(Mirroring::from_header(&rom), Mirroring::Vertical);
+
+    let mut nrom = mapper::load_mapper(Box::new(rom)).unwrap();
+    assert_eq!(nrom.load_byte_prg(0x8000), 1);
+    assert_eq!(nrom.load_byte_prg(0xc000), 2);
+    assert_eq!(nrom.load_byte_chr(0x0000), 3);
+
+    assert_eq!(unif::board_mapper("HVC-ETROM"), Some((5, 0)));
+    assert_eq!(unif::board_mapper("UNL-KONAMI-VRC-2"), Some((23, 3)));
+
+    let unknown = unif_image(&[(b"MAPR", b"UNL-NOTABOARD\0".to_vec()), (b"PRG0", vec![0; 0x4000])]);
+    assert_eq!(unif::parse(&unknown).err(), Some(UnifError::UnknownBoard("UNL-NOTABOARD".to_string())));
+    assert_eq!(unif::parse(&unknown[.. unknown.len() - 1]).err(), Some(UnifError::UnexpectedEnd));
+}